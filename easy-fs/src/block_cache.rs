@@ -1,11 +1,13 @@
 use super::{
     BLOCK_SZ,
     BlockDevice,
+    Mutex,
+    relax,
 };
 use alloc::collections::VecDeque;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use lazy_static::*;
-use spin::Mutex;
 
 /// Cached block inside memory
 pub struct BlockCache {
@@ -17,21 +19,29 @@ pub struct BlockCache {
     block_device: Arc<dyn BlockDevice>,
     /// whether the block is dirty
     modified: bool,
+    /// whether the block has been read from disk
+    loaded: bool,
 }
 
 impl BlockCache {
-    /// Load a new BlockCache from disk.
+    /// A new BlockCache, which is empty until it is loaded.
     pub fn new(
         block_id: usize,
         block_device: Arc<dyn BlockDevice>
     ) -> Self {
-        let mut cache = [0u8; BLOCK_SZ];
-        block_device.read_block(block_id, &mut cache);
         Self {
-            cache,
+            cache: [0u8; BLOCK_SZ],
             block_id,
             block_device,
             modified: false,
+            loaded: false,
+        }
+    }
+    /// Read the block from disk, unless that has been done already.
+    fn load(&mut self) {
+        if !self.loaded {
+            self.block_device.read_block(self.block_id, &mut self.cache);
+            self.loaded = true;
         }
     }
     /// Get the address of an offset inside the cached block data
@@ -88,35 +98,38 @@ impl BlockCacheManager {
         Self { queue: VecDeque::new() }
     }
 
+    /// Find the block cache of `block_id`, or make room for a new one
+    /// which is not loaded yet. Without room, the block which has to be
+    /// substituted is returned as `Err` if it is dirty, for the caller to
+    /// write it back and try again.
     pub fn get_block_cache(
         &mut self,
         block_id: usize,
         block_device: Arc<dyn BlockDevice>,
-    ) -> Arc<Mutex<BlockCache>> {
+    ) -> Result<Arc<Mutex<BlockCache>>, Option<Arc<Mutex<BlockCache>>>> {
         if let Some(pair) = self.queue
             .iter()
             .find(|pair| pair.0 == block_id) {
-                Arc::clone(&pair.1)
-        } else {
-            // substitute
-            if self.queue.len() == BLOCK_CACHE_SIZE {
-                // from front to tail
-                if let Some((idx, _)) = self.queue
-                    .iter()
-                    .enumerate()
-                    .find(|(_, pair)| Arc::strong_count(&pair.1) == 1) {
-                    self.queue.drain(idx..=idx);
-                } else {
-                    panic!("Run out of BlockCache!");
-                }
+                return Ok(Arc::clone(&pair.1));
+        }
+        // substitute
+        if self.queue.len() == BLOCK_CACHE_SIZE {
+            // from front to tail, nobody else holds a block which is unused
+            let idx = self.queue
+                .iter()
+                .position(|pair| Arc::strong_count(&pair.1) == 1)
+                .ok_or(None)?;
+            if self.queue[idx].1.lock().modified {
+                return Err(Some(Arc::clone(&self.queue[idx].1)));
             }
-            // load block into mem and push back
-            let block_cache = Arc::new(Mutex::new(
-                BlockCache::new(block_id, Arc::clone(&block_device))
-            ));
-            self.queue.push_back((block_id, Arc::clone(&block_cache)));
-            block_cache
+            self.queue.drain(idx..=idx);
         }
+        // push back, the block is read without the manager locked
+        let block_cache = Arc::new(Mutex::new(
+            BlockCache::new(block_id, Arc::clone(&block_device))
+        ));
+        self.queue.push_back((block_id, Arc::clone(&block_cache)));
+        Ok(block_cache)
     }
 }
 
@@ -127,18 +140,40 @@ lazy_static! {
     );
 }
 
-/// Get the block cache corresponding to the given block id and block device
+/// Get the block cache corresponding to the given block id and block device.
+/// The disk is only accessed with the lock of the block held, so that
+/// other blocks can be used meanwhile.
 pub fn get_block_cache(
     block_id: usize,
     block_device: Arc<dyn BlockDevice>
 ) -> Arc<Mutex<BlockCache>> {
-    BLOCK_CACHE_MANAGER.lock().get_block_cache(block_id, block_device)
+    loop {
+        let found = BLOCK_CACHE_MANAGER
+            .lock()
+            .get_block_cache(block_id, Arc::clone(&block_device));
+        match found {
+            Ok(block_cache) => {
+                block_cache.lock().load();
+                return block_cache;
+            }
+            // the block stays cached until it is written back, so nobody
+            // reads it from disk before that
+            Err(Some(dirty)) => dirty.lock().sync(),
+            // all blocks are in use, wait for one to be released
+            Err(None) => relax(),
+        }
+    }
 }
 
 /// Sync all block cache to block device
 pub fn block_cache_sync_all() {
-    let manager = BLOCK_CACHE_MANAGER.lock();
-    for (_, cache) in manager.queue.iter() {
+    let caches: Vec<_> = BLOCK_CACHE_MANAGER
+        .lock()
+        .queue
+        .iter()
+        .map(|(_, cache)| Arc::clone(cache))
+        .collect();
+    for cache in caches {
         cache.lock().sync();
     }
 }
@@ -1,7 +1,7 @@
 use alloc::sync::Arc;
-use spin::Mutex;
 use super::{
     BlockDevice,
+    RwLock,
     Bitmap,
     SuperBlock,
     DiskInode,
@@ -30,7 +30,7 @@ impl EasyFileSystem {
         block_device: Arc<dyn BlockDevice>,
         total_blocks: u32,
         inode_bitmap_blocks: u32,
-    ) -> Arc<Mutex<Self>> {
+    ) -> Arc<RwLock<Self>> {
         // calculate block size of areas & create bitmaps
         let inode_bitmap = Bitmap::new(1, inode_bitmap_blocks as usize);
         let inode_num = inode_bitmap.maximum();
@@ -87,10 +87,10 @@ impl EasyFileSystem {
             disk_inode.initialize(DiskInodeType::Directory);
         });
         block_cache_sync_all();
-        Arc::new(Mutex::new(efs))
+        Arc::new(RwLock::new(efs))
     }
     /// Open a block device as a filesystem
-    pub fn open(block_device: Arc<dyn BlockDevice>) -> Arc<Mutex<Self>> {
+    pub fn open(block_device: Arc<dyn BlockDevice>) -> Arc<RwLock<Self>> {
         // read SuperBlock
         get_block_cache(0, Arc::clone(&block_device))
             .lock()
@@ -111,14 +111,14 @@ impl EasyFileSystem {
                     inode_area_start_block: 1 + super_block.inode_bitmap_blocks,
                     data_area_start_block: 1 + inode_total_blocks + super_block.data_bitmap_blocks,
                 };
-                Arc::new(Mutex::new(efs))
+                Arc::new(RwLock::new(efs))
             })
     }
     /// Get the root inode of the filesystem
-    pub fn root_inode(efs: &Arc<Mutex<Self>>) -> Inode {
-        let block_device = Arc::clone(&efs.lock().block_device);
+    pub fn root_inode(efs: &Arc<RwLock<Self>>) -> Inode {
+        let block_device = Arc::clone(&efs.read().block_device);
         // acquire efs lock temporarily
-        let (block_id, block_offset) = efs.lock().get_disk_inode_pos(0);
+        let (block_id, block_offset) = efs.read().get_disk_inode_pos(0);
         // release efs lock
         Inode::new(
             block_id,
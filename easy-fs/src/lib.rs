@@ -8,12 +8,15 @@ mod efs;
 mod bitmap;
 mod vfs;
 mod block_cache;
+mod sync;
 
 /// Use a block size of 512 bytes
 pub const BLOCK_SZ: usize = 512;
 pub use block_dev::BlockDevice;
 pub use efs::EasyFileSystem;
 pub use vfs::Inode;
+pub use sync::set_lock_relax;
 use layout::*;
 use bitmap::Bitmap;
 use block_cache::{get_block_cache, block_cache_sync_all};
+use sync::{Mutex, RwLock, RwLockWriteGuard, relax};
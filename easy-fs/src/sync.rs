@@ -0,0 +1,73 @@
+//! Locks of the filesystem and its block cache.
+//!
+//! A block device may give up the processor while it waits for the disk,
+//! with locks of the filesystem held. Spinning for such a lock could keep
+//! its holder from ever running again, so a waiter calls the hook set by
+//! [`set_lock_relax`] instead, which may run something else meanwhile.
+
+use spin::Once;
+pub use spin::{MutexGuard, RwLockReadGuard, RwLockWriteGuard};
+
+static RELAX: Once<fn()> = Once::new();
+
+/// Have waiters for a lock which is held call `relax`, instead of spinning.
+pub fn set_lock_relax(relax: fn()) {
+    RELAX.call_once(|| relax);
+}
+
+/// Wait a moment for somebody else
+pub fn relax() {
+    match RELAX.get() {
+        Some(relax) => relax(),
+        None => core::hint::spin_loop(),
+    }
+}
+
+/// A spin lock which waits through [`relax`]
+pub struct Mutex<T> {
+    inner: spin::Mutex<T>,
+}
+
+impl<T> Mutex<T> {
+    pub const fn new(value: T) -> Self {
+        Self { inner: spin::Mutex::new(value) }
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        loop {
+            if let Some(guard) = self.inner.try_lock() {
+                return guard;
+            }
+            relax();
+        }
+    }
+}
+
+/// A readers-writer spin lock which waits through [`relax`]
+pub struct RwLock<T> {
+    inner: spin::RwLock<T>,
+}
+
+impl<T> RwLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self { inner: spin::RwLock::new(value) }
+    }
+
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        loop {
+            if let Some(guard) = self.inner.try_read() {
+                return guard;
+            }
+            relax();
+        }
+    }
+
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        loop {
+            if let Some(guard) = self.inner.try_write() {
+                return guard;
+            }
+            relax();
+        }
+    }
+}
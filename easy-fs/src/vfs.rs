@@ -5,19 +5,20 @@ use super::{
     DirEntry,
     EasyFileSystem,
     DIRENT_SZ,
+    RwLock,
+    RwLockWriteGuard,
     get_block_cache,
     block_cache_sync_all,
 };
 use alloc::sync::Arc;
 use alloc::string::String;
 use alloc::vec::Vec;
-use spin::{Mutex, MutexGuard};
 
 /// Virtual filesystem layer over easy-fs
 pub struct Inode {
     block_id: usize,
     block_offset: usize,
-    fs: Arc<Mutex<EasyFileSystem>>,
+    fs: Arc<RwLock<EasyFileSystem>>,
     block_device: Arc<dyn BlockDevice>,
 }
 
@@ -26,7 +27,7 @@ impl Inode {
     pub fn new(
         block_id: u32,
         block_offset: usize,
-        fs: Arc<Mutex<EasyFileSystem>>,
+        fs: Arc<RwLock<EasyFileSystem>>,
         block_device: Arc<dyn BlockDevice>,
     ) -> Self {
         Self {
@@ -43,6 +44,11 @@ impl Inode {
             Arc::clone(&self.block_device)
         ).lock().read(self.block_offset, f)
     }
+    /// A copy of the disk inode, so that its block is not locked while the
+    /// data is read. The caller holds the fs lock, which keeps writers out.
+    fn disk_inode(&self) -> DiskInode {
+        self.read_disk_inode(|disk_inode| disk_inode.clone())
+    }
     /// Call a function over a disk inode to modify it
     fn modify_disk_inode<V>(&self, f: impl FnOnce(&mut DiskInode) -> V) -> V {
         get_block_cache(
@@ -77,26 +83,22 @@ impl Inode {
     }
     /// Find inode under current inode by name
     pub fn find(&self, name: &str) -> Option<Arc<Inode>> {
-        let fs = self.fs.lock();
-        self.read_disk_inode(|disk_inode| {
-            self.find_inode_id(name, disk_inode)
-            .map(|inode_id| {
-                let (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
-                Arc::new(Self::new(
-                    block_id,
-                    block_offset,
-                    self.fs.clone(),
-                    self.block_device.clone(),
-                ))
-            })
-        })
+        let fs = self.fs.read();
+        let inode_id = self.find_inode_id(name, &self.disk_inode())?;
+        let (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
+        Some(Arc::new(Self::new(
+            block_id,
+            block_offset,
+            self.fs.clone(),
+            self.block_device.clone(),
+        )))
     }
     /// Increase the size of a disk inode
     fn increase_size(
         &self,
         new_size: u32,
         disk_inode: &mut DiskInode,
-        fs: &mut MutexGuard<EasyFileSystem>,
+        fs: &mut RwLockWriteGuard<EasyFileSystem>,
     ) {
         if new_size < disk_inode.size {
             return;
@@ -110,7 +112,7 @@ impl Inode {
     }
     /// Create inode under current inode by name
     pub fn create(&self, name: &str) -> Option<Arc<Inode>> {
-        let mut fs = self.fs.lock();
+        let mut fs = self.fs.write();
         if self.modify_disk_inode(|root_inode| {
             // assert it is a directory
             assert!(root_inode.is_dir());
@@ -159,35 +161,32 @@ impl Inode {
     }
     /// List inodes under current inode
     pub fn ls(&self) -> Vec<String> {
-        let _fs = self.fs.lock();
-        self.read_disk_inode(|disk_inode| {
-            let file_count = (disk_inode.size as usize) / DIRENT_SZ;
-            let mut v: Vec<String> = Vec::new();
-            for i in 0..file_count {
-                let mut dirent = DirEntry::empty();
-                assert_eq!(
-                    disk_inode.read_at(
-                        i * DIRENT_SZ,
-                        dirent.as_bytes_mut(),
-                        &self.block_device,
-                    ),
-                    DIRENT_SZ,
-                );
-                v.push(String::from(dirent.name()));
-            }
-            v
-        })
+        let _fs = self.fs.read();
+        let disk_inode = self.disk_inode();
+        let file_count = (disk_inode.size as usize) / DIRENT_SZ;
+        let mut v: Vec<String> = Vec::new();
+        for i in 0..file_count {
+            let mut dirent = DirEntry::empty();
+            assert_eq!(
+                disk_inode.read_at(
+                    i * DIRENT_SZ,
+                    dirent.as_bytes_mut(),
+                    &self.block_device,
+                ),
+                DIRENT_SZ,
+            );
+            v.push(String::from(dirent.name()));
+        }
+        v
     }
-    /// Read data from current inode
+    /// Read data from current inode, readers may wait for the disk at once
     pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
-        let _fs = self.fs.lock();
-        self.read_disk_inode(|disk_inode| {
-            disk_inode.read_at(offset, buf, &self.block_device)
-        })
+        let _fs = self.fs.read();
+        self.disk_inode().read_at(offset, buf, &self.block_device)
     }
     /// Write data to current inode
     pub fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
-        let mut fs = self.fs.lock();
+        let mut fs = self.fs.write();
         let size = self.modify_disk_inode(|disk_inode| {
             self.increase_size((offset + buf.len()) as u32, disk_inode, &mut fs);
             disk_inode.write_at(offset, buf, &self.block_device)
@@ -197,7 +196,7 @@ impl Inode {
     }
     /// Clear the data in current inode
     pub fn clear(&self) {
-        let mut fs = self.fs.lock();
+        let mut fs = self.fs.write();
         self.modify_disk_inode(|disk_inode| {
             let size = disk_inode.size;
             let data_blocks_dealloc = disk_inode.clear_size(&self.block_device);
@@ -0,0 +1,89 @@
+//! The kernel command line from `/chosen/bootargs`.
+//!
+//! Options are separated by spaces, unknown ones are logged and ignored:
+//!
+//! - `init=<app>[,<app>...]`: the apps started at boot, `ch5_usertest` by default
+//! - `loglevel=<off|error|warn|info|debug|trace>`, or a number from 0 to 5
+//! - `sched=<stride|rr>`: how a hart picks the next task from its queue
+//! - `wx=<enforce|warn|off>`: the [`WxPolicy`] for user mappings
+//! - `maxcpus=<n>`: the number of harts to schedule on, the boot hart included
+
+use alloc::{string::String, vec, vec::Vec};
+use spin::Once;
+
+use crate::{
+    config::MAX_HARTS,
+    logging,
+    mm::{set_wx_policy, WxPolicy},
+    task::{set_sched_policy, SchedPolicy},
+};
+
+/// the options which are read after boot
+#[derive(Debug)]
+pub struct BootArgs {
+    /// apps to start as tasks without a parent
+    pub init: Vec<String>,
+    /// how many harts to bring online
+    pub maxcpus: usize,
+}
+
+static BOOT_ARGS: Once<BootArgs> = Once::new();
+
+/// Parse `cmdline`, options which only change a setting take effect here.
+pub fn init(cmdline: &str) {
+    BOOT_ARGS.call_once(|| {
+        let mut args = BootArgs {
+            init: vec![String::from("ch5_usertest")],
+            maxcpus: MAX_HARTS,
+        };
+        for option in cmdline.split_whitespace() {
+            let (key, value) = option.split_once('=').unwrap_or((option, ""));
+            if !apply(&mut args, key, value) {
+                log::warn!("ignore kernel option {:?}", option);
+            }
+        }
+        args
+    });
+    if !cmdline.is_empty() {
+        log::info!("kernel command line: {}", cmdline);
+    }
+}
+
+/// Apply one option, false if it is unknown or its value is invalid.
+fn apply(args: &mut BootArgs, key: &str, value: &str) -> bool {
+    match key {
+        "init" => {
+            let init: Vec<String> = value
+                .split(',')
+                .filter(|name| !name.is_empty())
+                .map(String::from)
+                .collect();
+            if init.is_empty() {
+                return false;
+            }
+            args.init = init;
+        }
+        "loglevel" => return logging::set_level(value),
+        "sched" => match value {
+            "stride" => set_sched_policy(SchedPolicy::Stride),
+            "rr" => set_sched_policy(SchedPolicy::RoundRobin),
+            _ => return false,
+        },
+        "wx" => match value {
+            "enforce" => set_wx_policy(WxPolicy::Enforce),
+            "warn" => set_wx_policy(WxPolicy::Warn),
+            "off" => set_wx_policy(WxPolicy::Off),
+            _ => return false,
+        },
+        "maxcpus" => match value.parse() {
+            Ok(n) if n > 0 => args.maxcpus = n,
+            _ => return false,
+        },
+        _ => return false,
+    }
+    true
+}
+
+pub fn boot_args() -> &'static BootArgs {
+    BOOT_ARGS.get().expect("command line not parsed yet")
+}
@@ -1,21 +1,43 @@
 // base
 pub const PAGE_SIZE: usize = 0x1000;
 pub const PAGE_SIZE_BITS: usize = 0xc;
+/// timebase frequency if the device tree does not tell
 pub const CLOCK_FREQ: usize = 12500000;
+/// harts beyond this number are left parked, keep in sync with entry.asm
+pub const MAX_HARTS: usize = 4;
 
 // kernel space config
+/// boot stack of each hart, which is its idle stack later on, keep in sync
+/// with entry.asm
+pub const BOOT_STACK_SIZE: usize = PAGE_SIZE * 1024;
 pub const KERNEL_STACK_PAGE_NUM: usize = 15;
 pub const KERNEL_STACK_SIZE: usize = PAGE_SIZE * KERNEL_STACK_PAGE_NUM;
 pub const KERNEL_HEAP_SIZE: usize = PAGE_SIZE * 4096;
+pub const KERNEL_HEAP_GROW_PAGES: usize = 256;
+/// end of the RAM if the device tree does not tell
 pub const MEMORY_END: usize = 0x88000000;
+/// size of the swap disk, keep in sync with SWAP_SIZE in the Makefile
+pub const SWAP_SIZE: usize = 256 * 1024 * 1024;
 
 // syscall/user config
 pub const MAX_SYSCALL_NUM: usize = 500;
 #[allow(dead_code)]
 pub const BIG_STRIDE: usize = 500000;
+/// timer ticks between two load balancing rounds of a hart
+pub const LOAD_BALANCE_TICKS: usize = 10;
+/// affinity of a new task, all harts
+pub const ALL_HARTS: usize = (1 << MAX_HARTS) - 1;
+/// number of records kept by the syscall trace buffer
+pub const TRACE_BUFFER_LEN: usize = 512;
 
 // user space config
 pub const USER_STACK_PAGE_NUM: usize = 20;
 pub const USER_STACK_SIZE: usize = 4096 * USER_STACK_PAGE_NUM;
+pub const USER_STACK_RLIMIT: usize = 4096 * 2048;
 pub const TRAMPOLINE: usize = usize::MAX - PAGE_SIZE + 1;
 pub const TRAP_CONTEXT: usize = TRAMPOLINE - PAGE_SIZE;
+pub const USER_PIE_BASE: usize = 0x10_0000_0000;
+pub const USER_MMAP_BASE: usize = 0x20_0000_0000;
+pub const USER_STACK_TOP: usize = 0x40_0000_0000;
+/// load base, mmap base and stack top are shifted by up to this many pages
+pub const ASLR_PAGES: usize = 0x10000;
@@ -2,22 +2,47 @@
     本模块实现了 print 和 println 宏
 */
 
-use crate::sbi::console_putchar;
+use crate::{
+    drivers::uart,
+    sbi::{console_getchar, console_putchar},
+    sync::SpinLock,
+};
 use core::fmt::{self, Write};
 
 struct Stdout;
 
 impl Write for Stdout {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        for c in s.chars() {
-            console_putchar(c as usize);
+        // the SBI console until the UART driver is up
+        if uart::is_ready() {
+            uart::write(s.as_bytes());
+        } else {
+            for c in s.chars() {
+                console_putchar(c as usize);
+            }
         }
         Ok(())
     }
 }
 
+/// keeps the lines of different harts apart
+static STDOUT: SpinLock<Stdout> = SpinLock::new(Stdout);
+
 pub fn print(args: fmt::Arguments) {
-    Stdout.write_fmt(args).unwrap();
+    STDOUT.lock().write_fmt(args).unwrap();
+}
+
+/// a byte from the console, `None` if there is none yet
+pub fn getchar() -> Option<u8> {
+    if uart::is_ready() {
+        uart::getchar()
+    } else {
+        // the legacy call returns -1 without input, RustSBI 0
+        match console_getchar() {
+            0 | usize::MAX => None,
+            c => Some(c as u8),
+        }
+    }
 }
 
 #[macro_export]
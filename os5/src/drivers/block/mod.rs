@@ -0,0 +1,65 @@
+mod virtio_blk;
+
+use crate::fdt::machine;
+use alloc::{sync::Arc, vec::Vec};
+use spin::Once;
+use virtio_blk::VirtIOBlock;
+
+pub use easy_fs::BlockDevice;
+
+/// size of a block in bytes
+pub const BLOCK_SIZE: usize = easy_fs::BLOCK_SZ;
+
+/// the virtio-blk devices by address, which is the order of
+/// `virtio-mmio-bus.N` on QEMU virt. The first one holds the file system
+/// and the second one, if any, the swap area.
+static BLOCK_DEVICES: Once<Vec<Arc<VirtIOBlock>>> = Once::new();
+
+/// Take the virtio-blk devices of the device tree, and have the PLIC
+/// deliver their interrupts.
+pub fn init() {
+    let devices = BLOCK_DEVICES.call_once(|| {
+        let mut slots = machine().virtio.clone();
+        slots.sort_by_key(|mmio| mmio.base);
+        slots
+            .iter()
+            .filter_map(|mmio| {
+                let device = VirtIOBlock::probe(mmio.base, mmio.irq)?;
+                log::info!("virtio-blk at {:#x}, irq {:?}", mmio.base, device.irq());
+                Some(Arc::new(device))
+            })
+            .collect()
+    });
+    if devices.is_empty() {
+        log::warn!("no virtio-blk device");
+    }
+}
+
+fn nth_device(n: usize) -> Option<Arc<dyn BlockDevice>> {
+    let device = BLOCK_DEVICES.get()?.get(n)?;
+    Some(Arc::clone(device) as Arc<dyn BlockDevice>)
+}
+
+/// the disk, `None` without one or before [`init`]
+pub fn block_device() -> Option<Arc<dyn BlockDevice>> {
+    nth_device(0)
+}
+
+/// the swap disk, `None` without a second disk or before [`init`]
+pub fn swap_device() -> Option<Arc<dyn BlockDevice>> {
+    nth_device(1)
+}
+
+/// Handle `irq` if it belongs to a disk, returns whether it did.
+pub fn handle_irq(irq: u32) -> bool {
+    let device = BLOCK_DEVICES
+        .get()
+        .and_then(|devices| devices.iter().find(|device| device.irq() == Some(irq)));
+    match device {
+        Some(device) => {
+            device.handle_irq();
+            true
+        }
+        None => false,
+    }
+}
@@ -0,0 +1,249 @@
+//! The virtio-blk device of QEMU virt, behind the `virtio-drivers` crate.
+//!
+//! A task issuing a request parks until the device has used it, the hart
+//! runs other tasks meanwhile. The interrupt of the device, taken through
+//! the PLIC by any hart, unparks the task which owns each used token. As
+//! easy-fs does not hold a lock across the disk access of a reader, several
+//! tasks may have requests in flight at once. If the queue is full, a task
+//! parks until some request is used.
+//!
+//! Callers which cannot park, before the first task runs, without an
+//! interrupt or while holding a spinlock as swapping does, wait on their
+//! hart instead: it sleeps in `wfi` until the interrupt, whose hart sends
+//! an IPI to the waiting harts, as the PLIC only wakes the one which claims
+//! it. Without an interrupt the used ring is polled.
+//!
+//! The crate finds the memory it shares with the device through the
+//! `virtio_*` functions exported here.
+
+use super::{super::plic, BlockDevice};
+use crate::{
+    config::PAGE_SIZE,
+    mm::{frame_alloc_contiguous, frame_dealloc, PhysAddr, PhysPageNum, VirtAddr, KERNEL_SPACE},
+    sbi::send_ipi,
+    smp::hart_id,
+    sync::SpinLock,
+    task::{park, parkable_task, unpark, Task},
+    trap::{disable_timer_interrupt, enable_timer_interrupt, wait_for_interrupt},
+};
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+    vec::Vec,
+};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use virtio_drivers::{BlkResp, DeviceType, Error, RespStatus, VirtIOBlk, VirtIOHeader};
+
+/// the device and who waits for it
+struct Queue {
+    blk: VirtIOBlk<'static>,
+    /// the parked task of each request in flight which has one
+    parked: BTreeMap<u16, Arc<Task>>,
+    /// tasks parked until the queue has room again
+    full: Vec<Arc<Task>>,
+    /// tokens of the requests the device has used but whose waiting harts
+    /// have not yet seen
+    completed: BTreeSet<u16>,
+}
+
+pub struct VirtIOBlock {
+    queue: SpinLock<Queue>,
+    irq: Option<u32>,
+    /// bit i is set while hart i waits for a request
+    waiters: AtomicUsize,
+}
+
+impl BlockDevice for VirtIOBlock {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+        let mut resp = BlkResp::default();
+        self.request(&mut resp, |blk, resp| unsafe {
+            // `buf` and `resp` outlive the request, it is waited for below
+            blk.read_block_nb(block_id, buf, resp)
+        });
+        assert!(
+            resp.status() == RespStatus::Ok,
+            "Error when reading VirtIOBlk"
+        );
+    }
+    fn write_block(&self, block_id: usize, buf: &[u8]) {
+        let mut resp = BlkResp::default();
+        self.request(&mut resp, |blk, resp| unsafe {
+            blk.write_block_nb(block_id, buf, resp)
+        });
+        assert!(
+            resp.status() == RespStatus::Ok,
+            "Error when writing VirtIOBlk"
+        );
+    }
+}
+
+impl VirtIOBlock {
+    /// Take the virtio slot at `base` if a block device sits there, and have
+    /// the PLIC deliver `irq`, its interrupt.
+    pub fn probe(base: usize, irq: Option<u32>) -> Option<Self> {
+        let header = unsafe { &mut *(base as *mut VirtIOHeader) };
+        if !header.verify() || header.device_type() != DeviceType::Block {
+            return None;
+        }
+        match VirtIOBlk::new(header) {
+            Ok(blk) => Some(Self {
+                queue: SpinLock::new(Queue {
+                    blk,
+                    parked: BTreeMap::new(),
+                    full: Vec::new(),
+                    completed: BTreeSet::new(),
+                }),
+                irq: irq.filter(|&irq| plic::enable(irq)),
+                waiters: AtomicUsize::new(0),
+            }),
+            Err(err) => {
+                log::warn!("virtio-blk at {:#x}: {:?}", base, err);
+                None
+            }
+        }
+    }
+
+    /// Queue a request with `submit` and wait until the device has used it.
+    fn request<F>(&self, resp: &mut BlkResp, submit: F)
+    where
+        F: FnMut(&mut VirtIOBlk<'static>, &mut BlkResp) -> Result<u16, Error>,
+    {
+        match parkable_task() {
+            Some(task) if self.irq.is_some() => self.request_parked(task, resp, submit),
+            _ => self.request_waiting(resp, submit),
+        }
+    }
+
+    /// [`request`](Self::request) for `task`, the current one, which parks
+    fn request_parked<F>(&self, task: Arc<Task>, resp: &mut BlkResp, mut submit: F)
+    where
+        F: FnMut(&mut VirtIOBlk<'static>, &mut BlkResp) -> Result<u16, Error>,
+    {
+        loop {
+            // registered under the lock, so the interrupt cannot come
+            // before the task is found
+            {
+                let mut queue = self.queue.lock();
+                match submit(&mut queue.blk, resp) {
+                    Ok(token) => {
+                        queue.parked.insert(token, Arc::clone(&task));
+                        drop(queue);
+                        // unparked for this token only
+                        park(task);
+                        return;
+                    }
+                    Err(Error::BufferTooSmall) => queue.full.push(Arc::clone(&task)),
+                    Err(err) => panic!("virtio-blk request failed: {:?}", err),
+                }
+            }
+            park(Arc::clone(&task));
+        }
+    }
+
+    /// [`request`](Self::request) on a hart which waits for the device
+    fn request_waiting<F>(&self, resp: &mut BlkResp, mut submit: F)
+    where
+        F: FnMut(&mut VirtIOBlk<'static>, &mut BlkResp) -> Result<u16, Error>,
+    {
+        // a pending timer interrupt would end every wfi at once, it is
+        // taken when the task returns to user mode
+        let timer = disable_timer_interrupt();
+        let hart = 1 << hart_id();
+        self.waiters.fetch_or(hart, Ordering::SeqCst);
+        let token = loop {
+            let result = submit(&mut self.queue.lock().blk, resp);
+            match result {
+                Ok(token) => break token,
+                // the queue is full, wait for a request to finish
+                Err(Error::BufferTooSmall) => self.wait(),
+                Err(err) => panic!("virtio-blk request failed: {:?}", err),
+            }
+        };
+        while !self.queue.lock().completed.remove(&token) {
+            self.wait();
+        }
+        self.waiters.fetch_and(!hart, Ordering::SeqCst);
+        if timer {
+            enable_timer_interrupt();
+        }
+    }
+
+    /// sleep until the next interrupt, or poll the device without one
+    fn wait(&self) {
+        if self.irq.is_some() {
+            wait_for_interrupt();
+        } else {
+            self.handle_irq();
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Note the requests the device has used, from its interrupt or polled,
+    /// unpark their tasks and wake the other harts waiting for requests.
+    pub fn handle_irq(&self) {
+        let mut unparked = Vec::new();
+        {
+            let mut queue = self.queue.lock();
+            queue.blk.ack_interrupt();
+            let mut used = false;
+            while let Ok(token) = queue.blk.pop_used() {
+                used = true;
+                match queue.parked.remove(&token) {
+                    Some(task) => unparked.push(task),
+                    None => {
+                        queue.completed.insert(token);
+                    }
+                }
+            }
+            if used {
+                // there is room in the queue again
+                unparked.append(&mut queue.full);
+            }
+        }
+        // without the lock, which the tasks take again once they run
+        for task in unparked {
+            unpark(task);
+        }
+        let others = self.waiters.load(Ordering::SeqCst) & !(1 << hart_id());
+        if others != 0 && self.irq.is_some() {
+            // a hart missing the IPI would sleep on, so this must not fail
+            send_ipi(others, 0).expect("cannot wake the harts waiting for virtio-blk");
+        }
+    }
+
+    pub fn irq(&self) -> Option<u32> {
+        self.irq
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn virtio_dma_alloc(pages: usize) -> PhysAddr {
+    let ppn = frame_alloc_contiguous(pages).expect("out of frames for virtio queues");
+    let pa: PhysAddr = ppn.into();
+    unsafe { core::ptr::write_bytes(pa.0 as *mut u8, 0, pages * PAGE_SIZE) };
+    pa
+}
+
+#[no_mangle]
+pub extern "C" fn virtio_dma_dealloc(pa: PhysAddr, pages: usize) -> i32 {
+    let ppn: PhysPageNum = pa.into();
+    for i in 0..pages {
+        frame_dealloc(PhysPageNum(ppn.0 + i));
+    }
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn virtio_phys_to_virt(paddr: PhysAddr) -> VirtAddr {
+    VirtAddr(paddr.0)
+}
+
+/// Buffers may be on a kernel stack, which is not mapped one to one.
+#[no_mangle]
+pub extern "C" fn virtio_virt_to_phys(vaddr: VirtAddr) -> PhysAddr {
+    let pte = KERNEL_SPACE
+        .lock()
+        .translate(vaddr.floor())
+        .expect("virtio buffer not mapped");
+    PhysAddr(PhysAddr::from(pte.ppn()).0 + vaddr.page_offset())
+}
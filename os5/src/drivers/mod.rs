@@ -0,0 +1,41 @@
+//! Device drivers
+//!
+//! The interfaces shared by the rest of the kernel, and the drivers of the
+//! QEMU virt devices. Device interrupts come in through the PLIC and are
+//! dispatched by their source number from the device tree.
+
+mod block;
+mod plic;
+mod rtc;
+mod test_finisher;
+pub mod uart;
+
+pub use block::{block_device, swap_device, BlockDevice, BLOCK_SIZE};
+pub use test_finisher::{poweroff, task_exit_status, PANIC_EXIT_CODE};
+
+use crate::fdt::machine;
+
+/// Set up the devices, on the boot hart.
+pub fn init() {
+    uart::init();
+    rtc::init();
+    block::init();
+}
+
+/// let this hart take device interrupts
+pub fn init_hart(hart: usize) {
+    plic::init_hart(hart);
+}
+
+/// Handle all pending device interrupts of `hart`.
+pub fn handle_external_interrupt(hart: usize) {
+    let uart_irq = machine().uart.and_then(|uart| uart.irq);
+    while let Some(irq) = plic::claim(hart) {
+        if Some(irq) == uart_irq {
+            uart::handle_irq();
+        } else if !block::handle_irq(irq) {
+            log::warn!("unexpected external interrupt {}", irq);
+        }
+        plic::complete(hart, irq);
+    }
+}
@@ -0,0 +1,94 @@
+//! The platform-level interrupt controller, which routes the interrupts of
+//! the devices to the harts.
+//!
+//! Every hart takes the interrupts of all enabled sources in its S-mode
+//! context, the claim makes sure only one of them handles each interrupt.
+//! The contexts are laid out as on QEMU virt, M-mode and S-mode of hart 0,
+//! then of hart 1 and so on.
+
+use crate::{config::MAX_HARTS, fdt::machine, smp::online_harts};
+use core::{
+    ptr::{read_volatile, write_volatile},
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+const PRIORITY_OFFSET: usize = 0;
+const ENABLE_OFFSET: usize = 0x2000;
+const ENABLE_STRIDE: usize = 0x80;
+const CONTEXT_OFFSET: usize = 0x20_0000;
+const CONTEXT_STRIDE: usize = 0x1000;
+const CLAIM_OFFSET: usize = 4;
+
+/// sources 1 to 63 are enough for QEMU virt
+const MAX_IRQS: u32 = 64;
+
+/// bit i of word i / 32 is set if source i is enabled, as in the enable
+/// registers
+static ENABLED_IRQS: [AtomicU32; 2] = [AtomicU32::new(0), AtomicU32::new(0)];
+
+fn base() -> Option<usize> {
+    machine().plic.map(|plic| plic.base)
+}
+
+/// the S-mode context of `hart`
+fn context(hart: usize) -> usize {
+    2 * hart + 1
+}
+
+fn reg(base: usize, offset: usize) -> *mut u32 {
+    (base + offset) as *mut u32
+}
+
+/// Enable source `irq` with priority 1 on all harts which are online or
+/// come online later. Returns false without a PLIC.
+pub fn enable(irq: u32) -> bool {
+    let base = match base() {
+        Some(base) if irq > 0 && irq < MAX_IRQS => base,
+        _ => return false,
+    };
+    let (word, bit) = ((irq / 32) as usize, irq % 32);
+    ENABLED_IRQS[word].fetch_or(1 << bit, Ordering::AcqRel);
+    unsafe {
+        write_volatile(reg(base, PRIORITY_OFFSET + irq as usize * 4), 1);
+    }
+    for hart in (0..MAX_HARTS).filter(|hart| online_harts() & (1 << hart) != 0) {
+        write_enables(base, hart);
+    }
+    true
+}
+
+fn write_enables(base: usize, hart: usize) {
+    let enable = ENABLE_OFFSET + context(hart) * ENABLE_STRIDE;
+    for (word, mask) in ENABLED_IRQS.iter().enumerate() {
+        unsafe {
+            write_volatile(reg(base, enable + word * 4), mask.load(Ordering::Acquire));
+        }
+    }
+}
+
+/// Let the S-mode context of `hart` take the enabled interrupts.
+pub fn init_hart(hart: usize) {
+    if let Some(base) = base() {
+        write_enables(base, hart);
+        let threshold = CONTEXT_OFFSET + context(hart) * CONTEXT_STRIDE;
+        unsafe { write_volatile(reg(base, threshold), 0) };
+    }
+}
+
+/// the highest priority pending interrupt of `hart`, to be completed
+pub fn claim(hart: usize) -> Option<u32> {
+    let base = base()?;
+    let claim = CONTEXT_OFFSET + context(hart) * CONTEXT_STRIDE + CLAIM_OFFSET;
+    match unsafe { read_volatile(reg(base, claim)) } {
+        0 => None,
+        irq => Some(irq),
+    }
+}
+
+/// tell the PLIC that `hart` has handled `irq`
+pub fn complete(hart: usize, irq: u32) {
+    if let Some(base) = base() {
+        let claim = CONTEXT_OFFSET + context(hart) * CONTEXT_STRIDE + CLAIM_OFFSET;
+        unsafe { write_volatile(reg(base, claim), irq) };
+    }
+}
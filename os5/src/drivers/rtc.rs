@@ -0,0 +1,61 @@
+//! The goldfish RTC of QEMU virt, which counts ns since the Unix epoch.
+//!
+//! It is only read once at boot to set the wall clock, the `time` CSR
+//! keeps it going from there.
+
+use crate::{fdt::machine, timer::set_realtime};
+use core::ptr::read_volatile;
+
+const TIME_LOW: usize = 0x00;
+const TIME_HIGH: usize = 0x04;
+
+const SECS_PER_DAY: usize = 86400;
+const NANO_PER_SEC: usize = 1_000_000_000;
+
+/// ns since the Unix epoch, reading the low word latches the high one
+fn read_time(base: usize) -> usize {
+    unsafe {
+        let low = read_volatile((base + TIME_LOW) as *const u32) as usize;
+        let high = read_volatile((base + TIME_HIGH) as *const u32) as usize;
+        high << 32 | low
+    }
+}
+
+/// Set the wall clock from the RTC of the device tree, if there is one.
+pub fn init() {
+    let base = match machine().rtc {
+        Some(rtc) => rtc.base,
+        None => {
+            log::warn!("no RTC, the wall clock counts from boot");
+            return;
+        }
+    };
+    let now_ns = read_time(base);
+    set_realtime(now_ns);
+    let (year, month, day) = civil_from_days(now_ns / NANO_PER_SEC / SECS_PER_DAY);
+    let secs = now_ns / NANO_PER_SEC % SECS_PER_DAY;
+    println!(
+        "[kernel] RTC: {:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    );
+}
+
+/// Year, month and day of the `days`th day after 1970-01-01, after Howard
+/// Hinnant's `civil_from_days`.
+fn civil_from_days(days: usize) -> (usize, usize, usize) {
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as usize;
+    (year, month, day)
+}
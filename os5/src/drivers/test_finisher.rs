@@ -0,0 +1,55 @@
+//! The `sifive_test` device of QEMU virt, writing to it ends QEMU with an
+//! exit status.
+
+use crate::{
+    fdt::try_machine,
+    sbi::{shutdown, ResetReason},
+};
+use core::ptr::write_volatile;
+
+/// where QEMU virt has it, for a panic before the device tree is read
+const TEST_BASE: usize = 0x100000;
+const FINISHER_PASS: u32 = 0x5555;
+const FINISHER_FAIL: u32 = 0x3333;
+
+/// QEMU exit status after a kernel panic, no task exit code maps to it
+pub const PANIC_EXIT_CODE: u32 = 101;
+/// the highest QEMU exit status for a failed task, see [`task_exit_status`]
+const MAX_TASK_EXIT_STATUS: u32 = 100;
+
+/// The QEMU exit status for a task exit code: 0 stays 0, codes from 1 to
+/// 100 are kept and all others become 100, so that a failure never reads
+/// as success or as a kernel panic.
+pub fn task_exit_status(exit_code: i32) -> u32 {
+    match exit_code {
+        0 => 0,
+        code if (1..=MAX_TASK_EXIT_STATUS as i32).contains(&code) => code as u32,
+        _ => MAX_TASK_EXIT_STATUS,
+    }
+}
+
+/// Power off with `exit_code` as exit status of QEMU. Only the low 16 bits
+/// get through, a non-zero code without any of them set exits with 1.
+/// Without the device it falls back to an SBI shutdown, which only tells
+/// success from failure.
+pub fn poweroff(exit_code: u32) -> ! {
+    super::uart::flush();
+    let base = match try_machine() {
+        Some(machine) => machine.test.map(|test| test.base),
+        None => Some(TEST_BASE),
+    };
+    let value = match exit_code {
+        0 => FINISHER_PASS,
+        code if code & 0xffff == 0 => 1 << 16 | FINISHER_FAIL,
+        code => (code & 0xffff) << 16 | FINISHER_FAIL,
+    };
+    if let Some(base) = base {
+        unsafe { write_volatile(base as *mut u32, value) };
+    }
+    let reason = if exit_code == 0 {
+        ResetReason::NoReason
+    } else {
+        ResetReason::SystemFailure
+    };
+    shutdown(reason, exit_code)
+}
@@ -0,0 +1,211 @@
+//! The ns16550a UART of QEMU virt.
+//!
+//! Received bytes are moved into a ring buffer by the RX interrupt, where
+//! [`getchar`] finds them. Bytes to send go into another ring buffer, from
+//! which as many as the transmitter takes are written right away and the
+//! rest when the transmitter interrupts once it is empty again. Until
+//! [`init`] has found the UART the console falls back to the SBI.
+
+use crate::{fdt::machine, sync::SpinLock};
+use core::ptr::{read_volatile, write_volatile};
+use spin::Once;
+
+use super::plic;
+
+// register offsets, with a register shift of 0
+const RBR_THR: usize = 0;
+const IER: usize = 1;
+const FCR: usize = 2;
+const LCR: usize = 3;
+const MCR: usize = 4;
+const LSR: usize = 5;
+
+const IER_RX_AVAILABLE: u8 = 1 << 0;
+const IER_TX_EMPTY: u8 = 1 << 1;
+/// enable the FIFOs and clear both
+const FCR_FIFO_RESET: u8 = 0b111;
+/// 8 data bits, no parity, 1 stop bit
+const LCR_8N1: u8 = 0b11;
+/// DTR, RTS and OUT2, which gates the interrupt line on a real 16550
+const MCR_DTR_RTS_OUT2: u8 = 0b1011;
+const LSR_DATA_READY: u8 = 1 << 0;
+const LSR_THR_EMPTY: u8 = 1 << 5;
+
+/// bytes the transmitter FIFO takes once it is empty
+const TX_FIFO_SIZE: usize = 16;
+const RX_BUFFER_SIZE: usize = 256;
+const TX_BUFFER_SIZE: usize = 4096;
+
+/// a byte queue which drops new bytes when full
+struct RingBuffer<const N: usize> {
+    buf: [u8; N],
+    head: usize,
+    len: usize,
+}
+
+impl<const N: usize> RingBuffer<N> {
+    const fn new() -> Self {
+        Self {
+            buf: [0; N],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, byte: u8) -> bool {
+        if self.len == N {
+            return false;
+        }
+        self.buf[(self.head + self.len) % N] = byte;
+        self.len += 1;
+        true
+    }
+
+    fn pop(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+        let byte = self.buf[self.head];
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        Some(byte)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+struct Ns16550a {
+    base: usize,
+    rx: RingBuffer<RX_BUFFER_SIZE>,
+    tx: RingBuffer<TX_BUFFER_SIZE>,
+}
+
+impl Ns16550a {
+    fn read(&self, reg: usize) -> u8 {
+        unsafe { read_volatile((self.base + reg) as *const u8) }
+    }
+
+    fn write(&self, reg: usize, value: u8) {
+        unsafe { write_volatile((self.base + reg) as *mut u8, value) }
+    }
+
+    fn init(&self) {
+        self.write(IER, 0);
+        self.write(LCR, LCR_8N1);
+        self.write(FCR, FCR_FIFO_RESET);
+        self.write(MCR, MCR_DTR_RTS_OUT2);
+        self.write(IER, IER_RX_AVAILABLE);
+    }
+
+    /// Move received bytes into the RX buffer, bytes which do not fit are
+    /// lost. No logging here, the console may hold the lock.
+    fn receive(&mut self) {
+        while self.read(LSR) & LSR_DATA_READY != 0 {
+            let byte = self.read(RBR_THR);
+            self.rx.push(byte);
+        }
+    }
+
+    /// Fill the transmitter from the TX buffer if it is empty, and have it
+    /// interrupt when it is empty again as long as bytes are left.
+    fn transmit(&mut self) {
+        if self.read(LSR) & LSR_THR_EMPTY != 0 {
+            for _ in 0..TX_FIFO_SIZE {
+                match self.tx.pop() {
+                    Some(byte) => self.write(RBR_THR, byte),
+                    None => break,
+                }
+            }
+        }
+        let ier = if self.tx.is_empty() {
+            IER_RX_AVAILABLE
+        } else {
+            IER_RX_AVAILABLE | IER_TX_EMPTY
+        };
+        self.write(IER, ier);
+    }
+
+    fn putchar(&mut self, byte: u8) {
+        // kernel code runs with interrupts off, so make room by polling
+        while !self.tx.push(byte) {
+            self.transmit();
+        }
+    }
+
+    /// send everything in the TX buffer before returning
+    fn flush(&mut self) {
+        while !self.tx.is_empty() {
+            self.transmit();
+        }
+    }
+}
+
+static UART: Once<SpinLock<Ns16550a>> = Once::new();
+
+/// Take over the console from the SBI if the device tree has a UART, and
+/// have the PLIC deliver its interrupts.
+pub fn init() {
+    let mmio = match machine().uart {
+        Some(mmio) => mmio,
+        None => {
+            log::info!("no UART, console stays on the SBI");
+            return;
+        }
+    };
+    let uart = UART.call_once(|| {
+        SpinLock::new(Ns16550a {
+            base: mmio.base,
+            rx: RingBuffer::new(),
+            tx: RingBuffer::new(),
+        })
+    });
+    uart.lock().init();
+    match mmio.irq {
+        Some(irq) if plic::enable(irq) => log::info!("uart at {:#x}, irq {}", mmio.base, irq),
+        _ => log::warn!(
+            "uart at {:#x} without interrupt, input is polled",
+            mmio.base
+        ),
+    }
+}
+
+/// Whether the console goes through the UART, false before [`init`].
+pub fn is_ready() -> bool {
+    UART.get().is_some()
+}
+
+/// Queue bytes to send, see the module docs. Must only be called after
+/// [`init`].
+pub fn write(bytes: &[u8]) {
+    let mut uart = UART.get().expect("uart not initialized").lock();
+    for &byte in bytes {
+        uart.putchar(byte);
+    }
+    uart.transmit();
+}
+
+/// a received byte, `None` if there is none yet
+pub fn getchar() -> Option<u8> {
+    let mut uart = UART.get()?.lock();
+    // bytes which arrived while interrupts were off have not been moved yet
+    uart.receive();
+    uart.rx.pop()
+}
+
+/// send all queued bytes, before powering off
+pub fn flush() {
+    if let Some(uart) = UART.get() {
+        uart.lock().flush();
+    }
+}
+
+/// handler of the UART interrupt
+pub fn handle_irq() {
+    if let Some(uart) = UART.get() {
+        let mut uart = uart.lock();
+        uart.receive();
+        uart.transmit();
+    }
+}
@@ -1,12 +1,26 @@
     .section .text.entry
     .globl _start
+# a0 = hartid, every hart enters here, the boot hart from the SBI and the
+# others once it starts them
 _start:
-    la sp, boot_stack_top
+    mv tp, a0
+    # harts beyond MAX_HARTS have no stack
+    li t0, 4
+    bgeu a0, t0, 1f
+    # sp = boot_stack + (hartid + 1) * BOOT_STACK_SIZE
+    addi t0, a0, 1
+    li t1, 0x400000
+    mul t0, t0, t1
+    la sp, boot_stack
+    add sp, sp, t0
     call rust_main
+1:
+    wfi
+    j 1b
 
     .section .bss.stack
     .globl boot_stack
 boot_stack:
-    .space 4096 * 4 * 1024  // 16 MB
+    .space 4096 * 1024 * 4  // 4 MB for each of MAX_HARTS harts
     .globl boot_stack_top
-boot_stack_top:
\ No newline at end of file
+boot_stack_top:
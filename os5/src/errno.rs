@@ -0,0 +1,49 @@
+//! Error numbers returned by syscalls.
+//!
+//! A failing syscall returns the negated error number in `a0`, the values
+//! follow Linux so that user programs can tell the failures apart.
+
+use crate::mm::ElfLoadError;
+
+#[allow(clippy::upper_case_acronyms)]
+#[repr(isize)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Errno {
+    /// no such file or app
+    ENOENT = 2,
+    /// no such task
+    ESRCH = 3,
+    /// the image cannot be executed
+    ENOEXEC = 8,
+    /// bad file descriptor
+    EBADF = 9,
+    /// no child to wait for
+    ECHILD = 10,
+    /// try again later
+    EAGAIN = 11,
+    /// out of memory
+    ENOMEM = 12,
+    /// permission denied
+    EACCES = 13,
+    /// bad address
+    EFAULT = 14,
+    /// already exists
+    EEXIST = 17,
+    /// invalid argument
+    EINVAL = 22,
+    /// unknown syscall
+    ENOSYS = 38,
+}
+
+impl Errno {
+    /// the value written to `a0`
+    pub fn as_ret(self) -> isize {
+        -(self as isize)
+    }
+}
+
+impl From<ElfLoadError> for Errno {
+    fn from(_: ElfLoadError) -> Self {
+        Errno::ENOEXEC
+    }
+}
@@ -0,0 +1,316 @@
+//! A minimal reader of the flattened device tree the SBI passes in a1.
+//!
+//! The tree is walked once at boot and the few things the kernel needs
+//! are copied out: the RAM regions, the timebase frequency, the harts, the
+//! registers of the devices it has drivers for and the command line. The
+//! blob itself lies in RAM the frame allocator hands out later. Without a
+//! valid tree the QEMU virt defaults from `config` are used.
+
+use alloc::{string::String, vec, vec::Vec};
+use core::str::from_utf8;
+use spin::Once;
+
+use crate::config::{CLOCK_FREQ, MAX_HARTS, MEMORY_END};
+
+const FDT_MAGIC: u32 = 0xd00dfeed;
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+const FDT_END: u32 = 9;
+
+/// RAM start on QEMU virt, where the SBI lives
+const DRAM_BASE: usize = 0x80000000;
+
+/// the register window of a device and its first interrupt
+#[derive(Debug, Clone, Copy)]
+pub struct Mmio {
+    pub base: usize,
+    pub size: usize,
+    pub irq: Option<u32>,
+}
+
+impl Mmio {
+    const fn new(base: usize, size: usize, irq: Option<u32>) -> Self {
+        Self { base, size, irq }
+    }
+}
+
+/// what the kernel learned about the machine
+#[derive(Debug)]
+pub struct MachineInfo {
+    /// RAM regions as base and size
+    pub memory: Vec<(usize, usize)>,
+    /// frequency of the `time` CSR in Hz
+    pub timebase_freq: usize,
+    /// ids of the harts
+    pub harts: Vec<usize>,
+    /// `sifive,test0`, which powers QEMU off
+    pub test: Option<Mmio>,
+    /// `google,goldfish-rtc`
+    pub rtc: Option<Mmio>,
+    /// `ns16550a`
+    pub uart: Option<Mmio>,
+    /// `riscv,plic0`
+    pub plic: Option<Mmio>,
+    /// `virtio,mmio` slots, in the order of the tree
+    pub virtio: Vec<Mmio>,
+    /// `/chosen/bootargs`, the kernel command line
+    pub bootargs: String,
+}
+
+impl MachineInfo {
+    /// QEMU virt with the RAM up to `MEMORY_END`
+    fn qemu_virt() -> Self {
+        Self {
+            memory: vec![(DRAM_BASE, MEMORY_END - DRAM_BASE)],
+            timebase_freq: CLOCK_FREQ,
+            harts: (0..MAX_HARTS).collect(),
+            test: Some(Mmio::new(0x100000, 0x1000, None)),
+            rtc: Some(Mmio::new(0x101000, 0x1000, Some(11))),
+            uart: Some(Mmio::new(0x10000000, 0x100, Some(10))),
+            plic: Some(Mmio::new(0xc000000, 0x600000, None)),
+            virtio: (0..8)
+                .map(|i| Mmio::new(0x10001000 + i * 0x1000, 0x1000, Some(1 + i as u32)))
+                .collect(),
+            bootargs: String::new(),
+        }
+    }
+
+    /// end of the RAM region the kernel is loaded into
+    pub fn memory_end(&self) -> usize {
+        extern "C" {
+            fn ekernel();
+        }
+        let kernel = ekernel as usize;
+        self.memory
+            .iter()
+            .find(|&&(base, size)| base <= kernel && kernel < base + size)
+            .map_or(MEMORY_END, |&(base, size)| base + size)
+    }
+
+    /// all device register windows, to be mapped into the kernel space
+    pub fn mmio_regions(&self) -> Vec<Mmio> {
+        [self.test, self.rtc, self.uart, self.plic]
+            .iter()
+            .flatten()
+            .chain(self.virtio.iter())
+            .copied()
+            .collect()
+    }
+}
+
+static MACHINE: Once<MachineInfo> = Once::new();
+
+/// Read the device tree at physical address `dtb`, before the frame
+/// allocator may overwrite it.
+pub fn init(dtb: usize) {
+    MACHINE.call_once(|| match unsafe { parse(dtb) } {
+        Some(info) => info,
+        None => {
+            log::warn!("no valid device tree at {:#x}, assuming QEMU virt", dtb);
+            MachineInfo::qemu_virt()
+        }
+    });
+    log::info!("{:x?}", machine());
+}
+
+pub fn machine() -> &'static MachineInfo {
+    MACHINE.get().expect("device tree not read yet")
+}
+
+/// the machine info, `None` before [`init`]
+pub fn try_machine() -> Option<&'static MachineInfo> {
+    MACHINE.get()
+}
+
+fn be32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// a node with the properties the kernel looks at
+struct Node<'a> {
+    name: &'a str,
+    parent: Option<usize>,
+    props: Vec<(&'a str, &'a [u8])>,
+}
+
+impl<'a> Node<'a> {
+    fn prop(&self, name: &str) -> Option<&'a [u8]> {
+        self.props
+            .iter()
+            .find(|(prop, _)| *prop == name)
+            .map(|&(_, value)| value)
+    }
+
+    fn prop_u32(&self, name: &str) -> Option<u32> {
+        self.prop(name).and_then(|value| be32(value, 0))
+    }
+
+    /// a property holding a string, or a list of them
+    fn has_string(&self, name: &str, string: &str) -> bool {
+        self.prop(name).map_or(false, |value| {
+            value
+                .split(|&b| b == 0)
+                .any(|item| item == string.as_bytes())
+        })
+    }
+
+    fn enabled(&self) -> bool {
+        // "okay", or "ok" in older trees
+        self.prop("status")
+            .map_or(true, |status| status.starts_with(b"ok"))
+    }
+}
+
+/// Walk the structure block into a flat list of nodes, parents first.
+fn read_nodes<'a>(structs: &'a [u8], strings: &'a [u8]) -> Option<Vec<Node<'a>>> {
+    let mut nodes: Vec<Node> = Vec::new();
+    let mut stack: Vec<usize> = Vec::new();
+    let mut offset = 0;
+    loop {
+        let token = be32(structs, offset)?;
+        offset += 4;
+        match token {
+            FDT_BEGIN_NODE => {
+                let len = structs.get(offset..)?.iter().position(|&b| b == 0)?;
+                let name = from_utf8(&structs[offset..offset + len]).ok()?;
+                offset = (offset + len + 1 + 3) & !3;
+                nodes.push(Node {
+                    name,
+                    parent: stack.last().copied(),
+                    props: Vec::new(),
+                });
+                stack.push(nodes.len() - 1);
+            }
+            FDT_END_NODE => {
+                stack.pop()?;
+            }
+            FDT_PROP => {
+                let len = be32(structs, offset)? as usize;
+                let name_offset = be32(structs, offset + 4)? as usize;
+                let value = structs.get(offset + 8..offset + 8 + len)?;
+                offset = (offset + 8 + len + 3) & !3;
+                let name_len = strings.get(name_offset..)?.iter().position(|&b| b == 0)?;
+                let name = from_utf8(&strings[name_offset..name_offset + name_len]).ok()?;
+                nodes[*stack.last()?].props.push((name, value));
+            }
+            FDT_NOP => {}
+            FDT_END => return Some(nodes),
+            _ => return None,
+        }
+    }
+}
+
+/// read a number of `cells` 32-bit cells
+fn read_cells(value: &[u8], offset: usize, cells: u32) -> Option<usize> {
+    (0..cells as usize).try_fold(0usize, |acc, i| {
+        Some(acc << 32 | be32(value, offset + i * 4)? as usize)
+    })
+}
+
+/// `reg` of `nodes[idx]` as base and size pairs, sized by the cells of its parent
+fn read_reg(nodes: &[Node], idx: usize) -> Vec<(usize, usize)> {
+    let parent = nodes[idx].parent.map(|parent| &nodes[parent]);
+    let address_cells = parent
+        .and_then(|parent| parent.prop_u32("#address-cells"))
+        .unwrap_or(2);
+    let size_cells = parent
+        .and_then(|parent| parent.prop_u32("#size-cells"))
+        .unwrap_or(1);
+    let reg = match nodes[idx].prop("reg") {
+        Some(reg) => reg,
+        None => return Vec::new(),
+    };
+    let entry = (address_cells + size_cells) as usize * 4;
+    (0..reg.len() / entry.max(1))
+        .filter_map(|i| {
+            let base = read_cells(reg, i * entry, address_cells)?;
+            let size = read_cells(reg, i * entry + address_cells as usize * 4, size_cells)?;
+            Some((base, size))
+        })
+        .collect()
+}
+
+fn read_mmio(nodes: &[Node], idx: usize) -> Option<Mmio> {
+    let (base, size) = *read_reg(nodes, idx).first()?;
+    Some(Mmio::new(base, size, nodes[idx].prop_u32("interrupts")))
+}
+
+/// Parse the tree at physical address `dtb`, `None` if it is not one.
+unsafe fn parse(dtb: usize) -> Option<MachineInfo> {
+    if dtb == 0 || dtb % 4 != 0 {
+        return None;
+    }
+    let header = core::slice::from_raw_parts(dtb as *const u8, 40);
+    if be32(header, 0)? != FDT_MAGIC {
+        return None;
+    }
+    let total_size = be32(header, 4)? as usize;
+    let blob = core::slice::from_raw_parts(dtb as *const u8, total_size);
+    let off_structs = be32(header, 8)? as usize;
+    let off_strings = be32(header, 12)? as usize;
+    let size_strings = be32(header, 32)? as usize;
+    let size_structs = be32(header, 36)? as usize;
+    let nodes = read_nodes(
+        blob.get(off_structs..off_structs + size_structs)?,
+        blob.get(off_strings..off_strings + size_strings)?,
+    )?;
+
+    let mut info = MachineInfo {
+        memory: Vec::new(),
+        timebase_freq: CLOCK_FREQ,
+        harts: Vec::new(),
+        test: None,
+        rtc: None,
+        uart: None,
+        plic: None,
+        virtio: Vec::new(),
+        bootargs: String::new(),
+    };
+    for (idx, node) in nodes.iter().enumerate() {
+        if !node.enabled() {
+            continue;
+        }
+        if node.has_string("device_type", "memory") {
+            info.memory.extend(read_reg(&nodes, idx));
+        } else if node.has_string("device_type", "cpu") {
+            if let Some(&(hart, _)) = read_reg(&nodes, idx).first() {
+                info.harts.push(hart);
+            }
+            if let Some(freq) = node.prop_u32("timebase-frequency") {
+                info.timebase_freq = freq as usize;
+            }
+        } else if node.name == "chosen" {
+            if let Some(bootargs) = node.prop("bootargs") {
+                let len = bootargs
+                    .iter()
+                    .position(|&b| b == 0)
+                    .unwrap_or(bootargs.len());
+                info.bootargs = String::from(from_utf8(&bootargs[..len]).ok()?);
+            }
+        } else if node.name == "cpus" {
+            if let Some(freq) = node.prop_u32("timebase-frequency") {
+                info.timebase_freq = freq as usize;
+            }
+        } else if node.has_string("compatible", "sifive,test0") {
+            info.test = read_mmio(&nodes, idx);
+        } else if node.has_string("compatible", "google,goldfish-rtc") {
+            info.rtc = read_mmio(&nodes, idx);
+        } else if node.has_string("compatible", "ns16550a") {
+            info.uart = info.uart.or_else(|| read_mmio(&nodes, idx));
+        } else if node.has_string("compatible", "riscv,plic0")
+            || node.has_string("compatible", "sifive,plic-1.0.0")
+        {
+            info.plic = read_mmio(&nodes, idx);
+        } else if node.has_string("compatible", "virtio,mmio") {
+            info.virtio.extend(read_mmio(&nodes, idx));
+        }
+    }
+    if info.memory.is_empty() {
+        return None;
+    }
+    info.harts.sort_unstable();
+    Some(info)
+}
@@ -0,0 +1,53 @@
+//! The easy-fs image on the virtio-blk disk, mounted at boot.
+//!
+//! The filesystem is flat, the root directory holds the ELF files of the
+//! user apps under their names. A task waiting for a lock of easy-fs lets
+//! the others run, the holder may be parked on the disk.
+
+use crate::{drivers::block_device, task::relax};
+use alloc::{string::String, sync::Arc, vec::Vec};
+use easy_fs::{set_lock_relax, EasyFileSystem, Inode};
+use spin::Once;
+
+static ROOT_INODE: Once<Arc<Inode>> = Once::new();
+
+/// Mount the filesystem of the disk, if there is a disk.
+pub fn init() {
+    set_lock_relax(relax);
+    match block_device() {
+        Some(device) => {
+            ROOT_INODE.call_once(|| {
+                let efs = EasyFileSystem::open(device);
+                Arc::new(EasyFileSystem::root_inode(&efs))
+            });
+            info!("easy-fs mounted");
+        }
+        None => warn!("no disk, no filesystem to load apps from"),
+    }
+}
+
+/// names of the files in the root directory
+pub fn list() -> Vec<String> {
+    ROOT_INODE.get().map_or_else(Vec::new, |root| root.ls())
+}
+
+pub fn exists(name: &str) -> bool {
+    ROOT_INODE
+        .get()
+        .map_or(false, |root| root.find(name).is_some())
+}
+
+/// the whole content of file `name`, `None` if there is no such file
+pub fn read_file(name: &str) -> Option<Vec<u8>> {
+    let inode = ROOT_INODE.get()?.find(name)?;
+    let mut buffer = [0u8; 512];
+    let mut data = Vec::new();
+    loop {
+        let len = inode.read_at(data.len(), &mut buffer);
+        if len == 0 {
+            break;
+        }
+        data.extend_from_slice(&buffer[..len]);
+    }
+    Some(data)
+}
@@ -1,4 +1,4 @@
-use crate::sbi::shutdown;
+use crate::drivers::{poweroff, PANIC_EXIT_CODE};
 use core::panic::PanicInfo;
 
 #[panic_handler]
@@ -13,5 +13,5 @@ fn panic(info: &PanicInfo) -> ! {
     } else {
         println!("[kernel] Panicked: {}", info.message().unwrap());
     }
-    shutdown()
+    poweroff(PANIC_EXIT_CODE)
 }
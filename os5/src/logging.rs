@@ -39,3 +39,23 @@ pub fn init() {
         _ => LevelFilter::Off,
     });
 }
+
+/// Set the log level by name, case insensitive, or as number from 0 (off)
+/// to 5 (trace). Returns false if `level` is neither.
+pub fn set_level(level: &str) -> bool {
+    let filter = match level.parse::<usize>() {
+        Ok(0) => LevelFilter::Off,
+        Ok(1) => LevelFilter::Error,
+        Ok(2) => LevelFilter::Warn,
+        Ok(3) => LevelFilter::Info,
+        Ok(4) => LevelFilter::Debug,
+        Ok(5) => LevelFilter::Trace,
+        Ok(_) => return false,
+        Err(_) => match level.parse::<LevelFilter>() {
+            Ok(filter) => filter,
+            Err(_) => return false,
+        },
+    };
+    log::set_max_level(filter);
+    true
+}
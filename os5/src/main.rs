@@ -5,7 +5,7 @@
 
 use alloc::{sync::Arc, vec::Vec};
 use lazy_static::lazy_static;
-use sync::UPSafeCell;
+use sync::SpinLock;
 use task::add_task;
 
 use crate::task::Task;
@@ -19,12 +19,18 @@ extern crate alloc;
 
 #[macro_use]
 mod console;
+mod cmdline;
 mod config;
+mod drivers;
+mod errno;
+mod fdt;
+mod fs;
 mod lang_items;
 mod loader;
 mod logging;
 mod mm;
 mod sbi;
+mod smp;
 mod sync;
 mod syscall;
 mod task;
@@ -32,7 +38,8 @@ mod timer;
 mod trap;
 
 core::arch::global_asm!(include_str!("entry.asm"));
-core::arch::global_asm!(include_str!("link_app.S"));
+// entry.asm has its own copy of the number of harts and their stack size
+const _: () = assert!(config::MAX_HARTS == 4 && config::BOOT_STACK_SIZE == 0x400000);
 
 fn clear_bss() {
     extern "C" {
@@ -46,13 +53,34 @@ fn clear_bss() {
 }
 
 #[no_mangle]
-pub fn rust_main() -> ! {
+pub fn rust_main(hart_id: usize, dtb: usize) -> ! {
+    if !smp::claim_boot_hart(hart_id) {
+        smp::wait_for_boot();
+        secondary_main(hart_id)
+    }
     clear_bss();
+    smp::set_online();
     logging::init();
     println!("[kernel] Hello, world!");
+    sbi::init();
+    mm::init_heap();
+    fdt::init(dtb);
+    cmdline::init(&fdt::machine().bootargs);
+    timer::set_timebase_freq(fdt::machine().timebase_freq);
     mm::init();
     info!("after mm init!");
     mm::remap_test();
+    mm::slab_test();
+    // the disk interrupts while the filesystem is mounted already
+    drivers::init_hart(hart_id);
+    trap::enable_external_interrupt();
+    trap::enable_software_interrupt();
+    drivers::init();
+    fs::init();
+    if let Some(device) = drivers::swap_device() {
+        mm::init_swap(device, 0, config::SWAP_SIZE / drivers::BLOCK_SIZE);
+    }
+    task::init();
     trap::init();
     trap::enable_timer_interrupt();
     timer::set_next_trigger();
@@ -60,12 +88,26 @@ pub fn rust_main() -> ! {
     // task::add_initproc();
     // info!("after initproc!");
     run_usertest();
+    smp::start_secondary_harts();
+    task::run_next_task()
+}
+
+/// per hart setup of the harts started by the boot hart
+fn secondary_main(hart_id: usize) -> ! {
+    mm::init_hart();
+    trap::init();
+    trap::enable_timer_interrupt();
+    drivers::init_hart(hart_id);
+    trap::enable_external_interrupt();
+    trap::enable_software_interrupt();
+    timer::set_next_trigger();
+    smp::set_online();
+    info!("hart {} started", hart_id);
     task::run_next_task()
 }
 
 lazy_static! {
-    pub static ref BATCH_PROCESSING_TASK: UPSafeCell<Vec<Arc<Task>>> =
-        unsafe { UPSafeCell::new(Vec::new()) };
+    pub static ref BATCH_PROCESSING_TASK: SpinLock<Vec<Arc<Task>>> = SpinLock::new(Vec::new());
 }
 
 pub fn run_target_task(names: &[&str]) {
@@ -74,7 +116,7 @@ pub fn run_target_task(names: &[&str]) {
      * 进而释放内核栈, 导致缺页错误. 所以要在这里
      * 留一个引用计数
      */
-    let mut batch_processing_task = BATCH_PROCESSING_TASK.exclusive_access();
+    let mut batch_processing_task = BATCH_PROCESSING_TASK.lock();
     for name in names.iter() {
         batch_processing_task.push(Task::new(*name));
     }
@@ -84,8 +126,24 @@ pub fn run_target_task(names: &[&str]) {
     }
 }
 
+/// start the apps named by `init=` on the command line
 pub fn run_usertest() {
-    run_target_task(&["ch5_usertest"]);
+    let names: Vec<&str> = cmdline::boot_args()
+        .init
+        .iter()
+        .map(|name| name.as_str())
+        .filter(|name| {
+            let found = loader::has_app(name);
+            if !found {
+                println!("[kernel] init: no app named {}, skipped", name);
+            }
+            found
+        })
+        .collect();
+    if names.is_empty() {
+        panic!("no init app to run");
+    }
+    run_target_task(&names);
     // batch_processing_task.push(Task::new("ch2b_bad_address"));
     // batch_processing_task.push(Task::new("ch2b_hello_world"));
     // batch_processing_task.push(Task::new("ch2b_power_7"));
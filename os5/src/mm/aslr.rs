@@ -0,0 +1,29 @@
+//! Randomization of the user address space layout.
+//!
+//! Offsets come from a xorshift generator which is seeded from the `time`
+//! CSR the first time it is used, so they differ from boot to boot.
+
+use crate::config::PAGE_SIZE;
+use crate::timer::get_time;
+use spin::Mutex;
+
+static STATE: Mutex<u64> = Mutex::new(0);
+
+fn next_random() -> u64 {
+    let mut state = STATE.lock();
+    if *state == 0 {
+        // never seed with 0, xorshift would get stuck there
+        *state = get_time() as u64 ^ 0x9e37_79b9_7f4a_7c15;
+    }
+    let mut x = *state;
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    *state = x;
+    x
+}
+
+/// a random page aligned offset below `pages` pages
+pub fn random_offset(pages: usize) -> usize {
+    (next_random() as usize % pages) * PAGE_SIZE
+}
@@ -2,10 +2,13 @@
 //! controls all the frames in the operating system.
 
 use super::{PhysAddr, PhysPageNum};
-use crate::config::MEMORY_END;
-use crate::sync::UPSafeCell;
+use crate::fdt::machine;
+use crate::sync::SpinLock;
 use alloc::vec::Vec;
-use core::fmt::{self, Debug, Formatter};
+use core::{
+    fmt::{self, Debug, Formatter},
+    ops::Range,
+};
 use lazy_static::*;
 
 /// manage a frame which has the same lifecycle as the tracker
@@ -40,6 +43,7 @@ trait FrameAllocator {
     fn new() -> Self;
     fn alloc(&mut self) -> Option<PhysPageNum>;
     fn dealloc(&mut self, ppn: PhysPageNum);
+    fn available(&self) -> usize;
 }
 
 /// an implementation for frame allocator
@@ -53,6 +57,25 @@ impl StackFrameAllocator {
     pub fn init(&mut self, l: PhysPageNum, r: PhysPageNum) {
         self.current = l.0;
         self.end = r.0;
+        // the kernel heap may grow with frames, so `recycled` must never
+        // allocate while the allocator is borrowed
+        self.recycled.reserve(r.0 - l.0);
+    }
+    /// Take `pages` contiguous frames from the untouched range, or else from
+    /// a run of adjacent recycled frames. They go back one by one.
+    pub fn alloc_contiguous(&mut self, pages: usize) -> Option<PhysPageNum> {
+        if self.end - self.current >= pages {
+            self.current += pages;
+            return Some((self.current - pages).into());
+        }
+        // sorted in place, `recycled` must not allocate
+        self.recycled.sort_unstable();
+        let last = self.recycled.len().checked_sub(pages)?;
+        let start =
+            (0..=last).find(|&i| self.recycled[i + pages - 1] - self.recycled[i] == pages - 1)?;
+        let ppn = self.recycled[start];
+        self.recycled.drain(start..start + pages);
+        Some(ppn.into())
     }
 }
 impl FrameAllocator for StackFrameAllocator {
@@ -82,38 +105,53 @@ impl FrameAllocator for StackFrameAllocator {
         // recycle
         self.recycled.push(ppn);
     }
+    fn available(&self) -> usize {
+        self.end - self.current + self.recycled.len()
+    }
 }
 
 type FrameAllocatorImpl = StackFrameAllocator;
 
 lazy_static! {
     /// frame allocator instance through lazy_static!
-    pub static ref FRAME_ALLOCATOR: UPSafeCell<FrameAllocatorImpl> =
-        unsafe { UPSafeCell::new(FrameAllocatorImpl::new()) };
+    pub static ref FRAME_ALLOCATOR: SpinLock<FrameAllocatorImpl> =
+        SpinLock::new(FrameAllocatorImpl::new());
 }
 
-/// initiate the frame allocator using `ekernel` and `MEMORY_END`
-pub fn init_frame_allocator() {
+/// the frames of the RAM from `ekernel` to the end of its region
+pub fn managed_frames() -> Range<usize> {
     extern "C" {
         fn ekernel();
     }
-    FRAME_ALLOCATOR.exclusive_access().init(
-        PhysAddr::from(ekernel as usize).ceil(),
-        PhysAddr::from(MEMORY_END).floor(),
-    );
+    PhysAddr::from(ekernel as usize).ceil().0..PhysAddr::from(machine().memory_end()).floor().0
+}
+
+/// initiate the frame allocator with the [`managed_frames`]
+pub fn init_frame_allocator() {
+    let frames = managed_frames();
+    FRAME_ALLOCATOR
+        .lock()
+        .init(frames.start.into(), frames.end.into());
 }
 
 /// allocate a frame
 pub fn frame_alloc() -> Option<FrameTracker> {
-    FRAME_ALLOCATOR
-        .exclusive_access()
-        .alloc()
-        .map(FrameTracker::new)
+    FRAME_ALLOCATOR.lock().alloc().map(FrameTracker::new)
+}
+
+/// allocate `pages` contiguous frames for the kernel heap or DMA, without tracker
+pub fn frame_alloc_contiguous(pages: usize) -> Option<PhysPageNum> {
+    FRAME_ALLOCATOR.lock().alloc_contiguous(pages)
+}
+
+/// number of frames that can still be allocated
+pub fn frames_available() -> usize {
+    FRAME_ALLOCATOR.lock().available()
 }
 
 /// deallocate a frame
-fn frame_dealloc(ppn: PhysPageNum) {
-    FRAME_ALLOCATOR.exclusive_access().dealloc(ppn);
+pub fn frame_dealloc(ppn: PhysPageNum) {
+    FRAME_ALLOCATOR.lock().dealloc(ppn);
 }
 
 #[allow(unused)]
@@ -1,16 +1,151 @@
 //! The global allocator
+//!
+//! The kernel heap starts out in a static array and grows with frames from
+//! the frame allocator, recycled ones included. Growth is permanent: the
+//! buddy heap cannot give a range back, so frames it has grown with stay
+//! with the heap even once all their blocks are free.
 
-use crate::config::KERNEL_HEAP_SIZE;
-use buddy_system_allocator::LockedHeap;
+use super::frame_allocator::frame_alloc_contiguous;
+use super::slab::{slab_alloc, slab_dealloc};
+use super::PhysAddr;
+use crate::config::{KERNEL_HEAP_GROW_PAGES, KERNEL_HEAP_SIZE, PAGE_SIZE};
+use buddy_system_allocator::Heap;
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    mem::size_of,
+    ptr::NonNull,
+};
+use spin::Mutex;
+
+/// usage statistics of the kernel heap, in bytes
+#[derive(Debug, Default, Clone, Copy)]
+pub struct HeapStats {
+    /// memory managed by the heap, including frames it has grown with
+    pub total: usize,
+    /// bytes requested by live allocations
+    pub allocated: usize,
+    /// bytes taken by live allocations after buddy rounding
+    pub actual: usize,
+    /// highest value `allocated` has reached
+    pub peak: usize,
+    /// frames taken from the frame allocator
+    pub grown_frames: usize,
+}
+
+impl HeapStats {
+    /// internal fragmentation in percent, the share of `actual` lost to rounding
+    pub fn fragmentation(&self) -> usize {
+        if self.actual == 0 {
+            0
+        } else {
+            (self.actual - self.allocated) * 100 / self.actual
+        }
+    }
+}
+
+struct HeapInner {
+    heap: Heap,
+    stats: HeapStats,
+}
+
+/// A buddy heap living in `HEAP_SPACE`, which grows with contiguous frames
+/// from the frame allocator when it runs out of memory. Allocations made
+/// through the handle of a slab cache are served by the cache instead.
+pub struct GrowableHeap(Mutex<HeapInner>);
+
+/// block size the buddy system really hands out for `layout`
+fn buddy_size(layout: &Layout) -> usize {
+    layout
+        .size()
+        .next_power_of_two()
+        .max(layout.align())
+        .max(size_of::<usize>())
+}
+
+impl HeapInner {
+    /// Add enough frames for `layout`, any range twice as large as a
+    /// power-of-two block contains an aligned one. It takes
+    /// `KERNEL_HEAP_GROW_PAGES` at a time, or just what `layout` needs if
+    /// the free frames are too scattered for that.
+    fn grow(&mut self, layout: &Layout) -> bool {
+        let bytes = buddy_size(layout) * 2;
+        let needed = (bytes + PAGE_SIZE - 1) / PAGE_SIZE;
+        let mut pages = needed.max(KERNEL_HEAP_GROW_PAGES);
+        let mut start = frame_alloc_contiguous(pages);
+        if start.is_none() {
+            pages = needed;
+            start = frame_alloc_contiguous(pages);
+        }
+        let start: PhysAddr = match start {
+            Some(ppn) => ppn.into(),
+            None => return false,
+        };
+        // physical memory is identically mapped in kernel space
+        unsafe {
+            self.heap.add_to_heap(start.0, start.0 + pages * PAGE_SIZE);
+        }
+        self.stats.total += pages * PAGE_SIZE;
+        self.stats.grown_frames += pages;
+        true
+    }
+}
+
+unsafe impl GlobalAlloc for GrowableHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if let Some(ptr) = slab_alloc(&layout) {
+            return ptr;
+        }
+        let mut inner = self.0.lock();
+        let ptr = match inner.heap.alloc(layout) {
+            Ok(ptr) => ptr,
+            Err(_) => {
+                if !inner.grow(&layout) {
+                    return core::ptr::null_mut();
+                }
+                match inner.heap.alloc(layout) {
+                    Ok(ptr) => ptr,
+                    Err(_) => return core::ptr::null_mut(),
+                }
+            }
+        };
+        let stats = &mut inner.stats;
+        stats.allocated += layout.size();
+        stats.actual += buddy_size(&layout);
+        stats.peak = stats.peak.max(stats.allocated);
+        ptr.as_ptr()
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if slab_dealloc(ptr) {
+            return;
+        }
+        let mut inner = self.0.lock();
+        inner.heap.dealloc(NonNull::new_unchecked(ptr), layout);
+        inner.stats.allocated -= layout.size();
+        inner.stats.actual -= buddy_size(&layout);
+    }
+}
 
 #[global_allocator]
 /// heap allocator instance
-static HEAP_ALLOCATOR: LockedHeap = LockedHeap::empty();
+static HEAP_ALLOCATOR: GrowableHeap = GrowableHeap(Mutex::new(HeapInner {
+    heap: Heap::empty(),
+    stats: HeapStats {
+        total: 0,
+        allocated: 0,
+        actual: 0,
+        peak: 0,
+        grown_frames: 0,
+    },
+}));
 
 #[alloc_error_handler]
 /// panic when heap allocation error occurs
 pub fn handle_alloc_error(layout: core::alloc::Layout) -> ! {
-    panic!("Heap allocation error, layout = {:?}", layout);
+    panic!(
+        "Heap allocation error, layout = {:?}, heap stats = {:?}",
+        layout,
+        heap_stats()
+    );
 }
 
 /// heap space ([u8; KERNEL_HEAP_SIZE])
@@ -24,12 +159,19 @@ pub fn init_heap() {
             HEAP_SPACE.as_ptr() as usize,
             HEAP_SPACE.as_ptr() as usize + KERNEL_HEAP_SIZE
         );
-        HEAP_ALLOCATOR
-            .lock()
+        let mut inner = HEAP_ALLOCATOR.0.lock();
+        inner
+            .heap
             .init(HEAP_SPACE.as_ptr() as usize, KERNEL_HEAP_SIZE);
+        inner.stats.total = KERNEL_HEAP_SIZE;
     }
 }
 
+/// current usage statistics of the kernel heap
+pub fn heap_stats() -> HeapStats {
+    HEAP_ALLOCATOR.0.lock().stats
+}
+
 #[allow(unused)]
 pub fn heap_test() {
     use alloc::boxed::Box;
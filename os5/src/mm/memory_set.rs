@@ -1,12 +1,25 @@
 //! Implementation of [`MapArea`] and [`MemorySet`].
 //!
 use super::{
-    frame_alloc, FrameTracker, PTEFlags, PageTable, PageTableEntry, PhysAddr, PhysPageNum,
-    StepByOne, VPNRange, VirtAddr, VirtPageNum,
+    aslr::random_offset,
+    frame_alloc,
+    frame_allocator::frames_available,
+    policy::check_wx,
+    shm,
+    swap::{self, SwapSlot, SwapStat},
+    FrameTracker, PTEFlags, PageTable, PageTableEntry, PhysAddr, PhysPageNum, StepByOne,
+    VPNRange, VirtAddr, VirtPageNum,
 };
 use crate::{
-    config::{MEMORY_END, PAGE_SIZE, TRAMPOLINE, TRAP_CONTEXT, USER_STACK_SIZE},
-    task::PidHandle,
+    config::{
+        ASLR_PAGES, PAGE_SIZE, TRAMPOLINE, TRAP_CONTEXT, USER_MMAP_BASE, USER_PIE_BASE,
+        USER_STACK_RLIMIT, USER_STACK_SIZE, USER_STACK_TOP,
+    },
+    errno::Errno,
+    fdt::machine,
+    sbi::remote_sfence_vma,
+    smp::hart_id,
+    task::{swap_out_ready_task, PidHandle},
 };
 use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
 use lazy_static::*;
@@ -26,6 +39,11 @@ extern "C" {
     fn strampoline();
 }
 
+/// Frames kept free for page tables, kernel stacks and the growth of the
+/// kernel heap, which takes recycled frames as well. User pages are
+/// swapped out before the frame allocator drops below this watermark.
+const SWAP_WATERMARK: usize = 16;
+
 lazy_static! {
     /// a memory set instance through lazy_static! managing kernel space
     pub static ref KERNEL_SPACE: Arc<Mutex<MemorySet>> =
@@ -37,6 +55,22 @@ lazy_static! {
 pub struct MemorySet {
     page_table: PageTable,
     areas: Vec<MapArea>,
+    /// clock hand of page replacement: (index of area, vpn)
+    clock_hand: (usize, usize),
+    swap_stat: SwapStat,
+    /// bit i is set once the address space has been active on hart i, whose
+    /// tlb may still hold its translations
+    harts: usize,
+    /// start of the heap area, right after the last elf segment
+    heap_bottom: usize,
+    /// current program break
+    brk: usize,
+    /// top of the user stack
+    stack_top: usize,
+    /// lowest address the user stack may grow down to, the page below it is the guard page
+    stack_limit: usize,
+    /// where mmap starts looking for free ranges
+    mmap_base: usize,
 }
 
 impl MemorySet {
@@ -46,17 +80,40 @@ impl MemorySet {
         memory_set.map_trampoline();
         // copy data sections/trap_context/user_stack
         for area in user_space.areas.iter() {
-            let new_area = MapArea::from_another(area);
-            memory_set.push(new_area, None);
-            // copy data from another space
+            let mut new_area = MapArea::from_another(area);
+            if let Some(id) = area.shm_id {
+                // shared memory keeps being shared with the child
+                let frames = shm::shm_attach(id).expect("attached segment is gone");
+                new_area.map_shared(&mut memory_set.page_table, frames);
+                memory_set.areas.push(new_area);
+                continue;
+            }
             for vpn in area.vpn_range {
-                let src_ppn = user_space.translate(vpn).unwrap().ppn();
-                let dst_ppn = memory_set.translate(vpn).unwrap().ppn();
-                dst_ppn
-                    .get_bytes_array()
-                    .copy_from_slice(src_ppn.get_bytes_array());
+                let frame = match area.map_type {
+                    MapType::Identical => None,
+                    MapType::Framed => {
+                        let frame = memory_set.alloc_frame().expect("out of memory!");
+                        // copy data from another space, including swapped out pages
+                        if let Some(src) = area.data_frames.get(&vpn) {
+                            frame
+                                .ppn
+                                .get_bytes_array()
+                                .copy_from_slice(src.ppn.get_bytes_array());
+                        } else if let Some(slot) = area.swapped.get(&vpn) {
+                            swap::swap_in(slot, frame.ppn);
+                        }
+                        Some(Arc::new(frame))
+                    }
+                };
+                new_area.map_one(&mut memory_set.page_table, vpn, frame);
             }
+            memory_set.areas.push(new_area);
         }
+        memory_set.heap_bottom = user_space.heap_bottom;
+        memory_set.brk = user_space.brk;
+        memory_set.stack_top = user_space.stack_top;
+        memory_set.stack_limit = user_space.stack_limit;
+        memory_set.mmap_base = user_space.mmap_base;
         memory_set
     }
 
@@ -64,6 +121,14 @@ impl MemorySet {
         Self {
             page_table: PageTable::new(),
             areas: Vec::new(),
+            clock_hand: (0, 0),
+            swap_stat: SwapStat::default(),
+            harts: 0,
+            heap_bottom: 0,
+            brk: 0,
+            stack_top: 0,
+            stack_limit: PAGE_SIZE,
+            mmap_base: USER_MMAP_BASE,
         }
     }
     pub fn token(&self) -> usize {
@@ -75,11 +140,11 @@ impl MemorySet {
         start_va: VirtAddr,
         end_va: VirtAddr,
         permission: MapPermission,
-    ) -> Result<(), ()> {
+    ) -> Result<(), Errno> {
         for area in &self.areas {
             let range = area.vpn_range;
             if range.contains(start_va.floor()) || range.contains(end_va.floor()) {
-                return Err(());
+                return Err(Errno::EEXIST);
             }
         }
         self.push(
@@ -88,6 +153,125 @@ impl MemorySet {
         );
         Ok(())
     }
+    /// Attach shared memory segment `shm_id` at `start_va`, or at a free
+    /// place if `start_va` is 0. Returns the start address.
+    pub fn insert_shared_area(
+        &mut self,
+        start_va: VirtAddr,
+        shm_id: usize,
+        permission: MapPermission,
+    ) -> Result<VirtAddr, Errno> {
+        let len = shm::shm_size(shm_id).ok_or(Errno::EINVAL)?;
+        let start_va = if start_va.0 == 0 {
+            self.find_free_area(len).ok_or(Errno::ENOMEM)?
+        } else {
+            start_va
+        };
+        // user mappings stay in the lower half of the address space
+        let end_va: VirtAddr = match start_va.0.checked_add(len) {
+            Some(end) if end <= USER_STACK_TOP => end.into(),
+            _ => return Err(Errno::EINVAL),
+        };
+        if !start_va.aligned() || self.overlaps(start_va.floor(), end_va.ceil()) {
+            return Err(Errno::EINVAL);
+        }
+        // attach only once nothing can fail, the area detaches when dropped
+        let frames = shm::shm_attach(shm_id).ok_or(Errno::EINVAL)?;
+        let mut map_area = MapArea::new(start_va, end_va, MapType::Framed, permission);
+        map_area.shm_id = Some(shm_id);
+        map_area.map_shared(&mut self.page_table, frames);
+        self.areas.push(map_area);
+        Ok(start_va)
+    }
+    /// Detach the shared memory area starting at `start_va`.
+    pub fn remove_shared_area(&mut self, start_va: VirtAddr) -> Result<(), Errno> {
+        let idx = self
+            .areas
+            .iter()
+            .position(|area| {
+                area.shm_id.is_some() && VirtAddr::from(area.vpn_range.get_start()) == start_va
+            })
+            .ok_or(Errno::EINVAL)?;
+        self.areas[idx].unmap(&mut self.page_table);
+        self.areas.remove(idx);
+        Ok(())
+    }
+    /// whether any area intersects with [start_vpn, end_vpn)
+    fn overlaps(&self, start_vpn: VirtPageNum, end_vpn: VirtPageNum) -> bool {
+        self.areas.iter().any(|area| {
+            area.vpn_range.get_start() < end_vpn && start_vpn < area.vpn_range.get_end()
+        })
+    }
+    /// Find the lowest free range of `len` bytes above the mmap base, `None`
+    /// if there is none below the user stack top.
+    fn find_free_area(&self, len: usize) -> Option<VirtAddr> {
+        let pages = (len + PAGE_SIZE - 1) / PAGE_SIZE;
+        let limit = VirtAddr::from(USER_STACK_TOP).floor();
+        let mut start_vpn = VirtAddr::from(self.mmap_base).floor();
+        while let Some(area) = self.areas.iter().find(|area| {
+            area.vpn_range.get_start() < VirtPageNum(start_vpn.0 + pages)
+                && start_vpn < area.vpn_range.get_end()
+        }) {
+            start_vpn = area.vpn_range.get_end();
+        }
+        match start_vpn.0.checked_add(pages) {
+            Some(end_vpn) if end_vpn <= limit.0 => Some(start_vpn.into()),
+            _ => None,
+        }
+    }
+    /// Change the permission of [start_va, end_va) to `perm` for task `pid`.
+    /// The range has to lie in a single private area, which is split at its
+    /// boundaries. The heap and the stack cannot be changed.
+    pub fn protect(
+        &mut self,
+        pid: usize,
+        start_va: VirtAddr,
+        end_va: VirtAddr,
+        perm: MapPermission,
+    ) -> Result<(), Errno> {
+        let (start_vpn, end_vpn) = (start_va.floor(), end_va.ceil());
+        if !start_va.aligned() || start_vpn >= end_vpn {
+            return Err(Errno::EINVAL);
+        }
+        let mut idx = self
+            .areas
+            .iter()
+            .position(|area| {
+                area.vpn_range.get_start() <= start_vpn && end_vpn <= area.vpn_range.get_end()
+            })
+            .ok_or(Errno::ENOMEM)?;
+        let area = &self.areas[idx];
+        let whole = area.vpn_range.get_start() == start_vpn && area.vpn_range.get_end() == end_vpn;
+        if !area.map_perm.contains(MapPermission::U)
+            || area.vpn_range.get_start() == VirtAddr::from(self.heap_bottom).floor()
+            || area.vpn_range.get_end() == VirtAddr::from(self.stack_top).floor()
+            || (area.shm_id.is_some() && !whole)
+        {
+            return Err(Errno::EINVAL);
+        }
+        if !check_wx(pid, start_va, perm, area.was_writable) {
+            return Err(Errno::EACCES);
+        }
+        if let Some(upper) = self.areas[idx].split_off(end_vpn) {
+            self.areas.insert(idx + 1, upper);
+        }
+        if let Some(middle) = self.areas[idx].split_off(start_vpn) {
+            self.areas.insert(idx + 1, middle);
+            idx += 1;
+        }
+        let area = &mut self.areas[idx];
+        area.map_perm = perm;
+        area.was_writable |= perm.contains(MapPermission::W);
+        // swapped out pages take the new permission when they come back
+        let flags = PTEFlags::from_bits(perm.bits).unwrap();
+        for vpn in area.data_frames.keys() {
+            self.page_table.set_flags(*vpn, flags);
+        }
+        unsafe {
+            core::arch::asm!("sfence.vma");
+        }
+        Ok(())
+    }
     pub fn remove_area_with_start_vpn(&mut self, start_vpn: VirtPageNum) {
         if let Some((idx, area)) = self
             .areas
@@ -104,7 +288,7 @@ impl MemorySet {
         task_pid: PidHandle,
         start_va: VirtAddr,
         end_va: VirtAddr,
-    ) -> Result<(), ()> {
+    ) -> Result<(), Errno> {
         let mut target = usize::MAX;
         for (idx, area) in self.areas.iter().enumerate() {
             let range = area.vpn_range;
@@ -115,16 +299,22 @@ impl MemorySet {
         }
         log::info!("task_{}, unmap_area select area {}", task_pid, target);
         if target == usize::MAX {
-            return Err(());
+            return Err(Errno::EINVAL);
         }
         self.areas[target].unmap(&mut self.page_table);
         self.areas.remove(target);
         Ok(())
     }
     fn push(&mut self, mut map_area: MapArea, data: Option<&[u8]>) {
-        map_area.map(&mut self.page_table);
+        for vpn in map_area.vpn_range {
+            let frame = match map_area.map_type {
+                MapType::Identical => None,
+                MapType::Framed => Some(Arc::new(self.alloc_frame().expect("out of memory!"))),
+            };
+            map_area.map_one(&mut self.page_table, vpn, frame);
+        }
         if let Some(data) = data {
-            map_area.copy_data(&mut self.page_table, data);
+            map_area.copy_data(&mut self.page_table, data, 0);
         }
         self.areas.push(map_area);
     }
@@ -193,58 +383,244 @@ impl MemorySet {
         memory_set.push(
             MapArea::new(
                 (ekernel as usize).into(),
-                MEMORY_END.into(),
+                machine().memory_end().into(),
                 MapType::Identical,
                 MapPermission::R | MapPermission::W,
             ),
             None,
         );
+        info!("mapping memory-mapped registers");
+        for mmio in machine().mmio_regions() {
+            memory_set.push(
+                MapArea::new(
+                    mmio.base.into(),
+                    (mmio.base + mmio.size).into(),
+                    MapType::Identical,
+                    MapPermission::R | MapPermission::W,
+                ),
+                None,
+            );
+        }
         memory_set
     }
+    /// Check the identification and the header fields xmas-elf leaves to the user.
+    /// Return the type of the image.
+    fn check_elf_header(elf_data: &[u8]) -> Result<u16, ElfLoadError> {
+        if elf_data.len() < ELF64_HEADER_SIZE || elf_data[..4] != [0x7f, 0x45, 0x4c, 0x46] {
+            return Err(ElfLoadError::NotElf);
+        }
+        if elf_data[4] != ELFCLASS64 {
+            return Err(ElfLoadError::BadClass);
+        }
+        if elf_data[5] != ELFDATA2LSB {
+            return Err(ElfLoadError::BadEndian);
+        }
+        let elf_type = u16::from_le_bytes([elf_data[16], elf_data[17]]);
+        if elf_type != ET_EXEC && elf_type != ET_DYN {
+            return Err(ElfLoadError::BadType);
+        }
+        if u16::from_le_bytes([elf_data[18], elf_data[19]]) != EM_RISCV {
+            return Err(ElfLoadError::BadMachine);
+        }
+        Ok(elf_type)
+    }
+    /// Write a word of user memory which has already been mapped.
+    fn write_user_u64(&mut self, va: usize, value: u64) -> bool {
+        if va % 8 != 0 {
+            return false;
+        }
+        let va = VirtAddr::from(va);
+        match self.page_table.translate(va.floor()) {
+            Some(pte) if pte.is_valid() => {
+                let offset = va.page_offset();
+                pte.ppn().get_bytes_array()[offset..offset + 8]
+                    .copy_from_slice(&value.to_le_bytes());
+                true
+            }
+            _ => false,
+        }
+    }
+    /// Apply the relocations found through the dynamic segment of a
+    /// position-independent image loaded at `base`. Only relative
+    /// relocations are supported, there is no dynamic linker.
+    fn relocate(&mut self, elf: &xmas_elf::ElfFile, base: usize) -> Result<(), ElfLoadError> {
+        use xmas_elf::program::Type;
+        let data = elf.input;
+        let read_u64 = |at: usize| -> Result<u64, ElfLoadError> {
+            at.checked_add(8)
+                .and_then(|end| data.get(at..end))
+                .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+                .ok_or(ElfLoadError::BadRelocation)
+        };
+        // file offset of the link-time address range [vaddr, vaddr + len)
+        let file_offset = |vaddr: usize, len: usize| -> Result<usize, ElfLoadError> {
+            elf.program_iter()
+                .filter(|ph| ph.get_type() == Ok(Type::Load))
+                .find(|ph| {
+                    let start = ph.virtual_addr() as usize;
+                    vaddr >= start
+                        && vaddr
+                            .checked_add(len)
+                            .map_or(false, |end| end <= start + ph.file_size() as usize)
+                })
+                .map(|ph| ph.offset() as usize + vaddr - ph.virtual_addr() as usize)
+                .ok_or(ElfLoadError::BadRelocation)
+        };
+        let dynamic = match elf
+            .program_iter()
+            .find(|ph| ph.get_type() == Ok(Type::Dynamic))
+        {
+            Some(ph) => ph,
+            None => return Ok(()),
+        };
+        let (mut rela, mut rela_size, mut rela_ent) = (0, 0, RELA_ENTRY_SIZE);
+        let dynamic_start = dynamic.offset() as usize;
+        for i in 0..dynamic.file_size() as usize / DYN_ENTRY_SIZE {
+            let entry = dynamic_start + i * DYN_ENTRY_SIZE;
+            let (tag, value) = (read_u64(entry)?, read_u64(entry + 8)? as usize);
+            match tag {
+                DT_NULL => break,
+                DT_RELA => rela = value,
+                DT_RELASZ => rela_size = value,
+                DT_RELAENT => rela_ent = value,
+                DT_REL => return Err(ElfLoadError::BadRelocation),
+                _ => {}
+            }
+        }
+        if rela_size == 0 {
+            return Ok(());
+        }
+        if rela_ent != RELA_ENTRY_SIZE {
+            return Err(ElfLoadError::BadRelocation);
+        }
+        let table = file_offset(rela, rela_size)?;
+        for i in 0..rela_size / RELA_ENTRY_SIZE {
+            let entry = table + i * RELA_ENTRY_SIZE;
+            let offset = read_u64(entry)? as usize;
+            let info = read_u64(entry + 8)?;
+            let addend = read_u64(entry + 16)?;
+            match info & 0xffff_ffff {
+                R_RISCV_NONE => {}
+                R_RISCV_RELATIVE => {
+                    let target = base.wrapping_add(offset);
+                    if !self.write_user_u64(target, (base as u64).wrapping_add(addend)) {
+                        return Err(ElfLoadError::BadRelocation);
+                    }
+                }
+                _ => return Err(ElfLoadError::BadRelocation),
+            }
+        }
+        Ok(())
+    }
     /// Include sections in elf and trampoline and TrapContext and user stack,
     /// also returns user_sp and entry point.
-    pub fn from_elf(elf_data: &[u8]) -> (Self, usize, usize) {
+    /// `pid` is the task the image is loaded for, as reported by policy checks.
+    pub fn from_elf(elf_data: &[u8], pid: usize) -> Result<(Self, usize, usize), ElfLoadError> {
+        let elf_type = Self::check_elf_header(elf_data)?;
+        let elf = xmas_elf::ElfFile::new(elf_data).map_err(|_| ElfLoadError::Malformed)?;
         let mut memory_set = Self::new_bare();
         // map trampoline
         memory_set.map_trampoline();
         // map program headers of elf, with U flag
-        let elf = xmas_elf::ElfFile::new(elf_data).unwrap();
-        let elf_header = elf.header;
-        let magic = elf_header.pt1.magic;
-        assert_eq!(magic, [0x7f, 0x45, 0x4c, 0x46], "invalid elf!");
-        let ph_count = elf_header.pt2.ph_count();
+        let ph_count = elf.header.pt2.ph_count();
+        // position-independent images are loaded at a random base
+        let load_base = if elf_type == ET_DYN {
+            USER_PIE_BASE + random_offset(ASLR_PAGES)
+        } else {
+            0
+        };
+        let entrypoint = load_base.wrapping_add(elf.header.pt2.entry_point() as usize);
+        let mut entry_mapped = false;
         let mut max_end_vpn = VirtPageNum(0);
+        // segments stay below the guard page under the fully grown user stack
+        // at its lowest top, everything above up to `TRAP_CONTEXT` is taken
+        // or not canonical
+        let user_image_end =
+            USER_STACK_TOP - ASLR_PAGES * PAGE_SIZE - USER_STACK_RLIMIT - PAGE_SIZE;
         // parse elf
         for i in 0..ph_count {
-            let ph = elf.program_header(i).unwrap();
-            if ph.get_type().unwrap() == xmas_elf::program::Type::Load {
-                let start_va: VirtAddr = (ph.virtual_addr() as usize).into();
-                let end_va: VirtAddr = ((ph.virtual_addr() + ph.mem_size()) as usize).into();
-                let mut map_perm = MapPermission::U;
-                let ph_flags = ph.flags();
-                if ph_flags.is_read() {
-                    map_perm |= MapPermission::R;
-                }
-                if ph_flags.is_write() {
-                    map_perm |= MapPermission::W;
-                }
-                if ph_flags.is_execute() {
-                    map_perm |= MapPermission::X;
-                }
-                let map_area = MapArea::new(start_va, end_va, MapType::Framed, map_perm);
-                max_end_vpn = map_area.vpn_range.get_end();
-                memory_set.push(
-                    map_area,
-                    Some(&elf.input[ph.offset() as usize..(ph.offset() + ph.file_size()) as usize]),
-                );
+            let ph = elf
+                .program_header(i)
+                .map_err(|_| ElfLoadError::Malformed)?;
+            if ph.get_type().map_err(|_| ElfLoadError::Malformed)? != xmas_elf::program::Type::Load
+            {
+                continue;
+            }
+            let (offset, file_size) = (ph.offset() as usize, ph.file_size() as usize);
+            let (link_vaddr, mem_size) = (ph.virtual_addr() as usize, ph.mem_size() as usize);
+            let file_end = offset
+                .checked_add(file_size)
+                .filter(|end| *end <= elf_data.len())
+                .ok_or(ElfLoadError::SegmentOutOfFile)?;
+            if file_size > mem_size {
+                return Err(ElfLoadError::Malformed);
+            }
+            let align = ph.align() as usize;
+            if align > 1 && (!align.is_power_of_two() || link_vaddr % align != offset % align) {
+                return Err(ElfLoadError::SegmentMisaligned);
+            }
+            let vaddr = link_vaddr
+                .checked_add(load_base)
+                .ok_or(ElfLoadError::SegmentOutOfUserSpace)?;
+            let end = vaddr
+                .checked_add(mem_size)
+                .filter(|end| *end <= user_image_end)
+                .ok_or(ElfLoadError::SegmentOutOfUserSpace)?;
+            let start_va: VirtAddr = vaddr.into();
+            let end_va: VirtAddr = end.into();
+            if memory_set.overlaps(start_va.floor(), end_va.ceil()) {
+                return Err(ElfLoadError::SegmentOverlap);
             }
+            let mut map_perm = MapPermission::U;
+            let ph_flags = ph.flags();
+            if ph_flags.is_read() {
+                map_perm |= MapPermission::R;
+            }
+            if ph_flags.is_write() {
+                map_perm |= MapPermission::W;
+            }
+            if ph_flags.is_execute() {
+                map_perm |= MapPermission::X;
+                entry_mapped |= (vaddr..end).contains(&entrypoint);
+            }
+            if !check_wx(pid, start_va, map_perm, false) {
+                return Err(ElfLoadError::WxViolation);
+            }
+            let map_area = MapArea::new(start_va, end_va, MapType::Framed, map_perm);
+            max_end_vpn = max_end_vpn.max(map_area.vpn_range.get_end());
+            memory_set.push(map_area, None);
+            // the segment does not have to start at a page boundary
+            memory_set.areas.last_mut().unwrap().copy_data(
+                &mut memory_set.page_table,
+                &elf_data[offset..file_end],
+                start_va.page_offset(),
+            );
+        }
+        if !entry_mapped {
+            return Err(ElfLoadError::BadEntry);
         }
-        // map user stack with U flags
+        if elf_type == ET_DYN {
+            memory_set.relocate(&elf, load_base)?;
+        }
+        // the heap starts empty right after the last segment
         let max_end_va: VirtAddr = max_end_vpn.into();
-        let mut user_stack_bottom: usize = max_end_va.into();
-        // guard page
-        user_stack_bottom += PAGE_SIZE;
-        let user_stack_top = user_stack_bottom + USER_STACK_SIZE;
+        memory_set.heap_bottom = max_end_va.into();
+        memory_set.brk = memory_set.heap_bottom;
+        memory_set.push(
+            MapArea::new(
+                max_end_va,
+                max_end_va,
+                MapType::Framed,
+                MapPermission::R | MapPermission::W | MapPermission::U,
+            ),
+            None,
+        );
+        // map user stack with U flags near the top of user space, it grows on
+        // demand down to the rlimit, and the page below is left as guard page
+        let user_stack_top = USER_STACK_TOP - random_offset(ASLR_PAGES);
+        let user_stack_bottom = user_stack_top - USER_STACK_SIZE;
+        memory_set.stack_top = user_stack_top;
+        memory_set.stack_limit = user_stack_top - USER_STACK_RLIMIT;
         memory_set.push(
             MapArea::new(
                 user_stack_bottom.into(),
@@ -265,9 +641,16 @@ impl MemorySet {
             None,
         );
 
-        let entrypoint = elf.header.pt2.entry_point();
-        log::debug!("parse elf, entrypoint={}", entrypoint);
-        (memory_set, user_stack_top, entrypoint as usize)
+        memory_set.mmap_base = USER_MMAP_BASE + random_offset(ASLR_PAGES);
+
+        log::debug!(
+            "parse elf, entrypoint={:#x}, load_base={:#x}, user_stack_top={:#x}, mmap_base={:#x}",
+            entrypoint,
+            load_base,
+            user_stack_top,
+            memory_set.mmap_base
+        );
+        Ok((memory_set, user_stack_top, entrypoint))
     }
 
     pub fn activate(&self) {
@@ -280,14 +663,285 @@ impl MemorySet {
     pub fn translate(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
         self.page_table.translate(vpn)
     }
+    /// Note that a task is about to run in this address space on `hart`.
+    pub fn mark_active(&mut self, hart: usize) {
+        self.harts |= 1 << hart;
+    }
+    /// Flush the tlb entries of `vpn`, or of all pages without one, on this
+    /// hart and on every other hart the address space has been active on.
+    fn flush_tlb(&self, vpn: Option<VirtPageNum>) {
+        let (start, size) = match vpn {
+            Some(vpn) => (VirtAddr::from(vpn).0, PAGE_SIZE),
+            None => (0, 0),
+        };
+        unsafe {
+            match vpn {
+                Some(_) => core::arch::asm!("sfence.vma {}, zero", in(reg) start),
+                None => core::arch::asm!("sfence.vma"),
+            }
+        }
+        let others = self.harts & !(1 << hart_id());
+        if others != 0 {
+            // a stale translation on another hart could reach a freed frame
+            remote_sfence_vma(others, 0, start, size).expect("cannot flush the tlb of other harts");
+        }
+    }
+    /// Like `translate`, but brings a swapped out or not yet grown page in
+    /// first and returns `None` for pages which are not mapped at all or do
+    /// not allow a user access of `kind`. The kernel may write to the page
+    /// behind the mmu, so it counts as dirty.
+    pub fn translate_resident(
+        &mut self,
+        vpn: VirtPageNum,
+        kind: PageFaultKind,
+    ) -> Option<PageTableEntry> {
+        match self.page_table.translate(vpn) {
+            Some(pte) if pte.is_valid() && kind.allowed_by(pte.flags()) => {}
+            _ => self.handle_page_fault(vpn.into(), kind).ok()?,
+        }
+        let pte = self.page_table.translate(vpn)?;
+        if pte.is_valid() {
+            self.page_table.set_dirty(vpn);
+        }
+        Some(pte)
+    }
+    /// Try to resolve a page fault of a `kind` access at `va` by swapping
+    /// the page in or by growing the user stack.
+    pub fn handle_page_fault(
+        &mut self,
+        va: VirtAddr,
+        kind: PageFaultKind,
+    ) -> Result<(), PageFaultError> {
+        let vpn = va.floor();
+        if let Some(pte) = self.page_table.translate(vpn) {
+            // a swapped out entry keeps the permission of the page
+            if (pte.is_valid() || pte.is_swapped()) && !kind.allowed_by(pte.flags()) {
+                return Err(PageFaultError::PermissionDenied);
+            }
+            if pte.is_valid() {
+                // the tlb still has the entry from before it became valid
+                unsafe {
+                    core::arch::asm!("sfence.vma");
+                }
+                return Ok(());
+            }
+            if pte.is_swapped() {
+                return match self.swap_in(vpn) {
+                    true => Ok(()),
+                    false => Err(PageFaultError::OutOfMemory),
+                };
+            }
+        }
+        let guard_bottom = self.stack_limit - PAGE_SIZE;
+        if (guard_bottom..self.stack_limit).contains(&va.0) {
+            return Err(PageFaultError::StackOverflow);
+        }
+        if (self.stack_limit..self.stack_top).contains(&va.0) {
+            if kind == PageFaultKind::Fetch {
+                return Err(PageFaultError::PermissionDenied);
+            }
+            return self.grow_stack(vpn);
+        }
+        Err(PageFaultError::NotMapped)
+    }
+    /// Extend the stack area down to `vpn`.
+    fn grow_stack(&mut self, vpn: VirtPageNum) -> Result<(), PageFaultError> {
+        let stack_end = VirtAddr::from(self.stack_top).floor();
+        let idx = self
+            .areas
+            .iter()
+            .position(|area| area.vpn_range.get_end() == stack_end)
+            .expect("stack area is gone");
+        let old_start = self.areas[idx].vpn_range.get_start();
+        if vpn >= old_start {
+            return Err(PageFaultError::NotMapped);
+        }
+        if self.overlaps(vpn, old_start) {
+            return Err(PageFaultError::StackOverflow);
+        }
+        for new_vpn in VPNRange::new(vpn, old_start) {
+            let frame = self.alloc_frame().ok_or(PageFaultError::OutOfMemory)?;
+            self.areas[idx].map_one(&mut self.page_table, new_vpn, Some(Arc::new(frame)));
+            // keep the area consistent with what has been mapped so far
+            self.areas[idx].vpn_range = VPNRange::new(new_vpn, stack_end);
+        }
+        log::debug!("grow user stack to {:?}", VirtAddr::from(vpn));
+        Ok(())
+    }
+    pub fn brk(&self) -> usize {
+        self.brk
+    }
+    /// Move the program break to `new_brk`, growing or shrinking the heap area.
+    pub fn set_brk(&mut self, new_brk: usize) -> Result<usize, Errno> {
+        if new_brk < self.heap_bottom {
+            return Err(Errno::EINVAL);
+        }
+        // the heap has to end below the guard page of the stack
+        if new_brk > self.stack_limit - PAGE_SIZE {
+            return Err(Errno::ENOMEM);
+        }
+        let heap_start = VirtAddr::from(self.heap_bottom).floor();
+        let idx = self
+            .areas
+            .iter()
+            .position(|area| area.vpn_range.get_start() == heap_start)
+            .expect("heap area is gone");
+        let old_end = self.areas[idx].vpn_range.get_end();
+        let new_end = VirtAddr::from(new_brk).ceil();
+        if new_end > old_end {
+            if self.overlaps(old_end, new_end) {
+                return Err(Errno::ENOMEM);
+            }
+            for vpn in VPNRange::new(old_end, new_end) {
+                let frame = match self.alloc_frame() {
+                    Some(frame) => frame,
+                    None => {
+                        // roll back the pages mapped so far
+                        for mapped in VPNRange::new(old_end, vpn) {
+                            self.areas[idx].unmap_one(&mut self.page_table, mapped);
+                        }
+                        return Err(Errno::ENOMEM);
+                    }
+                };
+                self.areas[idx].map_one(&mut self.page_table, vpn, Some(Arc::new(frame)));
+            }
+        } else {
+            for vpn in VPNRange::new(new_end, old_end) {
+                self.areas[idx].unmap_one(&mut self.page_table, vpn);
+            }
+        }
+        self.areas[idx].vpn_range = VPNRange::new(heap_start, new_end);
+        self.brk = new_brk;
+        Ok(new_brk)
+    }
+    /// number of pages backed by a frame, swapped out pages excluded
+    pub fn resident_pages(&self) -> usize {
+        self.areas.iter().map(|area| area.data_frames.len()).sum()
+    }
+    pub fn swap_stat(&self) -> SwapStat {
+        self.swap_stat
+    }
+    /// Allocate a frame for this address space, swapping out its own cold
+    /// pages when physical memory runs low, or those of other tasks once it
+    /// has none left.
+    fn alloc_frame(&mut self) -> Option<FrameTracker> {
+        while frames_available() <= SWAP_WATERMARK {
+            if !self.swap_out_one() && !swap_out_ready_task() {
+                break;
+            }
+        }
+        frame_alloc()
+    }
+    /// The first swappable resident page at or after `hand`, wrapping around
+    /// past the last area.
+    fn clock_next(&self, (idx, vpn): (usize, usize)) -> Option<(usize, VirtPageNum)> {
+        let ahead = (idx..self.areas.len()).map(|i| (i, if i == idx { vpn } else { 0 }));
+        let behind = (0..=idx.min(self.areas.len())).map(|i| (i, 0));
+        ahead.chain(behind).find_map(|(i, from)| {
+            let area = self.areas.get(i).filter(|area| area.swappable())?;
+            let (vpn, _) = area.data_frames.range(VirtPageNum(from)..).next()?;
+            Some((i, *vpn))
+        })
+    }
+    /// Pick a victim with the clock (second-chance) algorithm driven by the
+    /// accessed bit and write it out to the swap area. A clean page which
+    /// still has a copy in the swap area is not written again.
+    pub fn swap_out_one(&mut self) -> bool {
+        if !swap::swap_enabled() {
+            return false;
+        }
+        let resident: usize = self
+            .areas
+            .iter()
+            .filter(|area| area.swappable())
+            .map(|area| area.data_frames.len())
+            .sum();
+        // the second round is guaranteed to find a page whose accessed bit was cleared
+        let mut hand = self.clock_hand;
+        let mut victim = None;
+        for _ in 0..resident * 2 {
+            let (idx, vpn) = match self.clock_next(hand) {
+                Some(page) => page,
+                None => break,
+            };
+            hand = (idx, vpn.0 + 1);
+            if !self.page_table.test_and_clear_accessed(vpn) {
+                victim = Some((idx, vpn));
+                break;
+            }
+        }
+        // cleared accessed bits only count once no tlb has them cached
+        if resident > 0 {
+            self.flush_tlb(None);
+        }
+        let (idx, vpn) = match victim {
+            Some(victim) => victim,
+            None => return false,
+        };
+        let (slot, fresh) = match self.areas[idx].swap_copies.remove(&vpn) {
+            Some(slot) => (slot, false),
+            None => match swap::alloc_slot() {
+                Some(slot) => (slot, true),
+                None => return false,
+            },
+        };
+        // take the page away first, so that no hart writes to it while it
+        // is written out
+        let pte = self.page_table.invalidate(vpn);
+        self.flush_tlb(Some(vpn));
+        let dirty = pte.dirty();
+        if fresh || dirty {
+            swap::swap_write(&slot, pte.ppn());
+        }
+        log::debug!("swap out {:?} to {:?}, dirty: {}", vpn, slot, dirty);
+        self.page_table.swap_out(vpn, slot.slot);
+        let area = &mut self.areas[idx];
+        area.data_frames.remove(&vpn);
+        area.swapped.insert(vpn, slot);
+        self.clock_hand = hand;
+        self.swap_stat.swap_outs += 1;
+        true
+    }
+    /// Read a swapped out page back into a new frame.
+    fn swap_in(&mut self, vpn: VirtPageNum) -> bool {
+        let idx = match self
+            .areas
+            .iter()
+            .position(|area| area.swapped.contains_key(&vpn))
+        {
+            Some(idx) => idx,
+            None => return false,
+        };
+        let frame = match self.alloc_frame() {
+            Some(frame) => frame,
+            None => return false,
+        };
+        let area = &mut self.areas[idx];
+        let slot = area.swapped.remove(&vpn).unwrap();
+        log::debug!("swap in {:?} from {:?}", vpn, slot);
+        swap::swap_in(&slot, frame.ppn);
+        area.map_one(&mut self.page_table, vpn, Some(Arc::new(frame)));
+        // the page is mapped clean, the slot stays valid until it is written
+        area.swap_copies.insert(vpn, slot);
+        self.swap_stat.swap_ins += 1;
+        true
+    }
 }
 
 /// map area structure, controls a contiguous piece of virtual memory
 pub struct MapArea {
     vpn_range: VPNRange,
-    data_frames: BTreeMap<VirtPageNum, FrameTracker>,
+    data_frames: BTreeMap<VirtPageNum, Arc<FrameTracker>>,
+    swapped: BTreeMap<VirtPageNum, SwapSlot>,
+    /// slots still holding the content of resident pages which came back
+    /// from the swap area, valid as long as the page is clean
+    swap_copies: BTreeMap<VirtPageNum, SwapSlot>,
+    /// shared memory segment this area is attached to
+    shm_id: Option<usize>,
     map_type: MapType,
     map_perm: MapPermission,
+    /// whether the area has ever been writable, it can never become executable then
+    was_writable: bool,
 }
 
 impl MapArea {
@@ -302,26 +956,55 @@ impl MapArea {
         Self {
             vpn_range: VPNRange::new(start_vpn, end_vpn),
             data_frames: BTreeMap::new(),
+            swapped: BTreeMap::new(),
+            swap_copies: BTreeMap::new(),
+            shm_id: None,
             map_type,
             map_perm,
+            was_writable: map_perm.contains(MapPermission::W),
         }
     }
     pub fn from_another(another: &MapArea) -> Self {
         Self {
             vpn_range: VPNRange::new(another.vpn_range.get_start(), another.vpn_range.get_end()),
             data_frames: BTreeMap::new(),
+            swapped: BTreeMap::new(),
+            swap_copies: BTreeMap::new(),
+            shm_id: another.shm_id,
             map_type: another.map_type,
             map_perm: another.map_perm,
+            was_writable: another.was_writable,
         }
     }
-    pub fn map_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
+    /// Split the area at `at`, keeping the lower part and returning the
+    /// upper one, or `None` if `at` is not inside the area.
+    fn split_off(&mut self, at: VirtPageNum) -> Option<MapArea> {
+        let (start, end) = (self.vpn_range.get_start(), self.vpn_range.get_end());
+        if at <= start || at >= end {
+            return None;
+        }
+        let mut upper = MapArea::from_another(self);
+        upper.vpn_range = VPNRange::new(at, end);
+        upper.data_frames = self.data_frames.split_off(&at);
+        upper.swapped = self.swapped.split_off(&at);
+        upper.swap_copies = self.swap_copies.split_off(&at);
+        self.vpn_range = VPNRange::new(start, at);
+        Some(upper)
+    }
+    /// `frame` is required by framed areas and ignored by identical ones.
+    pub fn map_one(
+        &mut self,
+        page_table: &mut PageTable,
+        vpn: VirtPageNum,
+        frame: Option<Arc<FrameTracker>>,
+    ) {
         let ppn: PhysPageNum;
         match self.map_type {
             MapType::Identical => {
                 ppn = PhysPageNum(vpn.0);
             }
             MapType::Framed => {
-                let frame = frame_alloc().unwrap();
+                let frame = frame.unwrap();
                 ppn = frame.ppn;
                 self.data_frames.insert(vpn, frame);
             }
@@ -335,47 +1018,140 @@ impl MapArea {
         match self.map_type {
             MapType::Framed => {
                 self.data_frames.remove(&vpn);
+                self.swapped.remove(&vpn);
+                self.swap_copies.remove(&vpn);
             }
             _ => {}
         }
         page_table.unmap(vpn);
     }
-    pub fn map(&mut self, page_table: &mut PageTable) {
-        for vpn in self.vpn_range {
-            self.map_one(page_table, vpn);
-        }
-    }
     #[allow(unused)]
     pub fn unmap(&mut self, page_table: &mut PageTable) {
         for vpn in self.vpn_range {
             self.unmap_one(page_table, vpn);
         }
     }
+    /// Map the frames of a shared memory segment, one for each page.
+    fn map_shared(&mut self, page_table: &mut PageTable, frames: Vec<Arc<FrameTracker>>) {
+        for (vpn, frame) in self.vpn_range.into_iter().zip(frames.into_iter()) {
+            self.map_one(page_table, vpn, Some(frame));
+        }
+    }
+    /// only private user pages can be swapped out
+    fn swappable(&self) -> bool {
+        self.map_type == MapType::Framed
+            && self.map_perm.contains(MapPermission::U)
+            && self.shm_id.is_none()
+    }
     /// data: start-aligned but maybe with shorter length
     /// assume that all frames were cleared before
-    pub fn copy_data(&mut self, page_table: &mut PageTable, data: &[u8]) {
+    /// `offset` is where the data starts in the first page
+    pub fn copy_data(&mut self, page_table: &mut PageTable, data: &[u8], offset: usize) {
         assert_eq!(self.map_type, MapType::Framed);
         let mut start: usize = 0;
+        let mut page_offset = offset;
         let mut current_vpn = self.vpn_range.get_start();
         let len = data.len();
-        loop {
-            let src = &data[start..len.min(start + PAGE_SIZE)];
+        while start < len {
+            let src = &data[start..len.min(start + PAGE_SIZE - page_offset)];
             let dst = &mut page_table
                 .translate(current_vpn)
                 .unwrap()
                 .ppn()
-                .get_bytes_array()[..src.len()];
-
+                .get_bytes_array()[page_offset..page_offset + src.len()];
             dst.copy_from_slice(src);
-            start += PAGE_SIZE;
-            if start >= len {
-                break;
-            }
+            start += src.len();
+            page_offset = 0;
             current_vpn.step();
         }
     }
 }
 
+impl Drop for MapArea {
+    fn drop(&mut self) {
+        if let Some(id) = self.shm_id {
+            shm::shm_detach(id);
+        }
+    }
+}
+
+const ELF64_HEADER_SIZE: usize = 64;
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const ET_EXEC: u16 = 2;
+const ET_DYN: u16 = 3;
+const DYN_ENTRY_SIZE: usize = 16;
+const DT_NULL: u64 = 0;
+const DT_RELA: u64 = 7;
+const DT_RELASZ: u64 = 8;
+const DT_RELAENT: u64 = 9;
+const DT_REL: u64 = 17;
+const RELA_ENTRY_SIZE: usize = 24;
+const R_RISCV_NONE: u64 = 0;
+const R_RISCV_RELATIVE: u64 = 3;
+const EM_RISCV: u16 = 243;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+/// reasons why an elf image cannot be loaded
+pub enum ElfLoadError {
+    /// no elf magic, or shorter than an elf header
+    NotElf,
+    /// not a 64-bit image
+    BadClass,
+    /// not little endian
+    BadEndian,
+    /// neither an executable nor a position-independent executable
+    BadType,
+    /// not built for RISC-V
+    BadMachine,
+    /// headers which cannot be parsed or are inconsistent
+    Malformed,
+    /// segment content beyond the end of the file
+    SegmentOutOfFile,
+    /// segment outside user space, or in the way of the user stack
+    SegmentOutOfUserSpace,
+    /// segment sharing pages with another one
+    SegmentOverlap,
+    /// segment violating its own alignment
+    SegmentMisaligned,
+    /// entry point outside every executable segment
+    BadEntry,
+    /// relocation other than a relative one, or pointing outside the image
+    BadRelocation,
+    /// writable and executable segment refused by the mapping policy
+    WxViolation,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+/// the access which caused a page fault
+pub enum PageFaultKind {
+    Load,
+    Store,
+    Fetch,
+}
+
+impl PageFaultKind {
+    /// whether an entry with `flags` allows this access from user mode
+    fn allowed_by(self, flags: PTEFlags) -> bool {
+        let needed = match self {
+            PageFaultKind::Load => PTEFlags::R,
+            PageFaultKind::Store => PTEFlags::W,
+            PageFaultKind::Fetch => PTEFlags::X,
+        };
+        flags.contains(needed | PTEFlags::U)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+/// reasons why a page fault cannot be resolved
+pub enum PageFaultError {
+    NotMapped,
+    StackOverflow,
+    OutOfMemory,
+    /// the page is mapped but does not allow the access
+    PermissionDenied,
+}
+
 #[derive(Copy, Clone, PartialEq, Debug)]
 /// map type for memory set: identical or framed
 pub enum MapType {
@@ -7,22 +7,45 @@
 //! Every task or process has a memory_set to control its virtual memory.
 
 mod address;
+mod aslr;
 mod frame_allocator;
 mod heap_allocator;
 mod memory_set;
 mod page_table;
+mod policy;
+mod shm;
+mod slab;
+mod swap;
 
 pub use address::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
 use address::{StepByOne, VPNRange};
-pub use frame_allocator::{frame_alloc, FrameTracker};
+pub use frame_allocator::{frame_alloc, frame_alloc_contiguous, frame_dealloc, FrameTracker};
+pub use heap_allocator::{heap_stats, HeapStats};
 pub use memory_set::remap_test;
-pub use memory_set::{MapPermission, MemorySet, KERNEL_SPACE};
+pub use memory_set::{
+    ElfLoadError, MapPermission, MemorySet, PageFaultError, PageFaultKind, KERNEL_SPACE,
+};
 pub use page_table::{translated_byte_buffer, PageTableEntry};
+pub use policy::{check_wx, set_wx_policy, WxPolicy};
+pub use shm::{shm_get, SHM_RDONLY};
+pub use slab::{kmem_cache_create, slab_stats, slab_test, SlabCache};
+pub use swap::init_swap;
+pub use swap::SwapStat;
 use page_table::{PTEFlags, PageTable};
 
-/// initiate heap allocator, frame allocator and kernel space
-pub fn init() {
+/// initiate the heap allocator, which works without frames until it grows
+pub fn init_heap() {
     heap_allocator::init_heap();
+}
+
+/// initiate frame allocator and kernel space, after the device tree is read
+pub fn init() {
     frame_allocator::init_frame_allocator();
+    slab::init();
+    KERNEL_SPACE.lock().activate();
+}
+
+/// switch a secondary hart to the kernel space set up by the boot hart
+pub fn init_hart() {
     KERNEL_SPACE.lock().activate();
 }
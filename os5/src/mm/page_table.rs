@@ -4,6 +4,7 @@ use super::{frame_alloc, FrameTracker, PhysPageNum, StepByOne, VirtAddr, VirtPag
 use alloc::vec;
 use alloc::vec::Vec;
 use bitflags::*;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 bitflags! {
     /// page table entry flags
@@ -19,6 +20,10 @@ bitflags! {
     }
 }
 
+/// RSW bit marking a non-present entry whose page has been swapped out,
+/// the ppn field of such an entry holds the swap slot instead.
+const PTE_SWAPPED: usize = 1 << 8;
+
 #[derive(Copy, Clone)]
 #[repr(C)]
 /// page table entry structure
@@ -27,6 +32,11 @@ pub struct PageTableEntry {
 }
 
 impl PageTableEntry {
+    /// The entry as an atomic, for changes which must not lose the
+    /// accessed and dirty bits the mmu of another hart sets meanwhile.
+    fn as_atomic(&mut self) -> &AtomicUsize {
+        unsafe { &*(&mut self.bits as *mut usize as *const AtomicUsize) }
+    }
     pub fn new(ppn: PhysPageNum, flags: PTEFlags) -> Self {
         PageTableEntry {
             bits: ppn.0 << 10 | flags.bits as usize,
@@ -35,6 +45,12 @@ impl PageTableEntry {
     pub fn empty() -> Self {
         PageTableEntry { bits: 0 }
     }
+    /// a non-present entry remembering the swap slot and the original flags
+    pub fn new_swapped(slot: usize, flags: PTEFlags) -> Self {
+        PageTableEntry {
+            bits: slot << 10 | PTE_SWAPPED | (flags - PTEFlags::V).bits as usize,
+        }
+    }
     pub fn ppn(&self) -> PhysPageNum {
         (self.bits >> 10 & ((1usize << 44) - 1)).into()
     }
@@ -53,6 +69,18 @@ impl PageTableEntry {
     pub fn executable(&self) -> bool {
         (self.flags() & PTEFlags::X) != PTEFlags::empty()
     }
+    pub fn accessed(&self) -> bool {
+        (self.flags() & PTEFlags::A) != PTEFlags::empty()
+    }
+    pub fn dirty(&self) -> bool {
+        (self.flags() & PTEFlags::D) != PTEFlags::empty()
+    }
+    pub fn is_swapped(&self) -> bool {
+        !self.is_valid() && self.bits & PTE_SWAPPED != 0
+    }
+    pub fn swap_slot(&self) -> usize {
+        self.bits >> 10
+    }
 }
 
 /// page table structure
@@ -124,9 +152,62 @@ impl PageTable {
     #[allow(unused)]
     pub fn unmap(&mut self, vpn: VirtPageNum) {
         let pte = self.find_pte_create(vpn).unwrap();
-        assert!(pte.is_valid(), "vpn {:?} is invalid before unmapping", vpn);
+        assert!(
+            pte.is_valid() || pte.is_swapped(),
+            "vpn {:?} is invalid before unmapping",
+            vpn
+        );
         *pte = PageTableEntry::empty();
     }
+    /// Clear the valid bit of a present entry, returns the entry as it was
+    /// with all accessed and dirty bits set up to then.
+    pub fn invalidate(&mut self, vpn: VirtPageNum) -> PageTableEntry {
+        let pte = self.find_pte_create(vpn).unwrap();
+        assert!(
+            pte.is_valid(),
+            "vpn {:?} is invalid before invalidating",
+            vpn
+        );
+        let bits = pte
+            .as_atomic()
+            .fetch_and(!(PTEFlags::V.bits as usize), Ordering::AcqRel);
+        PageTableEntry { bits }
+    }
+    /// Replace an entry cleared by `invalidate` with a swapped one pointing
+    /// to `slot`.
+    pub fn swap_out(&mut self, vpn: VirtPageNum, slot: usize) {
+        let pte = self.find_pte_create(vpn).unwrap();
+        assert!(
+            !pte.is_valid() && !pte.is_swapped() && pte.bits != 0,
+            "vpn {:?} is not invalidated before swapping out",
+            vpn
+        );
+        *pte = PageTableEntry::new_swapped(slot, pte.flags());
+    }
+    /// Replace the permission of a present entry, keeping its accessed and dirty bits.
+    pub fn set_flags(&mut self, vpn: VirtPageNum, flags: PTEFlags) {
+        let pte = self.find_pte_create(vpn).unwrap();
+        assert!(pte.is_valid(), "vpn {:?} is invalid before protecting", vpn);
+        let kept = pte.flags() & (PTEFlags::V | PTEFlags::A | PTEFlags::D);
+        *pte = PageTableEntry::new(pte.ppn(), flags | kept);
+    }
+    /// Clear the accessed bit of a present entry, returns the old value.
+    pub fn test_and_clear_accessed(&mut self, vpn: VirtPageNum) -> bool {
+        let pte = self.find_pte_create(vpn).unwrap();
+        if !pte.is_valid() {
+            return false;
+        }
+        let old = pte
+            .as_atomic()
+            .fetch_and(!(PTEFlags::A.bits as usize), Ordering::AcqRel);
+        PageTableEntry { bits: old }.accessed()
+    }
+    /// Set the dirty bit of a present entry, for writes which bypass the mmu.
+    pub fn set_dirty(&mut self, vpn: VirtPageNum) {
+        let pte = self.find_pte_create(vpn).unwrap();
+        assert!(pte.is_valid(), "vpn {:?} is invalid before writing", vpn);
+        *pte = PageTableEntry::new(pte.ppn(), pte.flags() | PTEFlags::D);
+    }
     pub fn translate(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
         self.find_pte(vpn).copied()
     }
@@ -0,0 +1,54 @@
+//! Policy for the permissions of user mappings.
+//!
+//! A user page must never be writable and executable at the same time
+//! (W^X), and a page which has once been writable must not become
+//! executable later. Violations are always logged with the pid and the
+//! address, whether they are refused depends on the [`WxPolicy`].
+
+use super::{MapPermission, VirtAddr};
+use spin::Mutex;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum WxPolicy {
+    /// refuse violating mappings
+    Enforce,
+    /// log violations but allow them
+    Warn,
+    /// allow everything silently
+    Off,
+}
+
+static WX_POLICY: Mutex<WxPolicy> = Mutex::new(WxPolicy::Enforce);
+
+pub fn set_wx_policy(policy: WxPolicy) {
+    *WX_POLICY.lock() = policy;
+}
+
+pub fn wx_policy() -> WxPolicy {
+    *WX_POLICY.lock()
+}
+
+/// Check whether task `pid` may map the page at `va` with `perm`,
+/// `was_writable` tells whether the page has been writable before.
+pub fn check_wx(pid: usize, va: VirtAddr, perm: MapPermission, was_writable: bool) -> bool {
+    let policy = wx_policy();
+    if policy == WxPolicy::Off || !perm.contains(MapPermission::X) {
+        return true;
+    }
+    let violation = if perm.contains(MapPermission::W) {
+        "writable and executable"
+    } else if was_writable {
+        "executable after being writable"
+    } else {
+        return true;
+    };
+    let allowed = policy == WxPolicy::Warn;
+    log::warn!(
+        "[W^X] task_{}, mapping at {:#x} would be {}, {}",
+        pid,
+        va.0,
+        violation,
+        if allowed { "allowed" } else { "refused" }
+    );
+    allowed
+}
@@ -0,0 +1,112 @@
+//! Implementation of System V style shared memory segments.
+//!
+//! A segment owns reference-counted frames, every [`super::MapArea`]
+//! attached to it holds another reference to them. The segment is removed
+//! once its last mapper detaches, the frames are freed when the last
+//! reference goes away.
+
+use super::{frame_alloc, FrameTracker};
+use crate::config::PAGE_SIZE;
+use crate::errno::Errno;
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+use lazy_static::*;
+use spin::Mutex;
+
+/// key which always creates a new segment
+pub const IPC_PRIVATE: usize = 0;
+/// create the segment if the key does not exist
+pub const IPC_CREAT: usize = 0o1000;
+/// attach the segment read-only
+pub const SHM_RDONLY: usize = 0o10000;
+
+struct ShmSegment {
+    key: usize,
+    frames: Vec<Arc<FrameTracker>>,
+    /// number of attached map areas
+    nattch: usize,
+}
+
+struct ShmManager {
+    segments: BTreeMap<usize, ShmSegment>,
+    next_id: usize,
+}
+
+impl ShmManager {
+    fn new() -> Self {
+        Self {
+            segments: BTreeMap::new(),
+            next_id: 1,
+        }
+    }
+    fn create(&mut self, key: usize, size: usize) -> Option<usize> {
+        let pages = (size + PAGE_SIZE - 1) / PAGE_SIZE;
+        let mut frames = Vec::with_capacity(pages);
+        for _ in 0..pages {
+            frames.push(Arc::new(frame_alloc()?));
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        self.segments.insert(
+            id,
+            ShmSegment {
+                key,
+                frames,
+                nattch: 0,
+            },
+        );
+        Some(id)
+    }
+}
+
+lazy_static! {
+    static ref SHM_MANAGER: Mutex<ShmManager> = Mutex::new(ShmManager::new());
+}
+
+/// look up the segment of `key`, or create one with `size` bytes
+pub fn shm_get(key: usize, size: usize, flags: usize) -> Result<usize, Errno> {
+    if size == 0 {
+        return Err(Errno::EINVAL);
+    }
+    let mut manager = SHM_MANAGER.lock();
+    if key != IPC_PRIVATE {
+        if let Some((id, segment)) = manager.segments.iter().find(|(_, seg)| seg.key == key) {
+            return if segment.frames.len() * PAGE_SIZE >= size {
+                Ok(*id)
+            } else {
+                Err(Errno::EINVAL)
+            };
+        }
+        if flags & IPC_CREAT == 0 {
+            return Err(Errno::ENOENT);
+        }
+    }
+    manager.create(key, size).ok_or(Errno::ENOMEM)
+}
+
+/// size of segment `id` in bytes
+pub fn shm_size(id: usize) -> Option<usize> {
+    let manager = SHM_MANAGER.lock();
+    Some(manager.segments.get(&id)?.frames.len() * PAGE_SIZE)
+}
+
+/// take a new reference to the frames of segment `id`
+pub fn shm_attach(id: usize) -> Option<Vec<Arc<FrameTracker>>> {
+    let mut manager = SHM_MANAGER.lock();
+    let segment = manager.segments.get_mut(&id)?;
+    segment.nattch += 1;
+    Some(segment.frames.clone())
+}
+
+/// drop a reference taken by `shm_attach`, removing the segment with the last one
+pub fn shm_detach(id: usize) {
+    let mut manager = SHM_MANAGER.lock();
+    let segment = manager
+        .segments
+        .get_mut(&id)
+        .expect("detach from a removed segment");
+    segment.nattch -= 1;
+    if segment.nattch == 0 {
+        log::info!("shm segment {} removed, key={}", id, segment.key);
+        manager.segments.remove(&id);
+    }
+}
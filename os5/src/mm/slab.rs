@@ -0,0 +1,498 @@
+//! Implementation of slab caches for fixed-size kernel objects.
+//!
+//! A cache carves objects of a single layout out of slabs, runs of
+//! contiguous frames taken from the frame allocator. Free objects are
+//! linked through their first word, in a list per slab. Objects only come
+//! from a cache through its typed handle, a [`SlabCache`], so that objects
+//! like `Arc<Task>` stay packed together instead of fragmenting the buddy
+//! heap, while other allocations of the same size stay out of the cache.
+//!
+//! Every frame of a slab is noted in a map, which leads the global
+//! allocator from a freed pointer to its slab. A slab which becomes empty
+//! goes back to the frame allocator if its cache has another empty one.
+
+use super::frame_allocator::{frame_alloc_contiguous, frame_dealloc, managed_frames};
+use super::{PhysAddr, PhysPageNum};
+use crate::{
+    config::{MAX_HARTS, PAGE_SIZE},
+    smp::hart_id,
+};
+use alloc::{sync::Arc, vec::Vec};
+use core::{
+    alloc::Layout,
+    marker::PhantomData,
+    mem::{align_of, size_of},
+    ptr::NonNull,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use spin::Mutex;
+
+const MAX_CACHES: usize = 16;
+/// a slab is made large enough to hold this many objects
+const OBJECTS_PER_SLAB: usize = 8;
+/// empty slabs a cache keeps for the next allocations
+const MAX_EMPTY_SLABS: usize = 1;
+/// the cache id + 1 is kept above the index of the frame in its slab
+const OWNER_SHIFT: u32 = 24;
+
+/// statistics of a slab cache
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SlabStats {
+    /// size of an object in bytes
+    pub obj_size: usize,
+    /// number of slabs
+    pub slabs: usize,
+    /// objects carved out of all slabs
+    pub total: usize,
+    /// objects handed out and not freed yet
+    pub in_use: usize,
+    pub allocs: usize,
+    pub frees: usize,
+}
+
+/// kept after the objects of each slab, links the slabs of a cache
+struct SlabTail {
+    prev: usize,
+    next: usize,
+    start: usize,
+    /// address of the first free object of the slab, 0 if it is full
+    free: usize,
+    in_use: usize,
+}
+
+/// # Safety
+///
+/// `tail` is the address of the tail of a live slab, and no other
+/// reference to it is alive.
+unsafe fn slab_at<'a>(tail: usize) -> &'a mut SlabTail {
+    &mut *(tail as *mut SlabTail)
+}
+
+struct KmemCache {
+    name: &'static str,
+    /// layout of the objects, set by the first allocation
+    layout: Option<Layout>,
+    obj_size: usize,
+    slab_pages: usize,
+    per_slab: usize,
+    /// run on every object before it is handed out
+    ctor: Option<fn(*mut u8)>,
+    /// tails of the first and the last slab, the slabs with free objects
+    /// come before the full ones
+    head: usize,
+    tail: usize,
+    /// slabs without objects in use
+    empty: usize,
+    stats: SlabStats,
+}
+
+impl KmemCache {
+    fn new(name: &'static str, ctor: Option<fn(*mut u8)>) -> Self {
+        Self {
+            name,
+            layout: None,
+            obj_size: 0,
+            slab_pages: 0,
+            per_slab: 0,
+            ctor,
+            head: 0,
+            tail: 0,
+            empty: 0,
+            stats: SlabStats::default(),
+        }
+    }
+    /// Fix the layout of the objects, false if slabs cannot hold it.
+    fn set_layout(&mut self, layout: Layout) -> bool {
+        if layout.align() > PAGE_SIZE {
+            return false;
+        }
+        let align = layout.align().max(align_of::<usize>());
+        let obj_size = (layout.size().max(size_of::<usize>()) + align - 1) / align * align;
+        let slab_bytes = obj_size * OBJECTS_PER_SLAB + size_of::<SlabTail>();
+        self.slab_pages = (slab_bytes + PAGE_SIZE - 1) / PAGE_SIZE;
+        self.per_slab = (self.slab_pages * PAGE_SIZE - size_of::<SlabTail>()) / obj_size;
+        self.obj_size = obj_size;
+        self.layout = Some(layout);
+        self.stats.obj_size = obj_size;
+        true
+    }
+    fn tail_of(&self, start: usize) -> usize {
+        start + self.slab_pages * PAGE_SIZE - size_of::<SlabTail>()
+    }
+    fn unlink(&mut self, tail: usize) {
+        let (prev, next) = {
+            let slab = unsafe { slab_at(tail) };
+            (slab.prev, slab.next)
+        };
+        match prev {
+            0 => self.head = next,
+            prev => unsafe { slab_at(prev) }.next = next,
+        }
+        match next {
+            0 => self.tail = prev,
+            next => unsafe { slab_at(next) }.prev = prev,
+        }
+    }
+    fn push_front(&mut self, tail: usize) {
+        {
+            let slab = unsafe { slab_at(tail) };
+            slab.prev = 0;
+            slab.next = self.head;
+        }
+        match self.head {
+            0 => self.tail = tail,
+            head => unsafe { slab_at(head) }.prev = tail,
+        }
+        self.head = tail;
+    }
+    fn push_back(&mut self, tail: usize) {
+        {
+            let slab = unsafe { slab_at(tail) };
+            slab.prev = self.tail;
+            slab.next = 0;
+        }
+        match self.tail {
+            0 => self.head = tail,
+            last => unsafe { slab_at(last) }.next = tail,
+        }
+        self.tail = tail;
+    }
+    /// take a new slab from the frame allocator and put it in front
+    fn grow(&mut self, id: usize, owners: &mut Owners) -> bool {
+        if owners.len == 0 {
+            return false;
+        }
+        let ppn = match frame_alloc_contiguous(self.slab_pages) {
+            Some(ppn) => ppn,
+            None => return false,
+        };
+        // physical memory is identically mapped in kernel space
+        let start = PhysAddr::from(ppn).0;
+        let mut free = 0;
+        for i in (0..self.per_slab).rev() {
+            let obj = start + i * self.obj_size;
+            unsafe {
+                *(obj as *mut usize) = free;
+            }
+            free = obj;
+        }
+        let tail = self.tail_of(start);
+        unsafe {
+            (tail as *mut SlabTail).write(SlabTail {
+                prev: 0,
+                next: 0,
+                start,
+                free,
+                in_use: 0,
+            });
+        }
+        for page in 0..self.slab_pages {
+            owners.set(ppn.0 + page, ((id + 1) << OWNER_SHIFT | page) as u32);
+        }
+        self.push_front(tail);
+        self.empty += 1;
+        self.stats.slabs += 1;
+        self.stats.total += self.per_slab;
+        true
+    }
+    fn alloc(&mut self, id: usize, layout: &Layout, owners: &mut Owners) -> Option<*mut u8> {
+        if self.layout.is_none() && !self.set_layout(*layout) {
+            return None;
+        }
+        if self.layout != Some(*layout) {
+            return None;
+        }
+        let has_free = self.head != 0 && unsafe { slab_at(self.head) }.free != 0;
+        if !has_free && !self.grow(id, owners) {
+            return None;
+        }
+        let tail = self.head;
+        let (obj, was_empty, full) = {
+            let slab = unsafe { slab_at(tail) };
+            let obj = slab.free;
+            slab.free = unsafe { *(obj as *const usize) };
+            slab.in_use += 1;
+            (obj, slab.in_use == 1, slab.free == 0)
+        };
+        if was_empty {
+            self.empty -= 1;
+        }
+        if full {
+            self.unlink(tail);
+            self.push_back(tail);
+        }
+        self.stats.in_use += 1;
+        self.stats.allocs += 1;
+        if let Some(ctor) = self.ctor {
+            ctor(obj as *mut u8);
+        }
+        Some(obj as *mut u8)
+    }
+    /// give `obj` back to the slab starting at frame `first`
+    fn dealloc(&mut self, obj: usize, first: PhysPageNum, owners: &mut Owners) {
+        let tail = self.tail_of(PhysAddr::from(first).0);
+        let (was_full, empty) = {
+            let slab = unsafe { slab_at(tail) };
+            let was_full = slab.free == 0;
+            unsafe {
+                *(obj as *mut usize) = slab.free;
+            }
+            slab.free = obj;
+            slab.in_use -= 1;
+            (was_full, slab.in_use == 0)
+        };
+        self.stats.in_use -= 1;
+        self.stats.frees += 1;
+        if was_full {
+            self.unlink(tail);
+            self.push_front(tail);
+        }
+        if empty {
+            if self.empty >= MAX_EMPTY_SLABS {
+                self.release(tail, owners);
+            } else {
+                self.empty += 1;
+            }
+        }
+    }
+    /// give an empty slab back to the frame allocator
+    fn release(&mut self, tail: usize, owners: &mut Owners) {
+        self.unlink(tail);
+        let first = PhysAddr::from(unsafe { slab_at(tail) }.start).floor();
+        for page in 0..self.slab_pages {
+            owners.set(first.0 + page, 0);
+            frame_dealloc(PhysPageNum(first.0 + page));
+        }
+        self.stats.slabs -= 1;
+        self.stats.total -= self.per_slab;
+    }
+}
+
+/// For every frame of the frame allocator 0, or if it belongs to a slab the
+/// id + 1 of its cache above `OWNER_SHIFT` and its index in the slab below.
+struct Owners {
+    first: usize,
+    len: usize,
+    /// address of `len` entries
+    map: usize,
+}
+
+impl Owners {
+    fn get(&self, ppn: usize) -> u32 {
+        match ppn.wrapping_sub(self.first) {
+            i if i < self.len => unsafe { *(self.map as *const u32).add(i) },
+            _ => 0,
+        }
+    }
+    fn set(&mut self, ppn: usize, owner: u32) {
+        let i = ppn - self.first;
+        assert!(i < self.len, "frame {:#x} is not managed", ppn);
+        unsafe { *(self.map as *mut u32).add(i) = owner };
+    }
+}
+
+struct Registry {
+    caches: [Option<KmemCache>; MAX_CACHES],
+    owners: Owners,
+}
+
+/// The registry lives in a fixed array, it is consulted by the global
+/// allocator and must not allocate itself.
+static REGISTRY: Mutex<Registry> = {
+    const NONE: Option<KmemCache> = None;
+    Mutex::new(Registry {
+        caches: [NONE; MAX_CACHES],
+        owners: Owners {
+            first: 0,
+            len: 0,
+            map: 0,
+        },
+    })
+};
+
+#[allow(clippy::declare_interior_mutable_const)]
+const UNSELECTED: AtomicUsize = AtomicUsize::new(0);
+
+/// the cache id + 1 whose handle the next allocation of each hart comes
+/// through, 0 for none
+static SELECTED: [AtomicUsize; MAX_HARTS] = [UNSELECTED; MAX_HARTS];
+
+/// Set up the map of slab frames once the frame allocator is ready, caches
+/// cannot grow before.
+pub fn init() {
+    let frames = managed_frames();
+    let len = frames.end - frames.start;
+    let pages = (len * size_of::<u32>() + PAGE_SIZE - 1) / PAGE_SIZE;
+    let ppn = frame_alloc_contiguous(pages).expect("no frames for the slab map");
+    let map = PhysAddr::from(ppn).0;
+    unsafe { core::ptr::write_bytes(map as *mut u32, 0, len) };
+    REGISTRY.lock().owners = Owners {
+        first: frames.start,
+        len,
+        map,
+    };
+}
+
+/// Typed handle of a slab cache. Only the objects made through it come
+/// from the cache.
+pub struct SlabCache<T> {
+    id: usize,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> SlabCache<T> {
+    /// Move `value` into an `Arc` whose block comes from the cache, or from
+    /// the heap if the cache cannot grow.
+    pub fn new_arc(&self, value: T) -> Arc<T> {
+        SELECTED[hart_id()].store(self.id + 1, Ordering::Relaxed);
+        let arc = Arc::new(value);
+        SELECTED[hart_id()].store(0, Ordering::Relaxed);
+        arc
+    }
+
+    /// An object as the constructor of the cache left it, `None` if the
+    /// cache cannot grow. It has to go back through [`SlabCache::free`].
+    pub fn alloc(&self) -> Option<NonNull<T>> {
+        let mut registry = REGISTRY.lock();
+        let Registry { caches, owners } = &mut *registry;
+        let cache = caches[self.id].as_mut().expect("slab cache is gone");
+        let obj = cache.alloc(self.id, &Layout::new::<T>(), owners)?;
+        NonNull::new(obj as *mut T)
+    }
+
+    /// Give back an object from [`SlabCache::alloc`].
+    ///
+    /// # Safety
+    ///
+    /// `obj` comes from `alloc` of this cache and is not used any more.
+    pub unsafe fn free(&self, obj: NonNull<T>) {
+        debug_assert!(self.owns(obj.as_ptr() as *const u8));
+        slab_dealloc(obj.as_ptr() as *mut u8);
+    }
+
+    /// whether `ptr` lies in a slab of this cache
+    pub fn owns(&self, ptr: *const u8) -> bool {
+        let ppn = PhysAddr::from(ptr as usize).floor();
+        REGISTRY.lock().owners.get(ppn.0) as usize >> OWNER_SHIFT == self.id + 1
+    }
+
+    pub fn stats(&self) -> SlabStats {
+        REGISTRY.lock().caches[self.id]
+            .as_ref()
+            .expect("slab cache is gone")
+            .stats
+    }
+}
+
+/// Dropping the handle destroys the cache, whose objects must all have
+/// been freed.
+impl<T> Drop for SlabCache<T> {
+    fn drop(&mut self) {
+        let mut registry = REGISTRY.lock();
+        let Registry { caches, owners } = &mut *registry;
+        let mut cache = caches[self.id].take().expect("slab cache is gone");
+        assert_eq!(
+            cache.stats.in_use, 0,
+            "slab cache {} destroyed with objects in use",
+            cache.name
+        );
+        while cache.head != 0 {
+            let head = cache.head;
+            cache.release(head, owners);
+        }
+    }
+}
+
+/// Create a cache for objects of type `T`, `ctor` is run on every object
+/// it hands out and must not allocate. Return its handle, or `None` if the
+/// registry is full.
+pub fn kmem_cache_create<T>(name: &'static str, ctor: Option<fn(*mut u8)>) -> Option<SlabCache<T>> {
+    assert!(
+        align_of::<T>() <= PAGE_SIZE,
+        "slab objects can be at most page aligned"
+    );
+    let mut registry = REGISTRY.lock();
+    let id = registry.caches.iter().position(|cache| cache.is_none())?;
+    registry.caches[id] = Some(KmemCache::new(name, ctor));
+    info!("slab cache {} created", name);
+    Some(SlabCache {
+        id,
+        _marker: PhantomData,
+    })
+}
+
+/// Allocate from the cache whose handle this allocation comes through, if
+/// any and if it fits the cache.
+pub fn slab_alloc(layout: &Layout) -> Option<*mut u8> {
+    let id = SELECTED[hart_id()]
+        .swap(0, Ordering::Relaxed)
+        .checked_sub(1)?;
+    let mut registry = REGISTRY.lock();
+    let Registry { caches, owners } = &mut *registry;
+    caches[id].as_mut()?.alloc(id, layout, owners)
+}
+
+/// Give `ptr` back to the slab it comes from. Return false if it does not
+/// lie in a slab.
+pub fn slab_dealloc(ptr: *mut u8) -> bool {
+    let mut registry = REGISTRY.lock();
+    let Registry { caches, owners } = &mut *registry;
+    let ppn = PhysAddr::from(ptr as usize).floor();
+    let owner = owners.get(ppn.0) as usize;
+    if owner == 0 {
+        return false;
+    }
+    let id = (owner >> OWNER_SHIFT) - 1;
+    let first = PhysPageNum(ppn.0 - (owner & ((1 << OWNER_SHIFT) - 1)));
+    caches[id]
+        .as_mut()
+        .expect("slab of a destroyed cache")
+        .dealloc(ptr as usize, first, owners);
+    true
+}
+
+/// call `f` with the name and statistics of every cache
+pub fn slab_stats(mut f: impl FnMut(&'static str, SlabStats)) {
+    for cache in REGISTRY.lock().caches.iter().flatten() {
+        f(cache.name, cache.stats);
+    }
+}
+
+/// Check that a cache hands out objects set up by its constructor, gives
+/// its slabs back once they are empty, and that an `Arc` made through a
+/// handle lands in its cache.
+pub fn slab_test() {
+    const PATTERN: [usize; 4] = [0x5a5a_5a5a; 4];
+    fn ctor(obj: *mut u8) {
+        unsafe { (obj as *mut [usize; 4]).write(PATTERN) };
+    }
+    let cache = kmem_cache_create::<[usize; 4]>("slab_test", Some(ctor)).expect("no room");
+    let mut objs = Vec::new();
+    while cache.stats().slabs < 3 {
+        let obj = cache.alloc().expect("slab test cache cannot grow");
+        assert_eq!(unsafe { *obj.as_ptr() }, PATTERN);
+        assert!(cache.owns(obj.as_ptr() as *const u8));
+        // scribbled over, it has to be constructed again when it comes back
+        unsafe { obj.as_ptr().write([0; 4]) };
+        objs.push(obj);
+    }
+    for obj in objs {
+        unsafe { cache.free(obj) };
+    }
+    let obj = cache.alloc().expect("slab test cache cannot grow");
+    assert_eq!(unsafe { *obj.as_ptr() }, PATTERN);
+    unsafe { cache.free(obj) };
+    let stats = cache.stats();
+    assert_eq!(stats.in_use, 0);
+    assert!(stats.slabs <= MAX_EMPTY_SLABS);
+    drop(cache);
+
+    let cache = kmem_cache_create::<usize>("slab_test_arc", None).expect("no room");
+    let arc = cache.new_arc(42);
+    assert!(cache.owns(Arc::as_ptr(&arc) as *const u8));
+    let boxed = alloc::boxed::Box::new([0usize; 3]);
+    assert!(!cache.owns(boxed.as_ref().as_ptr() as *const u8));
+    drop(arc);
+    drop(cache);
+    info!("slab_test passed!");
+}
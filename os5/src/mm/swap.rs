@@ -0,0 +1,131 @@
+//! Implementation of the swap area, which holds user pages evicted
+//! from memory on a block device.
+//!
+//! The swap area is split into page-sized slots. A slot is owned by a
+//! [`SwapSlot`], which gives it back to the allocator on drop, just like
+//! [`super::FrameTracker`] does for physical frames.
+
+use super::PhysPageNum;
+use crate::config::PAGE_SIZE;
+use crate::drivers::{BlockDevice, BLOCK_SIZE};
+use alloc::{sync::Arc, vec::Vec};
+use core::fmt::{self, Debug, Formatter};
+use lazy_static::*;
+use spin::Mutex;
+
+const BLOCKS_PER_SLOT: usize = PAGE_SIZE / BLOCK_SIZE;
+
+/// a swap slot which has the same lifecycle as the tracker
+pub struct SwapSlot {
+    pub slot: usize,
+}
+
+impl Debug for SwapSlot {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_fmt(format_args!("SwapSlot:{:#x}", self.slot))
+    }
+}
+
+impl Drop for SwapSlot {
+    fn drop(&mut self) {
+        SWAP_MANAGER.lock().dealloc(self.slot);
+    }
+}
+
+/// swap counters of an address space
+#[derive(Default, Clone, Copy, Debug)]
+#[repr(C)]
+pub struct SwapStat {
+    pub swap_ins: usize,
+    pub swap_outs: usize,
+}
+
+struct SwapManager {
+    device: Option<Arc<dyn BlockDevice>>,
+    start_block: usize,
+    current: usize,
+    end: usize,
+    recycled: Vec<usize>,
+}
+
+impl SwapManager {
+    fn new() -> Self {
+        Self {
+            device: None,
+            start_block: 0,
+            current: 0,
+            end: 0,
+            recycled: Vec::new(),
+        }
+    }
+    fn alloc(&mut self) -> Option<usize> {
+        if let Some(slot) = self.recycled.pop() {
+            Some(slot)
+        } else if self.current == self.end {
+            None
+        } else {
+            self.current += 1;
+            Some(self.current - 1)
+        }
+    }
+    fn dealloc(&mut self, slot: usize) {
+        // validity check
+        if slot >= self.current || self.recycled.iter().any(|v| *v == slot) {
+            panic!("Swap slot {:#x} has not been allocated!", slot);
+        }
+        self.recycled.push(slot);
+    }
+    fn first_block(&self, slot: usize) -> usize {
+        self.start_block + slot * BLOCKS_PER_SLOT
+    }
+}
+
+lazy_static! {
+    static ref SWAP_MANAGER: Mutex<SwapManager> = Mutex::new(SwapManager::new());
+}
+
+/// use `num_blocks` blocks of `device` starting at `start_block` as swap area
+pub fn init_swap(device: Arc<dyn BlockDevice>, start_block: usize, num_blocks: usize) {
+    let mut manager = SWAP_MANAGER.lock();
+    manager.device = Some(device);
+    manager.start_block = start_block;
+    manager.current = 0;
+    manager.end = num_blocks / BLOCKS_PER_SLOT;
+    info!("swap area enabled, {} slots", manager.end);
+}
+
+/// whether a swap area has been set up
+pub fn swap_enabled() -> bool {
+    SWAP_MANAGER.lock().device.is_some()
+}
+
+/// a free slot, `None` if the swap area is full or not set up
+pub fn alloc_slot() -> Option<SwapSlot> {
+    let mut manager = SWAP_MANAGER.lock();
+    manager.device.as_ref()?;
+    Some(SwapSlot {
+        slot: manager.alloc()?,
+    })
+}
+
+/// write the frame out into the slot
+pub fn swap_write(slot: &SwapSlot, ppn: PhysPageNum) {
+    let manager = SWAP_MANAGER.lock();
+    let device = Arc::clone(manager.device.as_ref().expect("swap area is gone"));
+    let first_block = manager.first_block(slot.slot);
+    drop(manager);
+    for (i, block) in ppn.get_bytes_array().chunks(BLOCK_SIZE).enumerate() {
+        device.write_block(first_block + i, block);
+    }
+}
+
+/// read the content of the slot back into the frame
+pub fn swap_in(slot: &SwapSlot, ppn: PhysPageNum) {
+    let manager = SWAP_MANAGER.lock();
+    let device = Arc::clone(manager.device.as_ref().expect("swap area is gone"));
+    let first_block = manager.first_block(slot.slot);
+    drop(manager);
+    for (i, block) in ppn.get_bytes_array().chunks_mut(BLOCK_SIZE).enumerate() {
+        device.read_block(first_block + i, block);
+    }
+}
@@ -1,12 +1,114 @@
-#![allow(unused)]
+//! Supervisor Binary Interface calls.
+//!
+//! The SBI v0.2 calling convention is used where the SBI implements the
+//! extension, found out by probing the base extension once per extension.
+//! Timer, IPI, remote fence and shutdown fall back to the legacy calls of
+//! v0.1 otherwise, the console only exists as legacy calls.
+
+use core::sync::atomic::{AtomicU8, Ordering};
 
 const SBI_SET_TIMER: usize = 0;
 const SBI_CONSOLE_PUTCHAR: usize = 1;
 const SBI_CONSOLE_GETCHAR: usize = 2;
+const SBI_SEND_IPI: usize = 4;
+const SBI_REMOTE_FENCE_I: usize = 5;
+const SBI_REMOTE_SFENCE_VMA: usize = 6;
 const SBI_SHUTDOWN: usize = 8;
 
+const EXT_BASE: usize = 0x10;
+const BASE_GET_SPEC_VERSION: usize = 0;
+const BASE_GET_IMPL_ID: usize = 1;
+const BASE_GET_IMPL_VERSION: usize = 2;
+const BASE_PROBE_EXTENSION: usize = 3;
+
+const EXT_TIME: usize = 0x54494D45;
+const TIME_SET_TIMER: usize = 0;
+
+const EXT_IPI: usize = 0x735049;
+const IPI_SEND_IPI: usize = 0;
+
+const EXT_RFENCE: usize = 0x52464E43;
+const RFENCE_REMOTE_FENCE_I: usize = 0;
+const RFENCE_REMOTE_SFENCE_VMA: usize = 1;
+
+const EXT_HSM: usize = 0x48534D;
+const HSM_HART_START: usize = 0;
+const HSM_HART_STOP: usize = 1;
+
+const EXT_SRST: usize = 0x53525354;
+const SRST_SYSTEM_RESET: usize = 0;
+const SRST_TYPE_SHUTDOWN: usize = 0;
+
+/// error codes of the v0.2 calling convention
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SbiError {
+    Failed,
+    NotSupported,
+    InvalidParam,
+    Denied,
+    InvalidAddress,
+    AlreadyAvailable,
+    AlreadyStarted,
+    AlreadyStopped,
+    Unknown(isize),
+}
+
+impl SbiError {
+    fn from_code(code: isize) -> Self {
+        match code {
+            -1 => Self::Failed,
+            -2 => Self::NotSupported,
+            -3 => Self::InvalidParam,
+            -4 => Self::Denied,
+            -5 => Self::InvalidAddress,
+            -6 => Self::AlreadyAvailable,
+            -7 => Self::AlreadyStarted,
+            -8 => Self::AlreadyStopped,
+            code => Self::Unknown(code),
+        }
+    }
+}
+
+pub type SbiResult = Result<usize, SbiError>;
+
+/// why the system is shut down
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetReason {
+    NoReason = 0,
+    SystemFailure = 1,
+}
+
+/// call function `fid` of extension `eid`
 #[inline(always)]
-fn sbi_call(which: usize, arg0: usize, arg1: usize, arg2: usize) -> usize {
+fn sbi_call(
+    eid: usize,
+    fid: usize,
+    arg0: usize,
+    arg1: usize,
+    arg2: usize,
+    arg3: usize,
+) -> SbiResult {
+    let (error, value): (isize, usize);
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            inlateout("x10") arg0 => error,
+            inlateout("x11") arg1 => value,
+            in("x12") arg2,
+            in("x13") arg3,
+            in("x16") fid,
+            in("x17") eid,
+        );
+    }
+    if error == 0 {
+        Ok(value)
+    } else {
+        Err(SbiError::from_code(error))
+    }
+}
+
+#[inline(always)]
+fn sbi_legacy_call(which: usize, arg0: usize, arg1: usize, arg2: usize) -> usize {
     let mut ret;
     unsafe {
         core::arch::asm!(
@@ -21,19 +123,195 @@ fn sbi_call(which: usize, arg0: usize, arg1: usize, arg2: usize) -> usize {
     ret
 }
 
-pub fn set_timer(timer: usize) {
-    sbi_call(SBI_SET_TIMER, timer, 0, 0);
+const PROBE_UNKNOWN: u8 = 0;
+const PROBE_ABSENT: u8 = 1;
+const PROBE_PRESENT: u8 = 2;
+
+/// probe results of TIME, IPI, RFENCE, HSM and SRST in this order
+static PROBED: [AtomicU8; 5] = [
+    AtomicU8::new(PROBE_UNKNOWN),
+    AtomicU8::new(PROBE_UNKNOWN),
+    AtomicU8::new(PROBE_UNKNOWN),
+    AtomicU8::new(PROBE_UNKNOWN),
+    AtomicU8::new(PROBE_UNKNOWN),
+];
+
+fn probe_slot(eid: usize) -> usize {
+    match eid {
+        EXT_TIME => 0,
+        EXT_IPI => 1,
+        EXT_RFENCE => 2,
+        EXT_HSM => 3,
+        EXT_SRST => 4,
+        _ => unreachable!("extension {:#x} is not cached", eid),
+    }
+}
+
+/// Whether the SBI implements extension `eid`, a v0.1 SBI has none.
+fn probe_extension(eid: usize) -> bool {
+    matches!(sbi_call(EXT_BASE, BASE_PROBE_EXTENSION, eid, 0, 0, 0), Ok(value) if value != 0)
+}
+
+/// [`probe_extension`] cached for the extensions used by the kernel
+fn has_extension(eid: usize) -> bool {
+    let slot = &PROBED[probe_slot(eid)];
+    match slot.load(Ordering::Relaxed) {
+        PROBE_PRESENT => true,
+        PROBE_ABSENT => false,
+        _ => {
+            let present = probe_extension(eid);
+            let state = if present { PROBE_PRESENT } else { PROBE_ABSENT };
+            slot.store(state, Ordering::Relaxed);
+            present
+        }
+    }
+}
+
+/// Log the SBI version and which extensions it has.
+pub fn init() {
+    match sbi_call(EXT_BASE, BASE_GET_SPEC_VERSION, 0, 0, 0, 0) {
+        Ok(version) => log::info!(
+            "SBI spec v{}.{}, implementation {} v{:#x}",
+            version >> 24 & 0x7f,
+            version & 0xff_ffff,
+            sbi_call(EXT_BASE, BASE_GET_IMPL_ID, 0, 0, 0, 0).unwrap_or(0),
+            sbi_call(EXT_BASE, BASE_GET_IMPL_VERSION, 0, 0, 0, 0).unwrap_or(0)
+        ),
+        Err(_) => log::info!("SBI v0.1, legacy calls only"),
+    }
+    for (name, eid) in [
+        ("TIME", EXT_TIME),
+        ("IPI", EXT_IPI),
+        ("RFENCE", EXT_RFENCE),
+        ("HSM", EXT_HSM),
+        ("SRST", EXT_SRST),
+    ] {
+        log::info!("SBI extension {}: {}", name, has_extension(eid));
+    }
+}
+
+/// Have the timer interrupt once `time` reaches `timer`.
+pub fn set_timer(timer: usize) -> SbiResult {
+    if has_extension(EXT_TIME) {
+        sbi_call(EXT_TIME, TIME_SET_TIMER, timer, 0, 0, 0)
+    } else {
+        sbi_legacy_call(SBI_SET_TIMER, timer, 0, 0);
+        Ok(0)
+    }
+}
+
+/// Make a legacy call which takes a pointer to a hart mask as first
+/// argument, a null one for all harts.
+fn legacy_mask_call(
+    which: usize,
+    hart_mask: usize,
+    hart_mask_base: usize,
+    arg1: usize,
+    arg2: usize,
+) -> SbiResult {
+    if hart_mask_base == usize::MAX {
+        sbi_legacy_call(which, 0, arg1, arg2);
+    } else {
+        let mask = hart_mask << hart_mask_base;
+        sbi_legacy_call(which, &mask as *const usize as usize, arg1, arg2);
+    }
+    Ok(0)
+}
+
+/// Send a software interrupt to the harts `hart_mask_base + i` for every
+/// bit i in `hart_mask`, or to all harts if `hart_mask_base` is
+/// `usize::MAX`.
+pub fn send_ipi(hart_mask: usize, hart_mask_base: usize) -> SbiResult {
+    if has_extension(EXT_IPI) {
+        sbi_call(EXT_IPI, IPI_SEND_IPI, hart_mask, hart_mask_base, 0, 0)
+    } else {
+        legacy_mask_call(SBI_SEND_IPI, hart_mask, hart_mask_base, 0, 0)
+    }
+}
+
+/// execute `fence.i` on the harts in the mask, see [`send_ipi`]
+// every return to user mode runs `fence.i`, so the kernel has no use yet
+#[allow(dead_code)]
+pub fn remote_fence_i(hart_mask: usize, hart_mask_base: usize) -> SbiResult {
+    if has_extension(EXT_RFENCE) {
+        sbi_call(
+            EXT_RFENCE,
+            RFENCE_REMOTE_FENCE_I,
+            hart_mask,
+            hart_mask_base,
+            0,
+            0,
+        )
+    } else {
+        legacy_mask_call(SBI_REMOTE_FENCE_I, hart_mask, hart_mask_base, 0, 0)
+    }
+}
+
+/// Flush the TLB entries of `[start, start + size)` on the harts in the
+/// mask, see [`send_ipi`]. A `start` and `size` of 0 flush all entries.
+pub fn remote_sfence_vma(
+    hart_mask: usize,
+    hart_mask_base: usize,
+    start: usize,
+    size: usize,
+) -> SbiResult {
+    if has_extension(EXT_RFENCE) {
+        sbi_call(
+            EXT_RFENCE,
+            RFENCE_REMOTE_SFENCE_VMA,
+            hart_mask,
+            hart_mask_base,
+            start,
+            size,
+        )
+    } else {
+        legacy_mask_call(
+            SBI_REMOTE_SFENCE_VMA,
+            hart_mask,
+            hart_mask_base,
+            start,
+            size,
+        )
+    }
+}
+
+/// Start `hartid` at physical address `start_addr` with `opaque` in a1.
+pub fn hart_start(hartid: usize, start_addr: usize, opaque: usize) -> SbiResult {
+    sbi_call(EXT_HSM, HSM_HART_START, hartid, start_addr, opaque, 0)
+}
+
+/// stop the calling hart, returns only on failure
+pub fn hart_stop() -> SbiError {
+    match sbi_call(EXT_HSM, HSM_HART_STOP, 0, 0, 0, 0) {
+        Ok(_) => unreachable!("hart_stop returned"),
+        Err(err) => err,
+    }
 }
 
 pub fn console_putchar(c: usize) {
-    sbi_call(SBI_CONSOLE_PUTCHAR, c, 0, 0);
+    sbi_legacy_call(SBI_CONSOLE_PUTCHAR, c, 0, 0);
 }
 
 pub fn console_getchar() -> usize {
-    sbi_call(SBI_CONSOLE_GETCHAR, 0, 0, 0)
+    sbi_legacy_call(SBI_CONSOLE_GETCHAR, 0, 0, 0)
 }
 
-pub fn shutdown() -> ! {
-    sbi_call(SBI_SHUTDOWN, 0, 0, 0);
+/// Power off through SRST, or the legacy call without it. SRST only tells
+/// a failure from a clean shutdown, `exit_code` is for platforms which can
+/// pass it on.
+pub fn shutdown(reason: ResetReason, exit_code: u32) -> ! {
+    log::info!("shutdown: {:?}, exit code {}", reason, exit_code);
+    if has_extension(EXT_SRST) {
+        let err = sbi_call(
+            EXT_SRST,
+            SRST_SYSTEM_RESET,
+            SRST_TYPE_SHUTDOWN,
+            reason as usize,
+            0,
+            0,
+        );
+        log::error!("system reset failed: {:?}", err);
+    }
+    sbi_legacy_call(SBI_SHUTDOWN, 0, 0, 0);
     panic!("It should shutdown!");
 }
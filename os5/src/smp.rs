@@ -0,0 +1,120 @@
+//! Bring-up of the harts.
+//!
+//! Every hart enters `_start` with its hartid in a0, which `entry.asm`
+//! keeps in `tp` for the whole time the hart is in the kernel, the trap
+//! entry restores it from the trap context. The first hart to arrive boots
+//! the kernel, then starts the others through the SBI HSM extension.
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::{
+    cmdline::boot_args,
+    config::{BOOT_STACK_SIZE, MAX_HARTS},
+    fdt::machine,
+    sbi::{hart_start, hart_stop, send_ipi},
+};
+
+/// the hart which boots the kernel, in .data so that clearing .bss keeps it
+static BOOT_HART: AtomicUsize = AtomicUsize::new(usize::MAX);
+/// set once the boot hart is done, the other harts wait for it
+static BOOTED: AtomicBool = AtomicBool::new(false);
+/// harts past the spin in [`wait_for_boot`], the boot hart included
+static HARTS_UP: AtomicUsize = AtomicUsize::new(1);
+/// bit i is set once hart i schedules tasks
+static ONLINE_HARTS: AtomicUsize = AtomicUsize::new(0);
+/// bit i is set while hart i looks for a task or sleeps in its idle loop
+static IDLE_HARTS: AtomicUsize = AtomicUsize::new(0);
+
+/// id of the hart running this code
+#[inline(always)]
+pub fn hart_id() -> usize {
+    let id;
+    unsafe { core::arch::asm!("mv {}, tp", out(reg) id) };
+    id
+}
+
+/// Whether `hart_id` is the first hart in the kernel, which has to boot it.
+pub fn claim_boot_hart(hart_id: usize) -> bool {
+    match BOOT_HART.compare_exchange(usize::MAX, hart_id, Ordering::AcqRel, Ordering::Acquire) {
+        Ok(_) => true,
+        Err(boot_hart) => boot_hart == hart_id,
+    }
+}
+
+/// Start the other harts, up to `maxcpus` in all. Some SBIs start them on
+/// their own, they wait for the boot hart then.
+pub fn start_secondary_harts() {
+    extern "C" {
+        fn _start();
+    }
+    BOOTED.store(true, Ordering::Release);
+    let boot_hart = hart_id();
+    let mut started = 1;
+    for &id in machine().harts.iter().filter(|&&id| id != boot_hart) {
+        if id >= MAX_HARTS {
+            log::warn!("hart {} is beyond MAX_HARTS, left parked", id);
+            continue;
+        }
+        if started >= boot_args().maxcpus {
+            log::info!("hart {} is beyond maxcpus, left parked", id);
+            continue;
+        }
+        // harts which do not exist or are running already fail, that is fine
+        started += 1;
+        match hart_start(id, _start as usize, 0) {
+            Ok(_) => log::info!("start hart {}", id),
+            Err(err) => log::info!("cannot start hart {}: {:?}", id, err),
+        }
+    }
+}
+
+/// Spin until the boot hart is done, then stop this hart if `maxcpus`
+/// harts are up already, which happens when the SBI starts all of them.
+pub fn wait_for_boot() {
+    while !BOOTED.load(Ordering::Acquire) {
+        core::hint::spin_loop();
+    }
+    if HARTS_UP.fetch_add(1, Ordering::AcqRel) >= boot_args().maxcpus {
+        log::info!("hart {} is beyond maxcpus, stopped", hart_id());
+        let err = hart_stop();
+        log::warn!("cannot stop hart {}: {:?}, parked", hart_id(), err);
+        loop {
+            unsafe { riscv::asm::wfi() };
+        }
+    }
+}
+
+/// top of the boot stack of this hart, which is its idle stack later on
+pub fn idle_stack_top() -> usize {
+    extern "C" {
+        fn boot_stack();
+    }
+    boot_stack as usize + (hart_id() + 1) * BOOT_STACK_SIZE
+}
+
+/// note that this hart takes part in scheduling from now on
+pub fn set_online() {
+    ONLINE_HARTS.fetch_or(1 << hart_id(), Ordering::AcqRel);
+}
+
+/// mask of the harts which schedule tasks
+pub fn online_harts() -> usize {
+    ONLINE_HARTS.load(Ordering::Acquire)
+}
+
+pub fn set_idle(idle: bool) {
+    if idle {
+        IDLE_HARTS.fetch_or(1 << hart_id(), Ordering::SeqCst);
+    } else {
+        IDLE_HARTS.fetch_and(!(1 << hart_id()), Ordering::SeqCst);
+    }
+}
+
+/// Wake `hart` with an IPI if it is idle, after a task was queued there.
+pub fn kick(hart: usize) {
+    if hart != hart_id() && IDLE_HARTS.load(Ordering::SeqCst) & (1 << hart) != 0 {
+        if let Err(err) = send_ipi(1 << hart, 0) {
+            log::warn!("cannot send IPI to hart {}: {:?}", hart, err);
+        }
+    }
+}
@@ -0,0 +1,125 @@
+//! Spinlock for data shared between harts.
+//!
+//! Interrupts of the hart stay off while it holds any spinlock, so that
+//! an interrupt handler never spins on a lock held by the code it
+//! interrupted. Nested locks only turn them back on when the outermost
+//! one is released.
+
+use core::{
+    cell::UnsafeCell,
+    hint::spin_loop,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+use riscv::register::sstatus;
+
+use crate::{config::MAX_HARTS, smp::hart_id};
+
+/// spinlocks held by a hart and whether it had interrupts on before the first
+struct IrqState {
+    depth: AtomicUsize,
+    enabled: AtomicBool,
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const IRQ_STATE_INIT: IrqState = IrqState {
+    depth: AtomicUsize::new(0),
+    enabled: AtomicBool::new(false),
+};
+
+/// only ever touched by the hart itself, with interrupts off
+static IRQ_STATE: [IrqState; MAX_HARTS] = [IRQ_STATE_INIT; MAX_HARTS];
+
+fn push_off() {
+    let enabled = sstatus::read().sie();
+    unsafe { sstatus::clear_sie() };
+    let state = &IRQ_STATE[hart_id()];
+    if state.depth.fetch_add(1, Ordering::Relaxed) == 0 {
+        state.enabled.store(enabled, Ordering::Relaxed);
+    }
+}
+
+fn pop_off() {
+    let state = &IRQ_STATE[hart_id()];
+    let depth = state.depth.fetch_sub(1, Ordering::Relaxed);
+    assert!(depth > 0, "pop_off without push_off");
+    if depth == 1 && state.enabled.load(Ordering::Relaxed) {
+        unsafe { sstatus::set_sie() };
+    }
+}
+
+/// Whether this hart holds a spinlock, then it must not give up the hart.
+pub fn holds_spinlock() -> bool {
+    IRQ_STATE[hart_id()].depth.load(Ordering::Relaxed) > 0
+}
+
+pub struct SpinLock<T> {
+    locked: AtomicBool,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for SpinLock<T> {}
+
+impl<T> SpinLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    /// Spin until the lock is free, interrupts are off until the guard is
+    /// dropped. Locking it again on the same hart deadlocks.
+    pub fn lock(&self) -> SpinLockGuard<'_, T> {
+        push_off();
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            spin_loop();
+        }
+        SpinLockGuard { lock: self }
+    }
+
+    /// Take the lock if it is free, `None` if it is held, by this hart or
+    /// another one.
+    pub fn try_lock(&self) -> Option<SpinLockGuard<'_, T>> {
+        push_off();
+        match self
+            .locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+        {
+            Ok(_) => Some(SpinLockGuard { lock: self }),
+            Err(_) => {
+                pop_off();
+                None
+            }
+        }
+    }
+}
+
+pub struct SpinLockGuard<'a, T> {
+    lock: &'a SpinLock<T>,
+}
+
+impl<T> Deref for SpinLockGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> DerefMut for SpinLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for SpinLockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.locked.store(false, Ordering::Release);
+        pop_off();
+    }
+}
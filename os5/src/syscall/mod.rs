@@ -1,195 +1,650 @@
 mod pointer;
+mod trace;
 
 use alloc::{
     string::ToString,
     sync::{Arc, Weak},
+    vec,
     vec::Vec,
 };
+use core::mem::size_of;
 
 use crate::{
-    config::MAX_SYSCALL_NUM,
-    mm::{MapPermission, VirtAddr},
-    sbi::console_getchar,
+    config::{MAX_SYSCALL_NUM, PAGE_SIZE, TRACE_BUFFER_LEN},
+    console,
+    errno::Errno,
+    loader::get_app_elf,
+    mm::{check_wx, shm_get, MapPermission, SwapStat, VirtAddr, SHM_RDONLY},
+    smp::online_harts,
     syscall::pointer::{from_user_ptr_to_slice, from_user_ptr_to_str},
-    task::{add_task, fork_task, pop_cur_task, run_next_task, switch_task, Task, TaskState},
-    timer::{self, get_time_ms},
+    task::{
+        add_task, exit_task, find_task, fork_task, fp_release, pop_cur_task, requeue, switch_task,
+        task_pids, Task, TaskState,
+    },
+    timer::{self, get_time_ms, get_time_us, TimeSpec, TimeVal},
 };
 
-use self::pointer::{from_user_cstring, from_user_ptr};
+use self::pointer::{as_bytes, copy_to_user, from_user_cstring, from_user_ptr};
+use self::trace::*;
 const STDOUT: usize = 1;
 
+/// A syscall decoded from its number and the raw arguments in `a0..a5`.
 #[derive(Debug)]
 enum Syscall {
-    Exit,
-    Write,
-    GetTimeOfDay,
+    Exit {
+        exit_code: i32,
+    },
+    Write {
+        fd: usize,
+        buf: usize,
+        len: usize,
+    },
+    GetTimeOfDay {
+        time_val: usize,
+        tz: usize,
+    },
+    ClockGetTime {
+        clock: usize,
+        tp: usize,
+    },
+    Times {
+        tms: usize,
+    },
+    GetRusage {
+        who: isize,
+        usage: usize,
+    },
     Yield,
-    TaskInfo,
-    Mmap,
-    Munmap,
+    TaskInfo {
+        info: usize,
+    },
+    Mmap {
+        start: usize,
+        len: usize,
+        port: usize,
+    },
+    Munmap {
+        start: usize,
+        len: usize,
+    },
     Fork,
-    WaitPid,
+    WaitPid {
+        pid: isize,
+        exit_code: usize,
+    },
     GetPid,
-    Read,
-    SetPriority,
-    Exec,
-    Spawn,
+    Read {
+        fd: usize,
+        buf: usize,
+        len: usize,
+    },
+    SetPriority {
+        priority: isize,
+    },
+    SchedSetAffinity {
+        pid: usize,
+        len: usize,
+        mask: usize,
+    },
+    SchedGetAffinity {
+        pid: usize,
+        len: usize,
+        mask: usize,
+    },
+    Exec {
+        path: usize,
+    },
+    Spawn {
+        path: usize,
+    },
+    SwapStat {
+        stat: usize,
+    },
+    ShmGet {
+        key: usize,
+        size: usize,
+        flags: usize,
+    },
+    ShmAt {
+        shm_id: usize,
+        addr: usize,
+        flags: usize,
+    },
+    ShmDt {
+        addr: usize,
+    },
+    Brk {
+        addr: usize,
+    },
+    Sbrk {
+        increment: isize,
+    },
+    Mprotect {
+        start: usize,
+        len: usize,
+        prot: usize,
+    },
+    Trace {
+        pid: usize,
+        mask: usize,
+    },
+    TraceRead {
+        pid: usize,
+        cursor: usize,
+        buf: usize,
+        count: usize,
+    },
+    ListTasks {
+        buf: usize,
+        count: usize,
+    },
+    ProcInfo {
+        pid: usize,
+        info: usize,
+    },
 }
 impl Syscall {
-    fn from(n: usize) -> Result<Syscall, ()> {
-        Ok(match n {
-            63 => Self::Read,          // 0x3f
-            64 => Self::Write,         // 0x40
-            93 => Self::Exit,          // 0x5d
-            124 => Self::Yield,        // 0x7c
-            140 => Self::SetPriority,  // 0x8c
-            169 => Self::GetTimeOfDay, // 0xa9
-            172 => Self::GetPid,       // 0xac
-            215 => Self::Munmap,       // 0xd7
-            220 => Self::Fork,         // 0xdc
-            221 => Self::Exec,         // 0xdd
-            222 => Self::Mmap,         // 0xde
-            260 => Self::WaitPid,      // 0x104
-            400 => Self::Spawn,        // 0x190
-            410 => Self::TaskInfo,     // 0x19a
+    fn decode(n: usize, a: [usize; 6]) -> Option<Syscall> {
+        Some(match n {
+            // 0x3f
+            63 => Self::Read {
+                fd: a[0],
+                buf: a[1],
+                len: a[2],
+            },
+            // 0x40
+            64 => Self::Write {
+                fd: a[0],
+                buf: a[1],
+                len: a[2],
+            },
+            // 0x5d
+            93 => Self::Exit {
+                exit_code: a[0] as i32,
+            },
+            // 0x71
+            113 => Self::ClockGetTime {
+                clock: a[0],
+                tp: a[1],
+            },
+            // 0x7a
+            122 => Self::SchedSetAffinity {
+                pid: a[0],
+                len: a[1],
+                mask: a[2],
+            },
+            // 0x7b
+            123 => Self::SchedGetAffinity {
+                pid: a[0],
+                len: a[1],
+                mask: a[2],
+            },
+            // 0x7c
+            124 => Self::Yield,
+            // 0x8c
+            140 => Self::SetPriority {
+                priority: a[0] as isize,
+            },
+            // 0x99
+            153 => Self::Times { tms: a[0] },
+            // 0xa5
+            165 => Self::GetRusage {
+                who: a[0] as isize,
+                usage: a[1],
+            },
+            // 0xa9
+            169 => Self::GetTimeOfDay {
+                time_val: a[0],
+                tz: a[1],
+            },
+            // 0xac
+            172 => Self::GetPid,
+            // 0xc2
+            194 => Self::ShmGet {
+                key: a[0],
+                size: a[1],
+                flags: a[2],
+            },
+            // 0xc4
+            196 => Self::ShmAt {
+                shm_id: a[0],
+                addr: a[1],
+                flags: a[2],
+            },
+            // 0xc5
+            197 => Self::ShmDt { addr: a[0] },
+            // 0xd6
+            214 => Self::Brk { addr: a[0] },
+            // 0xd7
+            215 => Self::Munmap {
+                start: a[0],
+                len: a[1],
+            },
+            // 0xdc
+            220 => Self::Fork,
+            // 0xdd
+            221 => Self::Exec { path: a[0] },
+            // 0xde
+            222 => Self::Mmap {
+                start: a[0],
+                len: a[1],
+                port: a[2],
+            },
+            // 0xe2
+            226 => Self::Mprotect {
+                start: a[0],
+                len: a[1],
+                prot: a[2],
+            },
+            // 0x104
+            260 => Self::WaitPid {
+                pid: a[0] as isize,
+                exit_code: a[1],
+            },
+            // 0x190
+            400 => Self::Spawn { path: a[0] },
+            // 0x19a
+            410 => Self::TaskInfo { info: a[0] },
+            // 0x19b
+            411 => Self::SwapStat { stat: a[0] },
+            // 0x19c
+            412 => Self::Sbrk {
+                increment: a[0] as isize,
+            },
+            // 0x19d
+            413 => Self::Trace {
+                pid: a[0],
+                mask: a[1],
+            },
+            // 0x19e
+            414 => Self::TraceRead {
+                pid: a[0],
+                cursor: a[1],
+                buf: a[2],
+                count: a[3],
+            },
+            // 0x19f
+            415 => Self::ListTasks {
+                buf: a[0],
+                count: a[1],
+            },
+            // 0x1a0
+            416 => Self::ProcInfo {
+                pid: a[0],
+                info: a[1],
+            },
             _ => {
                 log::warn!("unsupported syscall: {}", n.to_string());
-                return Err(());
+                return None;
             }
         })
     }
+    /// name, trace class and decoded arguments of the syscall
+    fn trace_info(&self) -> (&'static str, usize, Vec<usize>) {
+        match *self {
+            Self::Exit { exit_code } => ("exit", TRACE_PROCESS, vec![exit_code as usize]),
+            Self::Write { fd, buf, len } => ("write", TRACE_IO, vec![fd, buf, len]),
+            Self::GetTimeOfDay { time_val, tz } => ("gettimeofday", TRACE_TIME, vec![time_val, tz]),
+            Self::ClockGetTime { clock, tp } => ("clock_gettime", TRACE_TIME, vec![clock, tp]),
+            Self::Times { tms } => ("times", TRACE_TIME, vec![tms]),
+            Self::GetRusage { who, usage } => ("getrusage", TRACE_TIME, vec![who as usize, usage]),
+            Self::Yield => ("yield", TRACE_PROCESS, vec![]),
+            Self::TaskInfo { info } => ("task_info", TRACE_INFO, vec![info]),
+            Self::Mmap { start, len, port } => ("mmap", TRACE_MEMORY, vec![start, len, port]),
+            Self::Munmap { start, len } => ("munmap", TRACE_MEMORY, vec![start, len]),
+            Self::Fork => ("fork", TRACE_PROCESS, vec![]),
+            Self::WaitPid { pid, exit_code } => {
+                ("waitpid", TRACE_PROCESS, vec![pid as usize, exit_code])
+            }
+            Self::GetPid => ("getpid", TRACE_PROCESS, vec![]),
+            Self::Read { fd, buf, len } => ("read", TRACE_IO, vec![fd, buf, len]),
+            Self::SetPriority { priority } => {
+                ("set_priority", TRACE_PROCESS, vec![priority as usize])
+            }
+            Self::SchedSetAffinity { pid, len, mask } => {
+                ("sched_setaffinity", TRACE_PROCESS, vec![pid, len, mask])
+            }
+            Self::SchedGetAffinity { pid, len, mask } => {
+                ("sched_getaffinity", TRACE_PROCESS, vec![pid, len, mask])
+            }
+            Self::Exec { path } => ("exec", TRACE_PROCESS, vec![path]),
+            Self::Spawn { path } => ("spawn", TRACE_PROCESS, vec![path]),
+            Self::SwapStat { stat } => ("swap_stat", TRACE_INFO, vec![stat]),
+            Self::ShmGet { key, size, flags } => ("shmget", TRACE_MEMORY, vec![key, size, flags]),
+            Self::ShmAt {
+                shm_id,
+                addr,
+                flags,
+            } => ("shmat", TRACE_MEMORY, vec![shm_id, addr, flags]),
+            Self::ShmDt { addr } => ("shmdt", TRACE_MEMORY, vec![addr]),
+            Self::Brk { addr } => ("brk", TRACE_MEMORY, vec![addr]),
+            Self::Sbrk { increment } => ("sbrk", TRACE_MEMORY, vec![increment as usize]),
+            Self::Mprotect { start, len, prot } => {
+                ("mprotect", TRACE_MEMORY, vec![start, len, prot])
+            }
+            Self::Trace { pid, mask } => ("trace", TRACE_INFO, vec![pid, mask]),
+            Self::TraceRead {
+                pid,
+                cursor,
+                buf,
+                count,
+            } => ("trace_read", TRACE_INFO, vec![pid, cursor, buf, count]),
+            Self::ListTasks { buf, count } => ("list_tasks", TRACE_INFO, vec![buf, count]),
+            Self::ProcInfo { pid, info } => ("proc_info", TRACE_INFO, vec![pid, info]),
+        }
+    }
 }
 
-type SyscallResult = Result<isize, ()>;
+/// a failed syscall returns the negated error number
+type SyscallResult = Result<isize, Errno>;
 
 impl Syscall {
-    fn handle(&self, task: &Weak<Task>, arg1: usize, arg2: usize, arg3: usize) {
+    /// Run the syscall and write its return value, completing `trace` if
+    /// the task traces it.
+    fn handle(self, task: &Weak<Task>, mut trace: Option<TraceRecord>) {
+        // exit and yield never return here, record them up front
+        if matches!(self, Syscall::Exit { .. } | Syscall::Yield) {
+            if let Some(record) = trace.take() {
+                trace_push(record, parent_pid(task));
+            }
+        }
         let ret: SyscallResult = match self {
-            Syscall::Write => sys_write(task, arg1, arg2, arg3),
-            Syscall::Exit => sys_exit(Task::from_weak(&task), arg1 as i32),
-            Syscall::GetTimeOfDay => sys_gettimeofday(task, arg1, arg2),
+            Syscall::Write { fd, buf, len } => sys_write(task, fd, buf, len),
+            Syscall::Exit { exit_code } => sys_exit(Task::from_weak(&task), exit_code),
+            Syscall::GetTimeOfDay { time_val, tz } => sys_gettimeofday(task, time_val, tz),
+            Syscall::ClockGetTime { clock, tp } => sys_clock_gettime(task, clock, tp),
+            Syscall::Times { tms } => sys_times(task, tms),
+            Syscall::GetRusage { who, usage } => sys_getrusage(task, who, usage),
             Syscall::Yield => sys_yield(Task::from_weak(&task)),
-            Syscall::TaskInfo => sys_taskinfo(task, arg1),
-            Syscall::Mmap => sys_mmap(task, arg1, arg2, arg3),
-            Syscall::Munmap => sys_unmmap(task, arg1, arg2),
+            Syscall::TaskInfo { info } => sys_taskinfo(task, info),
+            Syscall::Mmap { start, len, port } => sys_mmap(task, start, len, port),
+            Syscall::Munmap { start, len } => sys_unmmap(task, start, len),
             Syscall::Fork => sys_fork(task),
-            Syscall::WaitPid => sys_waitpid(task, arg1 as isize, arg2),
+            Syscall::WaitPid { pid, exit_code } => sys_waitpid(task, pid, exit_code),
             Syscall::GetPid => sys_getpid(task),
-            Syscall::Read => sys_read(task.upgrade().unwrap(), arg1, arg2, arg3),
-            Syscall::SetPriority => sys_set_priority(task, arg1 as isize),
-            Syscall::Exec => sys_exec(task, arg1),
-            Syscall::Spawn => sys_spawn(task, arg1),
-            // _ => todo!("unsupported syscall handle function, syscall={:?}", self),
-        };
-        let ret = ret.unwrap_or(-1);
-        let task = Task::from_weak(&task);
-        let a0 = {
-            let inner = task.inner_exclusive_access();
-            let trap_ctx = inner.trap_context();
-            trap_ctx.set_reg_a(0, ret as usize);
-            trap_ctx.reg_a(0)
+            Syscall::Read { fd, buf, len } => sys_read(task.upgrade().unwrap(), fd, buf, len),
+            Syscall::SetPriority { priority } => sys_set_priority(task, priority),
+            Syscall::SchedSetAffinity { pid, len, mask } => {
+                sys_sched_setaffinity(task, pid, len, mask)
+            }
+            Syscall::SchedGetAffinity { pid, len, mask } => {
+                sys_sched_getaffinity(task, pid, len, mask)
+            }
+            Syscall::Exec { path } => sys_exec(task, path),
+            Syscall::Spawn { path } => sys_spawn(task, path),
+            Syscall::SwapStat { stat } => sys_swap_stat(task, stat),
+            Syscall::ShmGet { key, size, flags } => sys_shmget(task, key, size, flags),
+            Syscall::ShmAt {
+                shm_id,
+                addr,
+                flags,
+            } => sys_shmat(task, shm_id, addr, flags),
+            Syscall::ShmDt { addr } => sys_shmdt(task, addr),
+            Syscall::Brk { addr } => sys_brk(task, addr),
+            Syscall::Sbrk { increment } => sys_sbrk(task, increment),
+            Syscall::Mprotect { start, len, prot } => sys_mprotect(task, start, len, prot),
+            Syscall::Trace { pid, mask } => sys_trace(task, pid, mask),
+            Syscall::TraceRead {
+                pid,
+                cursor,
+                buf,
+                count,
+            } => sys_trace_read(task, pid, cursor, buf, count),
+            Syscall::ListTasks { buf, count } => sys_list_tasks(task, buf, count),
+            Syscall::ProcInfo { pid, info } => sys_proc_info(task, pid, info),
         };
-        log::info!(
-            "task_{} syscall ret={:x}, task.trap_ctx.x[10]={:x}",
-            task.pid,
-            ret,
-            a0
-        );
+        let ret = ret.unwrap_or_else(Errno::as_ret);
+        if let Some(mut record) = trace {
+            record.ret = ret;
+            record.duration_us = get_time_us() - record.start_us;
+            trace_push(record, parent_pid(task));
+        }
+        set_return_value(task, ret);
     }
 }
 
+fn set_return_value(task: &Weak<Task>, ret: isize) {
+    let task = Task::from_weak(&task);
+    let a0 = {
+        let inner = task.inner_exclusive_access();
+        let trap_ctx = inner.trap_context();
+        trap_ctx.set_reg_a(0, ret as usize);
+        trap_ctx.reg_a(0)
+    };
+    log::debug!(
+        "task_{} syscall ret={:x}, task.trap_ctx.x[10]={:x}",
+        task.pid,
+        ret,
+        a0
+    );
+}
+
 pub fn syscall_handler(weak_task: &Weak<Task>) {
-    let (syscall_num, a0, a1, a2) = {
+    let (syscall_num, args, trace_mask) = {
         let task = Task::from_weak(&weak_task);
         let mut inner = task.inner_exclusive_access();
         let trap_ctx = inner.trap_context();
         let syscall_num = trap_ctx.reg_a(7);
-        let ret = (
-            syscall_num,
-            trap_ctx.reg_a(0),
-            trap_ctx.reg_a(1),
-            trap_ctx.reg_a(2),
-        );
-        inner.syscall_times[syscall_num] += 1;
-        ret
+        let mut args = [0; 6];
+        for (i, arg) in args.iter_mut().enumerate() {
+            *arg = trap_ctx.reg_a(i);
+        }
+        if let Some(times) = inner.syscall_times.get_mut(syscall_num) {
+            *times += 1;
+        }
+        (syscall_num, args, inner.trace_mask)
     };
 
-    let syscall =
-        Syscall::from(syscall_num).unwrap_or_else(|_| sys_exit(pop_cur_task().unwrap(), 1));
+    let syscall = match Syscall::decode(syscall_num, args) {
+        Some(syscall) => syscall,
+        None => return set_return_value(weak_task, Errno::ENOSYS.as_ret()),
+    };
 
-    {
-        let task = Task::from_weak(&weak_task);
-        log::info!(
-            "{} syscall_handler, num={}, name={:?}",
-            task,
+    let task = Task::from_weak(&weak_task);
+    log::debug!(
+        "{} syscall_handler, num={}, syscall={:?}",
+        task,
+        syscall_num,
+        syscall
+    );
+    let (name, class, args) = syscall.trace_info();
+    let trace = if trace_mask & class != 0 {
+        Some(TraceRecord::new(
+            task.pid.0,
             syscall_num,
-            syscall
-        );
-    }
+            name,
+            &args,
+            get_time_us(),
+        ))
+    } else {
+        None
+    };
+    drop(task);
     // log::info!("syscall_times={:?}", ctx.syscall_times);
-    syscall.handle(weak_task, a0, a1, a2)
+    syscall.handle(weak_task, trace)
 }
 
 fn sys_write(task: &Weak<Task>, fd: usize, buf: usize, len: usize) -> SyscallResult {
     let task = Task::from_weak(&task);
-    let user_buf = from_user_ptr_to_str(&task, buf, len);
-
     log::info!("sys_write args, fd={}, buf=0x{:x}, len={}", fd, buf, len);
     if fd != STDOUT {
-        unimplemented!()
+        log::error!("{}, wrong fd? fd={}", task, fd);
+        return Err(Errno::EBADF);
     }
+    let user_buf = from_user_ptr_to_str(&task, buf, len)?;
     print!("{}", user_buf);
     Ok(len as isize)
 }
 
 fn sys_gettimeofday(task: &Weak<Task>, timeval_ptr: usize, _tz: usize) -> SyscallResult {
     let task = Task::from_weak(&task);
-    let time = from_user_ptr(&task, timeval_ptr);
+    let time = from_user_ptr(&task, timeval_ptr)?;
     timer::set_time_val(time);
     Ok(0)
 }
 
+const CLOCK_REALTIME: usize = 0;
+const CLOCK_MONOTONIC: usize = 1;
+const CLOCK_PROCESS_CPUTIME_ID: usize = 2;
+
+/// Write the time of `clock` to `tp`: the wall-clock time, the time since
+/// boot or the processor time of the task.
+fn sys_clock_gettime(task: &Weak<Task>, clock: usize, tp: usize) -> SyscallResult {
+    let task = Task::from_weak(&task);
+    let ns = match clock {
+        CLOCK_REALTIME => timer::get_realtime_ns(),
+        CLOCK_MONOTONIC => timer::get_time_ns(),
+        CLOCK_PROCESS_CPUTIME_ID => {
+            let times = task.inner_exclusive_access().times;
+            (times.utime_us + times.stime_us) * 1000
+        }
+        _ => return Err(Errno::EINVAL),
+    };
+    *from_user_ptr::<TimeSpec>(&task, tp)? = TimeSpec::from_ns(ns);
+    Ok(0)
+}
+
+/// unit of the times reported by `sys_times`, 100 ticks per second as on Linux
+const CLOCK_TICK_US: usize = 10_000;
+
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct Tms {
+    pub tms_utime: usize,
+    pub tms_stime: usize,
+    pub tms_cutime: usize,
+    pub tms_cstime: usize,
+}
+
+/// Copy the times of the task and its waited for children, in clock ticks,
+/// to `tms`. Returns the clock ticks since boot.
+fn sys_times(task: &Weak<Task>, tms: usize) -> SyscallResult {
+    let task = Task::from_weak(&task);
+    let (times, children_times) = {
+        let inner = task.inner_exclusive_access();
+        (inner.times, inner.children_times)
+    };
+    let user_tms: &mut Tms = from_user_ptr(&task, tms)?;
+    *user_tms = Tms {
+        tms_utime: times.utime_us / CLOCK_TICK_US,
+        tms_stime: times.stime_us / CLOCK_TICK_US,
+        tms_cutime: children_times.utime_us / CLOCK_TICK_US,
+        tms_cstime: children_times.stime_us / CLOCK_TICK_US,
+    };
+    Ok((get_time_us() / CLOCK_TICK_US) as isize)
+}
+
+const RUSAGE_SELF: isize = 0;
+const RUSAGE_CHILDREN: isize = -1;
+
+/// resource usage laid out like the `struct rusage` of Linux, fields the
+/// kernel does not keep track of stay 0
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct Rusage {
+    pub ru_utime: TimeVal,
+    pub ru_stime: TimeVal,
+    /// resident set size in KiB, the current one as no peak is kept
+    pub ru_maxrss: usize,
+    pub ru_ixrss: usize,
+    pub ru_idrss: usize,
+    pub ru_isrss: usize,
+    pub ru_minflt: usize,
+    pub ru_majflt: usize,
+    pub ru_nswap: usize,
+    pub ru_inblock: usize,
+    pub ru_oublock: usize,
+    pub ru_msgsnd: usize,
+    pub ru_msgrcv: usize,
+    pub ru_nsignals: usize,
+    pub ru_nvcsw: usize,
+    pub ru_nivcsw: usize,
+}
+
+fn us_to_time_val(us: usize) -> TimeVal {
+    TimeVal {
+        sec: us / 1_000_000,
+        usec: us % 1_000_000,
+    }
+}
+
+/// Copy the resource usage of the task, or of its waited for children if
+/// `who` is `RUSAGE_CHILDREN`, to `usage`.
+fn sys_getrusage(task: &Weak<Task>, who: isize, usage: usize) -> SyscallResult {
+    let task = Task::from_weak(&task);
+    let (times, maxrss) = {
+        let inner = task.inner_exclusive_access();
+        match who {
+            RUSAGE_SELF => (
+                inner.times,
+                inner.addr_space.resident_pages() * PAGE_SIZE / 1024,
+            ),
+            RUSAGE_CHILDREN => (inner.children_times, 0),
+            _ => return Err(Errno::EINVAL),
+        }
+    };
+    let user_usage: &mut Rusage = from_user_ptr(&task, usage)?;
+    *user_usage = Rusage {
+        ru_utime: us_to_time_val(times.utime_us),
+        ru_stime: us_to_time_val(times.stime_us),
+        ru_maxrss: maxrss,
+        ru_nvcsw: times.nvcsw,
+        ru_nivcsw: times.nivcsw,
+        ..Default::default()
+    };
+    Ok(0)
+}
+
 fn sys_yield(task: Arc<Task>) -> ! {
     {
         task.inner_exclusive_access().set_state(TaskState::Ready)
     }
-    switch_task(task)
+    switch_task(task, true)
 }
 
 pub fn sys_exit(task: Arc<Task>, exit_code: i32) -> ! {
-    {
-        let mut inner = task.inner_exclusive_access();
-        inner.set_state(TaskState::Exited);
-        inner.exit_code = exit_code;
-    }
+    fp_release(&task);
+    task.inner_exclusive_access().exit_code = exit_code;
     log::info!(
-        "{}, ready to exit, exit_code={}, Arc count={}",
+        "{}, ready to exit, exit_code={}, Arc count={}, swap_stat={:?}",
         task,
         exit_code,
-        Arc::strong_count(&task)
+        Arc::strong_count(&task),
+        task.inner_exclusive_access().addr_space.swap_stat()
     );
-    drop(task);
-    run_next_task()
+    // marked exited only once this hart is off its kernel stack
+    exit_task(task)
 }
 
+/// shared with user space
+#[repr(C)]
 #[derive(Debug)]
 pub struct TaskInfo {
     pub state: TaskState,
     pub syscall_times: [u32; MAX_SYSCALL_NUM],
+    /// wall time since the task was created, in ms
     pub exec_time: usize,
+    pub utime_us: usize,
+    pub stime_us: usize,
+    pub nvcsw: usize,
+    pub nivcsw: usize,
 }
 
 fn sys_taskinfo(task: &Weak<Task>, user_info: usize) -> SyscallResult {
     let task = Task::from_weak(&task);
-    let syscall_times = {
+    let (state, syscall_times, times) = {
         let inner = task.inner_exclusive_access();
-        inner.syscall_times
+        (inner.state, inner.syscall_times, inner.times)
     };
-    let taskinfo = from_user_ptr(&task, user_info);
+    let taskinfo = from_user_ptr(&task, user_info)?;
     *taskinfo = TaskInfo {
-        state: TaskState::Running,
+        state,
         syscall_times,
         exec_time: get_time_ms() - task.start_time_ms,
+        utime_us: times.utime_us,
+        stime_us: times.stime_us,
+        nvcsw: times.nvcsw,
+        nivcsw: times.nivcsw,
     };
     log::debug!(
         "task_{}({}) sys_taskinfo, copyout user_info={:?}",
@@ -200,6 +655,70 @@ fn sys_taskinfo(task: &Weak<Task>, user_info: usize) -> SyscallResult {
     Ok(0)
 }
 
+/// what `sys_proc_info` reports about a task
+#[repr(C)]
+#[derive(Debug)]
+pub struct ProcInfo {
+    pub pid: usize,
+    /// 0 if the parent is gone
+    pub ppid: usize,
+    /// name padded with 0
+    pub name: [u8; 32],
+    pub state: TaskState,
+    pub priority: usize,
+    pub pass: usize,
+    pub cpu_time_us: usize,
+    pub start_time_ms: usize,
+    pub resident_pages: usize,
+    /// the hart the task last ran on
+    pub hart: usize,
+    pub syscall_times: [u32; MAX_SYSCALL_NUM],
+}
+
+/// Write the pids of at most `count` tasks to `buf`. Returns the number of
+/// all tasks, which may be more than `count`.
+fn sys_list_tasks(task: &Weak<Task>, buf: usize, count: usize) -> SyscallResult {
+    let task = Task::from_weak(&task);
+    let pids = task_pids();
+    copy_to_user(&task, buf, as_bytes(&pids[..pids.len().min(count)]))?;
+    Ok(pids.len() as isize)
+}
+
+/// Copy the state of task `pid`, the caller itself if it is 0, to `info`.
+fn sys_proc_info(task: &Weak<Task>, pid: usize, info: usize) -> SyscallResult {
+    let task = Task::from_weak(&task);
+    let target = if pid == 0 {
+        Arc::clone(&task)
+    } else {
+        find_task(pid).ok_or(Errno::ESRCH)?
+    };
+    let mut name = [0; 32];
+    let len = target.name.len().min(name.len());
+    name[..len].copy_from_slice(&target.name.as_bytes()[..len]);
+    let inner = target.inner_exclusive_access();
+    let proc_info = ProcInfo {
+        pid: target.pid.0,
+        ppid: inner
+            .parent
+            .as_ref()
+            .and_then(Weak::upgrade)
+            .map_or(0, |parent| parent.pid.0),
+        name,
+        state: inner.state,
+        priority: inner.priority as usize,
+        pass: inner.pass,
+        // the caller is only charged for the current slice when it leaves
+        cpu_time_us: inner.times.utime_us + inner.times.stime_us,
+        start_time_ms: target.start_time_ms,
+        resident_pages: inner.addr_space.resident_pages(),
+        hart: inner.hart.unwrap_or(0),
+        syscall_times: inner.syscall_times,
+    };
+    drop(inner);
+    copy_to_user(&task, info, as_bytes(core::slice::from_ref(&proc_info)))?;
+    Ok(0)
+}
+
 fn sys_mmap(task: &Weak<Task>, start: usize, len: usize, port: usize) -> SyscallResult {
     let task = Task::from_weak(&task);
     log::info!(
@@ -218,7 +737,7 @@ fn sys_mmap(task: &Weak<Task>, start: usize, len: usize, port: usize) -> Syscall
             task.name,
             port
         );
-        return Err(());
+        return Err(Errno::EINVAL);
     }
     let perm = MapPermission::U
         | match port {
@@ -234,15 +753,18 @@ fn sys_mmap(task: &Weak<Task>, start: usize, len: usize, port: usize) -> Syscall
                     task.name,
                     port
                 );
-                return Err(());
+                return Err(Errno::EINVAL);
             }
         };
 
     let end = VirtAddr::from(start + len);
     let start = VirtAddr::from(start);
     if start.page_offset() != 0 {
-        return Err(());
+        return Err(Errno::EINVAL);
     };
+    if !check_wx(task.pid.0, start, perm, false) {
+        return Err(Errno::EACCES);
+    }
     let mut inner = task.inner_exclusive_access();
     inner
         .addr_space
@@ -250,6 +772,38 @@ fn sys_mmap(task: &Weak<Task>, start: usize, len: usize, port: usize) -> Syscall
         .map(|_| 0)
 }
 
+fn sys_mprotect(task: &Weak<Task>, start: usize, len: usize, prot: usize) -> SyscallResult {
+    let task = Task::from_weak(&task);
+    log::info!(
+        "task_{}({}) sys_mprotect, start=0x{:x}, len=0x{:x}, prot=0x{:x}",
+        task.pid,
+        task.name,
+        start,
+        len,
+        prot
+    );
+    // a page without any permission cannot be expressed as a leaf pte
+    if prot & !0x7 != 0 || prot == 0 {
+        return Err(Errno::EINVAL);
+    }
+    let mut perm = MapPermission::U;
+    if prot & 0x1 != 0 {
+        perm |= MapPermission::R;
+    }
+    // write-only is reserved in the pte encoding
+    if prot & 0x2 != 0 {
+        perm |= MapPermission::R | MapPermission::W;
+    }
+    if prot & 0x4 != 0 {
+        perm |= MapPermission::X;
+    }
+    let mut inner = task.inner_exclusive_access();
+    inner
+        .addr_space
+        .protect(task.pid.0, start.into(), (start + len).into(), perm)
+        .map(|_| 0)
+}
+
 fn sys_unmmap(task: &Weak<Task>, start: usize, len: usize) -> SyscallResult {
     let task = Task::from_weak(&task);
     log::info!(
@@ -262,7 +816,7 @@ fn sys_unmmap(task: &Weak<Task>, start: usize, len: usize) -> SyscallResult {
     let end = VirtAddr::from(start + len);
     let start = VirtAddr::from(start);
     if start.page_offset() != 0 {
-        return Err(());
+        return Err(Errno::EINVAL);
     }
     let mut inner = task.inner_exclusive_access();
     inner
@@ -293,7 +847,7 @@ fn sys_waitpid(task: &Weak<Task>, target_pid: isize, exit_code: usize) -> Syscal
                 .collect()
         };
         if target_children.is_empty() {
-            return Ok(-1);
+            return Err(Errno::ECHILD);
         }
         let exited_children: Vec<&Arc<Task>> = target_children
             .iter()
@@ -302,11 +856,12 @@ fn sys_waitpid(task: &Weak<Task>, target_pid: isize, exit_code: usize) -> Syscal
             .collect();
 
         if exited_children.is_empty() {
-            return Ok(-2);
+            return Err(Errno::EAGAIN);
         }
         (*exited_children.get(0).unwrap()).pid.clone()
     };
 
+    let exit_code: &mut i32 = from_user_ptr(&task, exit_code)?;
     let target_child = {
         let mut inner = task.inner_exclusive_access();
         let (idx, _) = inner
@@ -323,13 +878,16 @@ fn sys_waitpid(task: &Weak<Task>, target_pid: isize, exit_code: usize) -> Syscal
         Arc::strong_count(&target_child)
     );
 
-    let exit_code: &mut i32 = from_user_ptr(&task, exit_code);
-    *exit_code = {
+    let child_times = {
         let child_inner = target_child.inner_exclusive_access();
         assert!(target_child.pid == target_children_pid);
         assert!(child_inner.state == TaskState::Exited);
-        child_inner.exit_code
+        *exit_code = child_inner.exit_code;
+        let mut times = child_inner.times;
+        times += child_inner.children_times;
+        times
     };
+    task.inner_exclusive_access().children_times += child_times;
 
     Ok(target_children_pid.0 as isize)
 }
@@ -345,22 +903,24 @@ fn sys_read(task: Arc<Task>, fd: usize, buf: usize, len: usize) -> SyscallResult
     // let task = Task::from_weak(&task);
     if len != 1 {
         log::error!("{}, Only support len = 1 in sys_read! len={}", task, len);
-        return Err(());
+        return Err(Errno::EINVAL);
     }
     match fd {
         FD_STDIN => {
-            let c = console_getchar();
-            if c == 0 {
-                drop(task);
-                switch_task(pop_cur_task().unwrap());
-            }
-            let buffer: &mut [u8] = from_user_ptr_to_slice(&task, buf, len);
-            buffer[0] = c as u8;
+            let c = match console::getchar() {
+                Some(c) => c,
+                None => {
+                    drop(task);
+                    switch_task(pop_cur_task().unwrap(), true);
+                }
+            };
+            let buffer: &mut [u8] = from_user_ptr_to_slice(&task, buf, len)?;
+            buffer[0] = c;
             Ok(len as isize)
         }
         _ => {
             log::error!("{}, wrong fd? fd={}", task, fd);
-            Err(())
+            Err(Errno::EBADF)
         }
     }
 }
@@ -371,15 +931,58 @@ fn sys_set_priority(task: &Weak<Task>, priority: isize) -> SyscallResult {
         task.inner_exclusive_access().priority = priority as u32;
         Ok(priority)
     } else {
-        Err(())
+        Err(Errno::EINVAL)
     }
 }
 
+/// the task `pid`, or `task` itself if it is 0
+fn affinity_target(task: &Arc<Task>, pid: usize) -> Result<Arc<Task>, Errno> {
+    if pid == 0 {
+        Ok(Arc::clone(task))
+    } else {
+        find_task(pid).ok_or(Errno::ESRCH)
+    }
+}
+
+/// Restrict task `pid` to the harts in the mask at `mask`, `len` bytes
+/// long. Harts which are not online are dropped from the mask. A queued
+/// task moves to an allowed hart at once, a running one at its next switch.
+fn sys_sched_setaffinity(task: &Weak<Task>, pid: usize, len: usize, mask: usize) -> SyscallResult {
+    let task = Task::from_weak(&task);
+    if len < size_of::<usize>() {
+        return Err(Errno::EINVAL);
+    }
+    let mask = *from_user_ptr::<usize>(&task, mask)? & online_harts();
+    if mask == 0 {
+        return Err(Errno::EINVAL);
+    }
+    let target = affinity_target(&task, pid)?;
+    target.inner_exclusive_access().affinity = mask;
+    requeue(&target);
+    Ok(0)
+}
+
+/// Write the hart mask of task `pid` to `mask`, returns its size in bytes.
+fn sys_sched_getaffinity(task: &Weak<Task>, pid: usize, len: usize, mask: usize) -> SyscallResult {
+    let task = Task::from_weak(&task);
+    if len < size_of::<usize>() {
+        return Err(Errno::EINVAL);
+    }
+    let target = affinity_target(&task, pid)?;
+    let affinity = target.inner_exclusive_access().affinity & online_harts();
+    *from_user_ptr::<usize>(&task, mask)? = affinity;
+    Ok(size_of::<usize>() as isize)
+}
+
 fn sys_exec(task: &Weak<Task>, path: usize) -> SyscallResult {
     let task = Task::from_weak(&task);
-    let path = from_user_cstring(&task, path);
+    let path = from_user_cstring(&task, path)?;
     log::info!("sys_exec, {}, target app={}", task, path);
-    task.exec(&path)?;
+    let elf = get_app_elf(&path)?;
+    if let Err(err) = task.exec(&elf) {
+        log::info!("sys_exec, {}, cannot load {}: {:?}", task, path, err);
+        return Err(err.into());
+    }
     // drop(path);
     // drop(task);
     // restore(pop_cur_task().unwrap());
@@ -388,13 +991,153 @@ fn sys_exec(task: &Weak<Task>, path: usize) -> SyscallResult {
 
 fn sys_spawn(task: &Weak<Task>, path: usize) -> SyscallResult {
     let task = Task::from_weak(&task);
-    let path = from_user_cstring(&task, path);
+    let path = from_user_cstring(&task, path)?;
     log::info!("sys_spawn, {}, target app={}", task, path);
-    let child = Task::spawn(&path)?;
+    let elf = get_app_elf(&path)?;
+    let child = match Task::spawn(&path, &elf) {
+        Ok(child) => child,
+        Err(err) => {
+            log::info!("sys_spawn, {}, cannot load {}: {:?}", task, path, err);
+            return Err(err.into());
+        }
+    };
     let child_pid = child.pid.0;
+    child.inner_exclusive_access().parent = Some(Arc::downgrade(&task));
     task.inner_exclusive_access()
         .children
         .push(Arc::clone(&child));
     add_task(child);
     Ok(child_pid as isize)
 }
+
+fn sys_swap_stat(task: &Weak<Task>, user_stat: usize) -> SyscallResult {
+    let task = Task::from_weak(&task);
+    let swap_stat = task.inner_exclusive_access().addr_space.swap_stat();
+    let stat: &mut SwapStat = from_user_ptr(&task, user_stat)?;
+    *stat = swap_stat;
+    Ok(0)
+}
+
+fn sys_shmget(task: &Weak<Task>, key: usize, size: usize, flags: usize) -> SyscallResult {
+    let task = Task::from_weak(&task);
+    let id = shm_get(key, size, flags)?;
+    log::info!(
+        "{} sys_shmget, key={}, size=0x{:x}, shm_id={}",
+        task,
+        key,
+        size,
+        id
+    );
+    Ok(id as isize)
+}
+
+fn sys_shmat(task: &Weak<Task>, shm_id: usize, addr: usize, flags: usize) -> SyscallResult {
+    let task = Task::from_weak(&task);
+    let perm = if flags & SHM_RDONLY != 0 {
+        MapPermission::U | MapPermission::R
+    } else {
+        MapPermission::U | MapPermission::R | MapPermission::W
+    };
+    let start = task
+        .inner_exclusive_access()
+        .addr_space
+        .insert_shared_area(VirtAddr::from(addr), shm_id, perm)?;
+    log::info!("{} sys_shmat, shm_id={}, attach at {:?}", task, shm_id, start);
+    Ok(start.0 as isize)
+}
+
+fn sys_shmdt(task: &Weak<Task>, addr: usize) -> SyscallResult {
+    let task = Task::from_weak(&task);
+    log::info!("{} sys_shmdt, addr=0x{:x}", task, addr);
+    let mut inner = task.inner_exclusive_access();
+    inner
+        .addr_space
+        .remove_shared_area(VirtAddr::from(addr))
+        .map(|_| 0)
+}
+
+/// Returns the new program break, or the current one if `addr` is 0 or
+/// the break cannot be moved there.
+fn sys_brk(task: &Weak<Task>, addr: usize) -> SyscallResult {
+    let task = Task::from_weak(&task);
+    let mut inner = task.inner_exclusive_access();
+    let brk = if addr == 0 {
+        inner.addr_space.brk()
+    } else {
+        let old_brk = inner.addr_space.brk();
+        inner.addr_space.set_brk(addr).unwrap_or(old_brk)
+    };
+    log::info!("task_{} sys_brk, addr=0x{:x}, brk=0x{:x}", task.pid, addr, brk);
+    Ok(brk as isize)
+}
+
+/// Returns the old program break.
+fn sys_sbrk(task: &Weak<Task>, increment: isize) -> SyscallResult {
+    let task = Task::from_weak(&task);
+    let mut inner = task.inner_exclusive_access();
+    let old_brk = inner.addr_space.brk();
+    let new_brk = if increment >= 0 {
+        old_brk.checked_add(increment as usize)
+    } else {
+        old_brk.checked_sub(increment.unsigned_abs())
+    }
+    .ok_or(Errno::ENOMEM)?;
+    log::info!(
+        "task_{} sys_sbrk, increment={}, brk=0x{:x}",
+        task.pid,
+        increment,
+        old_brk
+    );
+    inner.addr_space.set_brk(new_brk)?;
+    Ok(old_brk as isize)
+}
+
+/// Set the trace mask of task `pid`, which is the caller itself if `pid`
+/// is 0 or one of its children. Returns the old mask.
+fn sys_trace(task: &Weak<Task>, pid: usize, mask: usize) -> SyscallResult {
+    let task = Task::from_weak(&task);
+    if mask & !TRACE_ALL != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let target = if pid == 0 || pid == task.pid.0 {
+        Arc::clone(&task)
+    } else {
+        task.inner_exclusive_access()
+            .children
+            .iter()
+            .find(|child| child.pid.0 == pid)
+            .cloned()
+            .ok_or(Errno::ESRCH)?
+    };
+    log::info!("{} sys_trace, pid={}, mask=0x{:x}", task, target.pid, mask);
+    let mut inner = target.inner_exclusive_access();
+    let old_mask = inner.trace_mask;
+    inner.trace_mask = mask;
+    Ok(old_mask as isize)
+}
+
+/// pid of the parent of `task`, 0 if it has none
+fn parent_pid(task: &Weak<Task>) -> usize {
+    let task = Task::from_weak(task);
+    let parent = task.inner_exclusive_access().parent.clone();
+    parent
+        .and_then(|parent| parent.upgrade())
+        .map_or(0, |parent| parent.pid.0)
+}
+
+/// Copy at most `count` trace records of task `pid`, or of every task if
+/// `pid` is 0, starting at sequence number `cursor` to `buf`. Like
+/// `sys_trace`, only the caller itself and its children, reaped ones
+/// included, can be read. Returns the number of records copied.
+fn sys_trace_read(
+    task: &Weak<Task>,
+    pid: usize,
+    cursor: usize,
+    buf: usize,
+    count: usize,
+) -> SyscallResult {
+    let task = Task::from_weak(&task);
+    let records = trace_read(task.pid.0, pid, cursor, count.min(TRACE_BUFFER_LEN));
+    copy_to_user(&task, buf, as_bytes(&records))?;
+    Ok(records.len() as isize)
+}
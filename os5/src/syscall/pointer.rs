@@ -1,57 +1,51 @@
-use alloc::{format, string::String, sync::Arc};
+use alloc::{string::String, sync::Arc, vec::Vec};
 
-use crate::task::Task;
+use crate::{config::PAGE_SIZE, errno::Errno, task::Task};
 
-pub fn from_user_ptr_to_str(task: &Arc<Task>, buf: usize, len: usize) -> &'static str {
-    let buf = task
-        .inner_exclusive_access()
-        .translate(buf)
-        .expect(&format!(
-            "task_{}, task_name={}, receive bad user buffer addr? user_buf_addr=0x{:x}",
-            task.pid, task.name, buf
-        ));
+/// translate `user_addr` of `task`, a bad address is `EFAULT`
+fn translate(task: &Arc<Task>, user_addr: usize) -> Result<usize, Errno> {
+    task.inner_exclusive_access()
+        .translate(user_addr)
+        .ok_or_else(|| {
+            log::warn!(
+                "task_{}, task_name={}, receive bad user addr? user_addr=0x{:x}",
+                task.pid,
+                task.name,
+                user_addr
+            );
+            Errno::EFAULT
+        })
+}
 
+pub fn from_user_ptr_to_str(
+    task: &Arc<Task>,
+    buf: usize,
+    len: usize,
+) -> Result<&'static str, Errno> {
+    let buf = translate(task, buf)?;
     let slice = unsafe { core::slice::from_raw_parts(buf as *const u8, len) };
-    core::str::from_utf8(slice).unwrap()
+    core::str::from_utf8(slice).map_err(|_| Errno::EINVAL)
 }
 
-pub fn from_user_ptr_to_slice<T>(task: &Arc<Task>, buf: usize, len: usize) -> &'static mut [T]
+pub fn from_user_ptr_to_slice<T>(
+    task: &Arc<Task>,
+    buf: usize,
+    len: usize,
+) -> Result<&'static mut [T], Errno>
 where
     T: Sized,
 {
-    let buf = task
-        .inner_exclusive_access()
-        .translate(buf)
-        .expect(&format!(
-            "task_{}, task_name={}, receive bad user buffer addr? user_buf_addr=0x{:x}",
-            task.pid, task.name, buf
-        ));
-
-    unsafe { core::slice::from_raw_parts_mut(buf as *mut T, len) }
+    let buf = translate(task, buf)?;
+    unsafe { Ok(core::slice::from_raw_parts_mut(buf as *mut T, len)) }
 }
 
-pub fn from_user_ptr<T>(task: &Arc<Task>, user_addr: usize) -> &'static mut T {
-    let phy_addr = task
-        .inner_exclusive_access()
-        .translate(user_addr)
-        .expect(&format!(
-            "task_{}, task_name={}, receive bad user addr? user_addr=0x{:x}",
-            task.pid, task.name, user_addr
-        ));
-
-    unsafe { &mut *(phy_addr as *mut T) }
+pub fn from_user_ptr<T>(task: &Arc<Task>, user_addr: usize) -> Result<&'static mut T, Errno> {
+    let phy_addr = translate(task, user_addr)?;
+    unsafe { Ok(&mut *(phy_addr as *mut T)) }
 }
 
-pub fn from_user_cstring(task: &Arc<Task>, user_addr: usize) -> String {
-    // let mut user_addr = user_addr;
-    let mut phy_addr = task
-        .inner_exclusive_access()
-        .translate(user_addr)
-        .expect(&format!(
-            "task_{}, task_name={}, receive bad user addr? user_addr=0x{:x}",
-            task.pid, task.name, user_addr
-        ));
-
+pub fn from_user_cstring(task: &Arc<Task>, user_addr: usize) -> Result<String, Errno> {
+    let mut phy_addr = translate(task, user_addr)?;
     let mut s = String::new();
     loop {
         unsafe {
@@ -63,5 +57,44 @@ pub fn from_user_cstring(task: &Arc<Task>, user_addr: usize) -> String {
             s.push(val as char);
         }
     }
-    s
+    Ok(s)
+}
+
+/// the pieces of the user buffer [buf, buf + len) in each page it spans
+fn from_user_buffer(
+    task: &Arc<Task>,
+    buf: usize,
+    len: usize,
+) -> Result<Vec<&'static mut [u8]>, Errno> {
+    let mut pieces = Vec::new();
+    let (mut start, end) = (buf, buf + len);
+    while start < end {
+        let piece_end = ((start / PAGE_SIZE + 1) * PAGE_SIZE).min(end);
+        let phy_addr = translate(task, start)?;
+        pieces.push(unsafe {
+            core::slice::from_raw_parts_mut(phy_addr as *mut u8, piece_end - start)
+        });
+        start = piece_end;
+    }
+    Ok(pieces)
+}
+
+/// copy `data` to the user buffer at `buf`, which may span several pages
+pub fn copy_to_user(task: &Arc<Task>, buf: usize, data: &[u8]) -> Result<(), Errno> {
+    let mut copied = 0;
+    for piece in from_user_buffer(task, buf, data.len())? {
+        piece.copy_from_slice(&data[copied..copied + piece.len()]);
+        copied += piece.len();
+    }
+    Ok(())
+}
+
+/// the bytes of `values`, to be copied to user space
+pub fn as_bytes<T: Sized>(values: &[T]) -> &[u8] {
+    unsafe {
+        core::slice::from_raw_parts(
+            values.as_ptr() as *const u8,
+            values.len() * core::mem::size_of::<T>(),
+        )
+    }
 }
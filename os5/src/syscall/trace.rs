@@ -0,0 +1,100 @@
+//! Tracing of syscalls.
+//!
+//! A task whose trace mask matches the class of a syscall gets a record of
+//! the call appended to a global ring buffer, the oldest records are
+//! overwritten once it is full. Every record carries a sequence number, a
+//! reader passes the number it has read up to and gets the newer ones.
+
+use crate::config::TRACE_BUFFER_LEN;
+use alloc::{collections::VecDeque, vec::Vec};
+use lazy_static::*;
+use spin::Mutex;
+
+/// exit, yield, fork, exec, spawn, waitpid, getpid, set_priority
+pub const TRACE_PROCESS: usize = 1 << 0;
+/// mmap, munmap, mprotect, brk, sbrk and shared memory
+pub const TRACE_MEMORY: usize = 1 << 1;
+/// read and write
+pub const TRACE_IO: usize = 1 << 2;
+/// gettimeofday, times and getrusage
+pub const TRACE_TIME: usize = 1 << 3;
+/// task info, swap stat and tracing itself
+pub const TRACE_INFO: usize = 1 << 4;
+pub const TRACE_ALL: usize = TRACE_PROCESS | TRACE_MEMORY | TRACE_IO | TRACE_TIME | TRACE_INFO;
+
+/// a traced syscall, shared with user space
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TraceRecord {
+    pub seq: usize,
+    pub pid: usize,
+    /// syscall number
+    pub id: usize,
+    /// syscall name, padded with 0
+    pub name: [u8; 16],
+    /// number of valid entries in `args`
+    pub nargs: usize,
+    /// arguments in the order they are decoded
+    pub args: [usize; 6],
+    pub ret: isize,
+    pub start_us: usize,
+    pub duration_us: usize,
+}
+
+impl TraceRecord {
+    pub fn new(pid: usize, id: usize, name: &str, args: &[usize], start_us: usize) -> Self {
+        let mut record = Self {
+            pid,
+            id,
+            nargs: args.len(),
+            start_us,
+            ..Default::default()
+        };
+        let len = name.len().min(record.name.len());
+        record.name[..len].copy_from_slice(&name.as_bytes()[..len]);
+        record.args[..args.len()].copy_from_slice(args);
+        record
+    }
+}
+
+struct TraceBuffer {
+    /// the records with the pid of the parent of the traced task, which may
+    /// read them even after the task has been reaped
+    records: VecDeque<(usize, TraceRecord)>,
+    next_seq: usize,
+}
+
+lazy_static! {
+    static ref TRACE_BUFFER: Mutex<TraceBuffer> = Mutex::new(TraceBuffer {
+        records: VecDeque::with_capacity(TRACE_BUFFER_LEN),
+        next_seq: 0,
+    });
+}
+
+/// append `record` of a task whose parent is `parent` to the ring buffer,
+/// assigning its sequence number
+pub fn trace_push(mut record: TraceRecord, parent: usize) {
+    let mut buffer = TRACE_BUFFER.lock();
+    record.seq = buffer.next_seq;
+    buffer.next_seq += 1;
+    if buffer.records.len() == TRACE_BUFFER_LEN {
+        buffer.records.pop_front();
+    }
+    buffer.records.push_back((parent, record));
+}
+
+/// At most `count` records of task `pid`, or of every task if `pid` is 0,
+/// whose sequence number is not below `cursor`. Only the records of
+/// `reader` itself and of its children are read.
+pub fn trace_read(reader: usize, pid: usize, cursor: usize, count: usize) -> Vec<TraceRecord> {
+    TRACE_BUFFER
+        .lock()
+        .records
+        .iter()
+        .filter(|(parent, record)| *parent == reader || record.pid == reader)
+        .map(|(_, record)| record)
+        .filter(|record| record.seq >= cursor && (pid == 0 || record.pid == pid))
+        .take(count)
+        .copied()
+        .collect()
+}
@@ -1,47 +1,216 @@
-use crate::task::{Task, TaskState};
-use alloc::{collections::VecDeque, sync::Arc};
+//! Run queues, one for each hart.
+//!
+//! A task is queued on the hart it last ran on if its affinity allows it,
+//! otherwise on the allowed hart with the shortest queue. An idle hart
+//! steals from the longest queue, and every `LOAD_BALANCE_TICKS` timer
+//! ticks a hart pulls a task from a queue which is at least two tasks
+//! longer than its own. A queued task whose affinity changes moves to a
+//! queue it allows.
+//!
+//! Under the default stride policy a hart runs the queued task with the
+//! smallest pass, under round robin the one queued first.
+//!
+//! Each queue has a virtual time, the largest pass it has dispatched. A
+//! task moving to another queue keeps its distance to the virtual time, so
+//! pass values stay comparable across queues.
+
+use crate::{
+    config::MAX_HARTS,
+    smp::{kick, online_harts},
+    sync::SpinLock,
+    task::Task,
+};
+use alloc::{collections::VecDeque, sync::Arc, vec::Vec};
 use lazy_static::lazy_static;
 use spin::Mutex;
 
+/// how a hart picks the next task from its queue
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum SchedPolicy {
+    Stride,
+    RoundRobin,
+}
+
+static SCHED_POLICY: Mutex<SchedPolicy> = Mutex::new(SchedPolicy::Stride);
+
+pub fn set_sched_policy(policy: SchedPolicy) {
+    *SCHED_POLICY.lock() = policy;
+}
+
 lazy_static! {
-    pub static ref TM: Mutex<TaskManager> = Mutex::new(TaskManager::new());
+    static ref RUN_QUEUES: Vec<SpinLock<TaskManager>> = (0..MAX_HARTS)
+        .map(|_| SpinLock::new(TaskManager::new()))
+        .collect();
 }
 
 pub struct TaskManager {
-    pub next_task: usize,
     task_list: VecDeque<Arc<Task>>,
+    /// largest pass dispatched from this queue
+    vtime: usize,
 }
 
 impl TaskManager {
     fn new() -> Self {
         Self {
             task_list: VecDeque::new(),
-            next_task: 0,
+            vtime: 0,
         }
     }
 
-    pub fn find_next_ready_task(&mut self) -> Option<Arc<Task>> {
-        if self.task_list.is_empty() {
-            return None;
-        }
-        let mut smallest_pass_idx = 0;
-        let mut smallest_pass = self.task_list[0].inner_exclusive_access().pass;
-        for i in 1..self.task_list.len() {
-            let pass = self.task_list[i].inner_exclusive_access().pass;
-            if pass < smallest_pass {
-                smallest_pass_idx = i;
-                smallest_pass = pass;
+    /// the task which may run on `hart` with the smallest pass, or the
+    /// first one under round robin
+    fn find_next_ready_task(&mut self, hart: usize) -> Option<Arc<Task>> {
+        let round_robin = *SCHED_POLICY.lock() == SchedPolicy::RoundRobin;
+        let mut smallest: Option<(usize, usize)> = None;
+        for (i, task) in self.task_list.iter().enumerate() {
+            let inner = task.inner_exclusive_access();
+            if inner.affinity & (1 << hart) == 0 {
+                continue;
+            }
+            if smallest.map_or(true, |(_, pass)| inner.pass < pass) {
+                smallest = Some((i, inner.pass));
+                if round_robin {
+                    break;
+                }
             }
         }
-
+        let (idx, pass) = smallest?;
+        self.vtime = self.vtime.max(pass);
         Some(
             self.task_list
-                .remove(smallest_pass_idx)
+                .remove(idx)
                 .expect("wrong smallest_pass_idx?"),
         )
     }
 
-    pub fn add_task(&mut self, task: Arc<Task>) {
+    /// Take the last queued task which may run on `hart`, its pass made
+    /// relative to the virtual time of this queue.
+    fn steal(&mut self, hart: usize) -> Option<Arc<Task>> {
+        let idx = self
+            .task_list
+            .iter()
+            .rposition(|task| task.inner_exclusive_access().affinity & (1 << hart) != 0)?;
+        let task = self.task_list.remove(idx)?;
+        {
+            let mut inner = task.inner_exclusive_access();
+            inner.pass = inner.pass.saturating_sub(self.vtime);
+        }
+        Some(task)
+    }
+
+    fn add_task(&mut self, task: Arc<Task>) {
         self.task_list.push_back(task);
     }
+
+    fn remove(&mut self, task: &Arc<Task>) -> Option<Arc<Task>> {
+        let idx = self
+            .task_list
+            .iter()
+            .position(|queued| Arc::ptr_eq(queued, task))?;
+        self.task_list.remove(idx)
+    }
+
+    /// Take out the tasks whose affinity excludes `hart` but allows another
+    /// online hart.
+    fn take_excluded(&mut self, hart: usize) -> Vec<Arc<Task>> {
+        let mut excluded = Vec::new();
+        self.task_list.retain(|task| {
+            let affinity = task.inner_exclusive_access().affinity;
+            let keep = affinity & (1 << hart) != 0 || affinity & online_harts() == 0;
+            if !keep {
+                excluded.push(Arc::clone(task));
+            }
+            keep
+        });
+        excluded
+    }
+
+    fn len(&self) -> usize {
+        self.task_list.len()
+    }
+}
+
+/// Queue `task` on a hart its affinity allows, see the module docs.
+pub fn enqueue(task: Arc<Task>) {
+    let (allowed, last_hart) = {
+        let inner = task.inner_exclusive_access();
+        (inner.affinity & online_harts(), inner.hart)
+    };
+    let hart = match last_hart {
+        Some(hart) if allowed & (1 << hart) != 0 => hart,
+        _ => (0..MAX_HARTS)
+            .filter(|hart| allowed & (1 << hart) != 0)
+            .min_by_key(|&hart| RUN_QUEUES[hart].lock().len())
+            .or(last_hart)
+            .unwrap_or(0),
+    };
+    if last_hart != Some(hart) {
+        // new tasks start at the virtual time, moved ones keep their distance
+        let from = last_hart.map_or(0, |last| RUN_QUEUES[last].lock().vtime);
+        let to = RUN_QUEUES[hart].lock().vtime;
+        let mut inner = task.inner_exclusive_access();
+        inner.pass = inner.pass.saturating_sub(from) + to;
+        inner.hart = Some(hart);
+    }
+    RUN_QUEUES[hart].lock().add_task(task);
+    kick(hart);
+}
+
+/// The next task of the queue of `hart`. Tasks which may not run there
+/// any more move to another queue.
+pub fn dequeue(hart: usize) -> Option<Arc<Task>> {
+    let (task, excluded) = {
+        let mut queue = RUN_QUEUES[hart].lock();
+        (queue.find_next_ready_task(hart), queue.take_excluded(hart))
+    };
+    for task in excluded {
+        enqueue(task);
+    }
+    task
+}
+
+/// Move `task` to another queue if it waits on a hart its affinity has
+/// just excluded. A running task moves once it is queued again, one
+/// which another hart is pulling right now once that hart dequeues.
+pub fn requeue(task: &Arc<Task>) {
+    let hart = {
+        let inner = task.inner_exclusive_access();
+        match inner.hart {
+            Some(hart) if inner.affinity & (1 << hart) == 0 => hart,
+            _ => return,
+        }
+    };
+    let removed = RUN_QUEUES[hart].lock().remove(task);
+    if let Some(task) = removed {
+        enqueue(task);
+    }
+}
+
+/// Move a task from the longest other queue which is at least `imbalance`
+/// tasks longer than the one of `hart` and has a task allowed to run on
+/// it. Returns whether a task moved.
+pub fn pull_task(hart: usize, imbalance: usize) -> bool {
+    let local = RUN_QUEUES[hart].lock().len();
+    let mut others: Vec<(usize, usize)> = (0..MAX_HARTS)
+        .filter(|&other| other != hart)
+        .map(|other| (other, RUN_QUEUES[other].lock().len()))
+        .filter(|&(_, len)| len >= local + imbalance)
+        .collect();
+    others.sort_unstable_by_key(|&(_, len)| usize::MAX - len);
+    for (other, _) in others {
+        let task = match RUN_QUEUES[other].lock().steal(hart) {
+            Some(task) => task,
+            None => continue,
+        };
+        log::debug!("hart {} pulls task_{} from hart {}", hart, task.pid, other);
+        let mut queue = RUN_QUEUES[hart].lock();
+        {
+            let mut inner = task.inner_exclusive_access();
+            inner.pass += queue.vtime;
+            inner.hart = Some(hart);
+        }
+        queue.add_task(task);
+        return true;
+    }
+    false
 }
@@ -2,70 +2,333 @@ mod kernel_stack;
 mod manager;
 mod pid;
 mod processor;
+mod registry;
 mod task;
 
 use alloc::sync::{Arc, Weak};
+use core::sync::atomic::{AtomicI32, AtomicUsize, Ordering};
+use spin::Once;
 
 use crate::{
-    config::BIG_STRIDE,
-    task::{manager::TM, processor::processor_inner},
-    trap::restore,
+    config::{BIG_STRIDE, LOAD_BALANCE_TICKS},
+    drivers::{poweroff, task_exit_status},
+    mm::{heap_stats, kmem_cache_create, slab_stats, SlabCache},
+    smp::{hart_id, idle_stack_top, set_idle},
+    sync::holds_spinlock,
+    task::{
+        manager::{dequeue, enqueue, pull_task},
+        processor::processor_inner,
+    },
+    timer::set_next_trigger,
+    trap::{poll_external_interrupt, restore, wait_for_interrupt},
     BATCH_PROCESSING_TASK,
 };
 pub use {
+    manager::{requeue, set_sched_policy, SchedPolicy},
     pid::{alloc_pid, PidHandle},
+    registry::{find_task, register_task, task_pids},
     task::{fork_task, Task, TaskInner, TaskState},
 };
 
+core::arch::global_asm!(include_str!("switch.S"));
+extern "C" {
+    fn __suspend(idle_sp: usize, idle: usize);
+    fn __resume(kernel_sp: usize) -> !;
+}
+
+/// the slab cache every `Arc<Task>` is allocated from
+static TASK_CACHE: Once<SlabCache<Task>> = Once::new();
+
+/// create the slab cache of the tasks
+pub fn init() {
+    TASK_CACHE.call_once(|| kmem_cache_create("task", None).expect("no room for task cache"));
+}
+
+/// Move `task` into an `Arc` from the task cache, or from the heap before
+/// [`init`].
+fn alloc_task(task: Task) -> Arc<Task> {
+    match TASK_CACHE.get() {
+        Some(cache) => cache.new_arc(task),
+        None => Arc::new(task),
+    }
+}
+
 // 将初始进程加入任务管理器.
 #[allow(dead_code)]
 pub fn add_initproc() {
-    TM.lock().add_task(Task::new("ch5b_initproc"))
+    add_task(Task::new("ch5b_initproc"))
 }
 
+/// Number of tasks which are queued, running or parked. A task moving
+/// between a hart and a queue keeps being counted, so an idle hart which
+/// sees none knows that all tasks are done.
+static RUNNABLE_TASKS: AtomicUsize = AtomicUsize::new(0);
+
+/// pid of the task [`swap_out_ready_task`] last took a page from
+static SWAP_HAND: AtomicUsize = AtomicUsize::new(0);
+
+/// Exit code of the tasks started by the kernel, the first one which is not
+/// 0. QEMU exits with it, as [`task_exit_status`] maps it, once all tasks
+/// are done.
+static INIT_EXIT_CODE: AtomicI32 = AtomicI32::new(0);
+
+/// queue a new task
 pub fn add_task(task: Arc<Task>) {
-    TM.lock().add_task(task)
+    RUNNABLE_TASKS.fetch_add(1, Ordering::SeqCst);
+    enqueue(task)
 }
 
-pub fn fetch_ready_task() -> Arc<Task> {
-    let mut task_manager = TM.lock();
-    let task = match task_manager.find_next_ready_task() {
-        None => {
-            let mut batch_tasks = BATCH_PROCESSING_TASK.exclusive_access();
-            while batch_tasks.len() > 0 {
-                batch_tasks.pop();
-            }
-            panic!("all task complete!");
-        }
-        Some(task) => task,
-    };
-
-    task
+/// Take the next task to run on this hart, stealing one from another hart
+/// if its own queue is empty. `None` if there is none for now.
+pub fn fetch_ready_task() -> Option<Arc<Task>> {
+    let hart = hart_id();
+    if let Some(task) = dequeue(hart) {
+        return Some(task);
+    }
+    if pull_task(hart, 1) {
+        return dequeue(hart);
+    }
+    if RUNNABLE_TASKS.load(Ordering::SeqCst) > 0 {
+        return None;
+    }
+    let mut batch_tasks = BATCH_PROCESSING_TASK.lock();
+    while batch_tasks.len() > 0 {
+        batch_tasks.pop();
+    }
+    let stats = heap_stats();
+    log::info!(
+        "kernel heap: total={} allocated={} peak={} fragmentation={}%",
+        stats.total,
+        stats.allocated,
+        stats.peak,
+        stats.fragmentation()
+    );
+    slab_stats(|name, stats| log::info!("slab cache {}: {:?}", name, stats));
+    let exit_code = INIT_EXIT_CODE.load(Ordering::SeqCst);
+    println!("[kernel] all task complete! exit code {}", exit_code);
+    poweroff(task_exit_status(exit_code))
 }
 
 pub fn run_task(task: Arc<Task>) -> ! {
-    processor_inner().cur_task = Some(Arc::clone(&task));
-    restore(task)
+    let mut processor = processor_inner();
+    processor.fp_prepare_run(&task);
+    processor.run(Arc::clone(&task));
+    drop(processor);
+    // a task which left the hart in the kernel goes on there
+    let kernel_sp = task.inner_exclusive_access().kernel_sp.take();
+    match kernel_sp {
+        Some(kernel_sp) => {
+            drop(task);
+            unsafe { __resume(kernel_sp) }
+        }
+        None => restore(task),
+    }
 }
 
 pub fn run_next_task() -> ! {
-    let task = fetch_ready_task();
+    schedule(None)
+}
 
-    log::info!(
-        "will run next task, task_pid={}, task_name={}",
-        &task.pid,
-        &task.name
-    );
-    run_task(task)
+/// Leave the kernel stack of the current task for the idle stack of this
+/// hart and run the next task from there. `leaving` gets its state only
+/// then, before that another hart could run it on the same kernel stack or
+/// its parent could free the stack.
+fn schedule(leaving: Option<(Arc<Task>, TaskState)>) -> ! {
+    processor_inner().leaving = leaving;
+    unsafe {
+        core::arch::asm!(
+            "mv sp, {sp}",
+            "jr {idle}",
+            sp = in(reg) idle_stack_top(),
+            idle = in(reg) idle_loop as usize,
+            in("a0") 0usize,
+            options(noreturn)
+        );
+    }
+}
+
+/// Leave the hart like [`schedule`], but keep the kernel stack of `task`,
+/// the current one, to return from here once [`run_task`] picks it again.
+/// The hart must not hold a spinlock.
+fn suspend(task: Arc<Task>, state: TaskState) {
+    processor_inner().leaving = Some((task, state));
+    unsafe { __suspend(idle_stack_top(), idle_loop as usize) }
+}
+
+/// Runs on the idle stack, hands off the task this hart left and waits for
+/// a ready task. `kernel_sp` is where [`suspend`] left the kernel stack of
+/// the task, 0 if it was abandoned.
+extern "C" fn idle_loop(kernel_sp: usize) -> ! {
+    let leaving = {
+        let mut processor = processor_inner();
+        processor.pop_task();
+        processor.leaving.take()
+    };
+    if let Some((task, state)) = leaving {
+        // another hart may run or steal the task once it is queued, and
+        // load fp from its context then
+        fp_flush(&task);
+        if state == TaskState::Exited {
+            fp_release(&task);
+        }
+        let state = {
+            let mut inner = task.inner_exclusive_access();
+            if kernel_sp != 0 {
+                inner.kernel_sp = Some(kernel_sp);
+            }
+            // unparked before it was off the hart
+            let state = match state {
+                TaskState::Blocked if core::mem::take(&mut inner.unparked) => TaskState::Ready,
+                state => state,
+            };
+            inner.set_state(state);
+            state
+        };
+        match state {
+            TaskState::Ready => enqueue(task),
+            // whoever unparks it holds on to it
+            TaskState::Blocked => drop(task),
+            _ => {
+                drop(task);
+                RUNNABLE_TASKS.fetch_sub(1, Ordering::SeqCst);
+            }
+        }
+    }
+    loop {
+        // a parked task may wait for one
+        poll_external_interrupt();
+        // before looking, so that a task queued meanwhile sends an IPI
+        set_idle(true);
+        if let Some(task) = fetch_ready_task() {
+            set_idle(false);
+            log::info!(
+                "hart {} will run next task, task_pid={}, task_name={}",
+                hart_id(),
+                &task.pid,
+                &task.name
+            );
+            run_task(task)
+        }
+        // woken by an IPI when a task is queued here, or by the timer to
+        // look for tasks to steal
+        if wait_for_interrupt() {
+            set_next_trigger();
+        }
+    }
 }
 
-pub fn switch_task(previous_task: Arc<Task>) -> ! {
+/// Put `previous_task` back to the ready queue and run the next task,
+/// `voluntary` tells whether it yields or is preempted.
+pub fn switch_task(previous_task: Arc<Task>, voluntary: bool) -> ! {
     {
         let mut inner = previous_task.inner_exclusive_access();
         inner.pass += BIG_STRIDE / (inner.priority as usize);
+        if voluntary {
+            inner.times.nvcsw += 1;
+        } else {
+            inner.times.nivcsw += 1;
+        }
+    }
+    schedule(Some((previous_task, TaskState::Ready)))
+}
+
+/// The current task if it may park, which it may not while the hart holds
+/// a spinlock.
+pub fn parkable_task() -> Option<Arc<Task>> {
+    if holds_spinlock() {
+        return None;
+    }
+    processor_inner().cur_task.clone()
+}
+
+/// Park `task`, which is the current one and got from [`parkable_task`],
+/// until [`unpark`] is called for it. The hart runs other tasks meanwhile.
+pub fn park(task: Arc<Task>) {
+    task.inner_exclusive_access().times.nvcsw += 1;
+    suspend(task, TaskState::Blocked)
+}
+
+/// Make a task ready which has parked, or keep it from parking if it has
+/// not yet.
+pub fn unpark(task: Arc<Task>) {
+    let mut inner = task.inner_exclusive_access();
+    if inner.state == TaskState::Blocked {
+        inner.set_state(TaskState::Ready);
+        drop(inner);
+        enqueue(task);
+    } else {
+        inner.unparked = true;
+    }
+}
+
+/// Let the other tasks run before the current one goes on, for a lock
+/// which may be held by a parked task. Spins where the task may not park.
+pub fn relax() {
+    match parkable_task() {
+        Some(task) => {
+            task.inner_exclusive_access().times.nvcsw += 1;
+            suspend(task, TaskState::Ready)
+        }
+        None => core::hint::spin_loop(),
+    }
+}
+
+/// Count a timer tick of this hart, every `LOAD_BALANCE_TICKS` ticks it
+/// pulls a task from a much longer queue.
+pub fn balance_tick() {
+    let ticks = {
+        let mut processor = processor_inner();
+        processor.ticks += 1;
+        processor.ticks
+    };
+    if ticks % LOAD_BALANCE_TICKS == 0 {
+        pull_task(hart_id(), 2);
     }
-    add_task(previous_task);
-    run_next_task();
+}
+
+/// Swap out a page of a task waiting in a queue, for an address space
+/// which has no page of its own left to give. Tasks take turns in pid
+/// order. Running tasks, and those which left the hart in the middle of
+/// the kernel, may hold pointers into their pages and are skipped, as are
+/// tasks whose lock is held.
+pub fn swap_out_ready_task() -> bool {
+    let pids = task_pids();
+    let hand = SWAP_HAND.load(Ordering::Relaxed);
+    let (behind, ahead) = pids.split_at(pids.partition_point(|&pid| pid <= hand));
+    for &pid in ahead.iter().chain(behind) {
+        let task = match find_task(pid) {
+            Some(task) => task,
+            None => continue,
+        };
+        let mut inner = match task.try_inner_exclusive_access() {
+            Some(inner) => inner,
+            None => continue,
+        };
+        if inner.state == TaskState::Ready
+            && inner.kernel_sp.is_none()
+            && inner.addr_space.swap_out_one()
+        {
+            SWAP_HAND.store(pid, Ordering::Relaxed);
+            return true;
+        }
+    }
+    false
+}
+
+/// Mark `task` exited for its parent to collect and run the next task.
+pub fn exit_task(task: Arc<Task>) -> ! {
+    {
+        let inner = task.inner_exclusive_access();
+        if inner.parent.is_none() && inner.exit_code != 0 {
+            let _ = INIT_EXIT_CODE.compare_exchange(
+                0,
+                inner.exit_code,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            );
+        }
+    }
+    schedule(Some((task, TaskState::Exited)))
 }
 
 pub fn pop_cur_task() -> Option<Arc<Task>> {
@@ -75,3 +338,28 @@ pub fn pop_cur_task() -> Option<Arc<Task>> {
 pub fn weak_cur_task() -> Option<Weak<Task>> {
     processor_inner().weak_task()
 }
+
+/// see [`processor::Processor::trap_enter`]
+pub fn account_trap() {
+    processor_inner().trap_enter()
+}
+
+/// see [`processor::Processor::fp_trap`]
+pub fn fp_trap(task: &Task) {
+    processor_inner().fp_trap(task)
+}
+
+/// see [`processor::Processor::fp_claim`]
+pub fn fp_claim(task: &Arc<Task>) -> bool {
+    processor_inner().fp_claim(task)
+}
+
+/// see [`processor::Processor::fp_flush`]
+pub fn fp_flush(task: &Task) {
+    processor_inner().fp_flush(task)
+}
+
+/// see [`processor::Processor::fp_release`]
+pub fn fp_release(task: &Task) {
+    processor_inner().fp_release(task)
+}
@@ -1,30 +1,174 @@
-use core::cell::RefMut;
-
-use alloc::sync::{Arc, Weak};
+use alloc::{
+    sync::{Arc, Weak},
+    vec::Vec,
+};
 use lazy_static::lazy_static;
+use riscv::register::sstatus::FS;
 
-use super::Task;
-use crate::sync::UPSafeCell;
+use super::{Task, TaskState};
+use crate::{
+    config::MAX_HARTS,
+    smp::hart_id,
+    sync::{SpinLock, SpinLockGuard},
+    timer::get_time_us,
+};
 
 lazy_static! {
-    pub static ref PROCESSOR: UPSafeCell<Processor> = unsafe { UPSafeCell::new(Processor::new()) };
+    /// one processor for each hart, indexed by hartid
+    static ref PROCESSORS: Vec<SpinLock<Processor>> =
+        (0..MAX_HARTS).map(|_| SpinLock::new(Processor::new())).collect();
 }
 pub struct Processor {
     pub cur_task: Option<Arc<Task>>,
+    /// task the hart is switching away from, with the state it gets once
+    /// the hart has left its kernel stack
+    pub leaving: Option<(Arc<Task>, TaskState)>,
+    /// task whose floating-point state is in the fp registers, stale once
+    /// the task has loaded its state on another hart
+    fp_owner: Option<Weak<Task>>,
+    /// whether the fp registers are newer than the context of `fp_owner`
+    fp_dirty: bool,
+    /// when the time of `cur_task` was last accounted, in us
+    mark_us: usize,
+    /// timer ticks seen by this hart
+    pub ticks: usize,
 }
 
 impl Processor {
     fn new() -> Self {
-        Self { cur_task: None }
+        Self {
+            cur_task: None,
+            leaving: None,
+            fp_owner: None,
+            fp_dirty: false,
+            mark_us: 0,
+            ticks: 0,
+        }
     }
 
-    pub fn pop_task(&mut self) -> Option<Arc<Task>> {
-        let cur_task = match &self.cur_task {
-            None => return None,
-            Some(task) => Arc::clone(task),
+    fn owns_fp(&self, task: &Task) -> bool {
+        matches!(&self.fp_owner, Some(owner) if owner.as_ptr() == task as *const Task)
+            && task.inner_exclusive_access().fp_hart == Some(hart_id())
+    }
+
+    /// Note on a trap whether the owner has written the fp registers.
+    pub fn fp_trap(&mut self, task: &Task) {
+        let dirty = {
+            let inner = task.inner_exclusive_access();
+            let trap_ctx = inner.trap_context();
+            let dirty = trap_ctx.fs() == FS::Dirty;
+            if dirty {
+                trap_ctx.set_fs(FS::Clean);
+            }
+            dirty
+        };
+        if dirty {
+            // only the owner runs with fp enabled
+            debug_assert!(self.owns_fp(task));
+            self.fp_dirty = true;
+        }
+    }
+
+    /// Enable fp for `task` if it owns the registers, so that any other
+    /// task traps on its first fp instruction.
+    pub fn fp_prepare_run(&mut self, task: &Task) {
+        let fs = if self.owns_fp(task) {
+            FS::Clean
+        } else {
+            FS::Off
         };
-        self.cur_task = None;
-        Some(cur_task)
+        task.inner_exclusive_access().trap_context().set_fs(fs);
+    }
+
+    /// Hand the fp registers to `task`, which trapped on an illegal
+    /// instruction. Return false if it could use them already, then the
+    /// instruction was illegal indeed.
+    pub fn fp_claim(&mut self, task: &Arc<Task>) -> bool {
+        if task.inner_exclusive_access().trap_context().fs() != FS::Off {
+            return false;
+        }
+        if !self.owns_fp(task) {
+            self.fp_save();
+            let mut inner = task.inner_exclusive_access();
+            inner.trap_context().fp.load();
+            // ownership left on the hart it ran on before is stale now
+            inner.fp_hart = Some(hart_id());
+            drop(inner);
+            self.fp_owner = Some(Arc::downgrade(task));
+        }
+        task.inner_exclusive_access()
+            .trap_context()
+            .set_fs(FS::Clean);
+        true
+    }
+
+    /// Write the fp registers back to `task` if they are newer than its
+    /// context. The hart keeps owning them, so that the task can use them
+    /// without a trap if it comes back before another task takes them.
+    pub fn fp_flush(&mut self, task: &Task) {
+        if self.owns_fp(task) {
+            self.fp_save();
+        }
+    }
+
+    /// Forget the fp registers of `task`, whose context is gone or replaced.
+    pub fn fp_release(&mut self, task: &Task) {
+        if self.owns_fp(task) {
+            self.fp_owner = None;
+            self.fp_dirty = false;
+        }
+        // what another hart may still hold is stale as well
+        task.inner_exclusive_access().fp_hart = None;
+    }
+
+    fn fp_save(&mut self) {
+        if !self.fp_dirty {
+            return;
+        }
+        self.fp_dirty = false;
+        if let Some(owner) = self.fp_owner.as_ref().and_then(Weak::upgrade) {
+            owner.inner_exclusive_access().trap_context().fp.save();
+        }
+    }
+
+    /// Make `task` the current one right before it returns to user mode,
+    /// the previous one is charged for its kernel time.
+    pub fn run(&mut self, task: Arc<Task>) {
+        self.charge_kernel();
+        {
+            let mut inner = task.inner_exclusive_access();
+            inner.state = TaskState::Running;
+            inner.hart = Some(hart_id());
+            inner.addr_space.mark_active(hart_id());
+            // restored into tp on the next trap of the task
+            inner.trap_context().kernel_tp = hart_id();
+        }
+        self.mark_us = get_time_us();
+        self.cur_task = Some(task);
+    }
+
+    /// Charge the current task for the user time since it was restored,
+    /// called on entering the trap handler.
+    pub fn trap_enter(&mut self) {
+        let now = get_time_us();
+        if let Some(task) = &self.cur_task {
+            task.inner_exclusive_access().times.utime_us += now - self.mark_us;
+        }
+        self.mark_us = now;
+    }
+
+    /// charge the current task for the kernel time since its trap
+    fn charge_kernel(&mut self) {
+        let now = get_time_us();
+        if let Some(task) = &self.cur_task {
+            task.inner_exclusive_access().times.stime_us += now - self.mark_us;
+        }
+        self.mark_us = now;
+    }
+
+    pub fn pop_task(&mut self) -> Option<Arc<Task>> {
+        self.charge_kernel();
+        self.cur_task.take()
     }
 
     pub fn weak_task(&mut self) -> Option<Weak<Task>> {
@@ -35,6 +179,7 @@ impl Processor {
     }
 }
 
-pub fn processor_inner() -> RefMut<'static, Processor> {
-    PROCESSOR.exclusive_access()
+/// the processor of the hart running this code
+pub fn processor_inner() -> SpinLockGuard<'static, Processor> {
+    PROCESSORS[hart_id()].lock()
 }
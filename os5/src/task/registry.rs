@@ -0,0 +1,36 @@
+//! Registry of all tasks.
+//!
+//! The task manager only holds ready tasks and the processor the running
+//! one, the registry knows every task which has not been freed yet,
+//! including exited tasks waiting for their parent. It holds weak
+//! references, so it never keeps a task alive.
+
+use super::Task;
+use alloc::{
+    collections::BTreeMap,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use lazy_static::*;
+use spin::Mutex;
+
+lazy_static! {
+    static ref REGISTRY: Mutex<BTreeMap<usize, Weak<Task>>> = Mutex::new(BTreeMap::new());
+}
+
+pub fn register_task(task: &Arc<Task>) {
+    REGISTRY.lock().insert(task.pid.0, Arc::downgrade(task));
+}
+
+/// the task with `pid`, if it has not been freed
+pub fn find_task(pid: usize) -> Option<Arc<Task>> {
+    REGISTRY.lock().get(&pid).and_then(Weak::upgrade)
+}
+
+/// pids of all live tasks in ascending order, freed tasks are dropped from
+/// the registry on the way
+pub fn task_pids() -> Vec<usize> {
+    let mut registry = REGISTRY.lock();
+    registry.retain(|_, task| task.strong_count() > 0);
+    registry.keys().copied().collect()
+}
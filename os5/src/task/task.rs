@@ -1,4 +1,4 @@
-use core::{cell::RefMut, fmt::Display};
+use core::{fmt::Display, ops::AddAssign};
 
 use alloc::{
     borrow::ToOwned,
@@ -10,24 +10,29 @@ use alloc::{
 use crate::{
     config::*,
     loader::get_app_elf,
-    mm::{MemorySet, PhysAddr, PhysPageNum, VirtAddr, VirtPageNum},
-    sync::UPSafeCell,
+    mm::{ElfLoadError, MemorySet, PageFaultKind, PhysAddr, PhysPageNum, VirtAddr, VirtPageNum},
+    sync::{SpinLock, SpinLockGuard},
     timer::get_time_ms,
     trap::TrapContext,
 };
 
 use super::{
-    add_task, alloc_pid,
+    add_task, alloc_pid, alloc_task, fp_flush, fp_release,
     kernel_stack::{alloc_kernel_stack, KernelStack},
-    PidHandle,
+    register_task, PidHandle,
 };
 
+/// shared with user space as part of `TaskInfo` and `ProcInfo`
+#[repr(C)]
 #[derive(PartialEq, Clone, Copy, Debug)]
 pub enum TaskState {
     UnInit,
     Ready,
     Running,
     Exited,
+    /// parked in the kernel until an event it waits for, see
+    /// [`park`](super::park)
+    Blocked,
 }
 
 impl Default for TaskState {
@@ -36,6 +41,28 @@ impl Default for TaskState {
     }
 }
 
+/// processor time and context switches of a task
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CpuTimes {
+    /// time spent in user mode, in us
+    pub utime_us: usize,
+    /// time spent in the kernel on behalf of the task, in us
+    pub stime_us: usize,
+    /// times the task gave up the processor itself
+    pub nvcsw: usize,
+    /// times the task was preempted
+    pub nivcsw: usize,
+}
+
+impl AddAssign for CpuTimes {
+    fn add_assign(&mut self, other: Self) {
+        self.utime_us += other.utime_us;
+        self.stime_us += other.stime_us;
+        self.nvcsw += other.nvcsw;
+        self.nivcsw += other.nivcsw;
+    }
+}
+
 #[repr(C)]
 pub struct TaskInner {
     pub trap_ctx_ppn: PhysPageNum,
@@ -46,6 +73,25 @@ pub struct TaskInner {
     pub exit_code: i32,
     pub priority: u32,
     pub pass: usize,
+    /// classes of syscalls which are traced, kept across exec
+    pub trace_mask: usize,
+    /// mask of the harts the task may run on, inherited on fork
+    pub affinity: usize,
+    /// the hart the task last ran on or is queued on
+    pub hart: Option<usize>,
+    /// the hart whose fp registers were last loaded with the fp state of
+    /// the task, they are only used if that hart still owns them
+    pub fp_hart: Option<usize>,
+    pub parent: Option<Weak<Task>>,
+    /// where the kernel stack was left when the task parked or yielded in
+    /// the middle of the kernel, it goes on there instead of returning to
+    /// user mode
+    pub kernel_sp: Option<usize>,
+    /// whether [`unpark`](super::unpark) came before the task parked
+    pub unparked: bool,
+    pub times: CpuTimes,
+    /// summed up times of all children which have been waited for
+    pub children_times: CpuTimes,
 }
 
 impl Default for TaskInner {
@@ -59,6 +105,15 @@ impl Default for TaskInner {
             exit_code: 0,
             priority: 16,
             pass: 0,
+            trace_mask: 0,
+            affinity: ALL_HARTS,
+            hart: None,
+            fp_hart: None,
+            parent: None,
+            kernel_sp: None,
+            unparked: false,
+            times: CpuTimes::default(),
+            children_times: CpuTimes::default(),
         }
     }
 }
@@ -68,10 +123,11 @@ impl TaskInner {
         self.state = state
     }
 
-    pub fn translate(&self, va: usize) -> Option<usize> {
+    /// Translate a user pointer, which the user has to be able to read.
+    pub fn translate(&mut self, va: usize) -> Option<usize> {
         let va = VirtAddr::from(va);
         self.addr_space
-            .translate(VirtPageNum::from(va.floor()))
+            .translate_resident(VirtPageNum::from(va.floor()), PageFaultKind::Load)
             .map(|entry| PhysAddr::from(entry.ppn()).0 + va.page_offset())
     }
 
@@ -86,7 +142,7 @@ pub struct Task {
     pub name: String,
     pub start_time_ms: usize,
     pub kernel_stack: KernelStack,
-    inner: UPSafeCell<TaskInner>,
+    inner: SpinLock<TaskInner>,
 }
 
 impl Task {
@@ -97,16 +153,22 @@ impl Task {
             name: name.to_owned(),
             start_time_ms: get_time_ms(),
             kernel_stack: alloc_kernel_stack(new_pid),
-            inner: unsafe { UPSafeCell::new(TaskInner::default()) },
+            inner: SpinLock::new(TaskInner::default()),
         };
         let elf = get_app_elf(name).unwrap();
-        task.init(elf);
-        Arc::new(task)
+        if let Err(err) = task.init(&elf) {
+            panic!("cannot load app {}: {:?}", name, err);
+        }
+        let task = alloc_task(task);
+        register_task(&task);
+        task
     }
 
-    fn init(&self, elf_data: &[u8]) {
+    /// Load `elf_data` into a new address space, the current one is kept
+    /// if the image is rejected.
+    fn init(&self, elf_data: &[u8]) -> Result<(), ElfLoadError> {
         let kernel_stack_top = self.kernel_stack.position().1;
-        let (ms, user_stack, entrypoint) = MemorySet::from_elf(elf_data);
+        let (ms, user_stack, entrypoint) = MemorySet::from_elf(elf_data, self.pid.0)?;
 
         log::debug!(
             "init Task from app_id, &elf_data=0x{:x}, elf_data.len={}, &kernel_stack_top=0x{:x}",
@@ -119,36 +181,47 @@ impl Task {
             .unwrap()
             .ppn();
 
-        let mut inner = self.inner.exclusive_access();
+        let mut inner = self.inner.lock();
         inner.trap_ctx_ppn = trap_ctx_ppn;
         inner.addr_space = ms;
         inner.state = TaskState::Ready;
         inner
             .trap_context()
-            .init(user_stack, entrypoint, kernel_stack_top)
-    }
-    pub fn exec(&self, name: &str) -> Result<(), ()> {
-        let elf = get_app_elf(name)?;
-        self.init(elf);
+            .init(user_stack, entrypoint, kernel_stack_top);
+        drop(inner);
+        // the registers belong to the old image
+        fp_release(self);
         Ok(())
     }
+    pub fn exec(&self, elf_data: &[u8]) -> Result<(), ElfLoadError> {
+        self.init(elf_data)
+    }
 
-    pub fn spawn(name: &str) -> Result<Arc<Task>, ()> {
+    pub fn spawn(name: &str, elf_data: &[u8]) -> Result<Arc<Task>, ElfLoadError> {
         let new_pid = alloc_pid();
         let task = Task {
             pid: new_pid.clone(),
             name: name.to_owned(),
             start_time_ms: get_time_ms(),
             kernel_stack: alloc_kernel_stack(new_pid),
-            inner: unsafe { UPSafeCell::new(TaskInner::default()) },
+            inner: SpinLock::new(TaskInner::default()),
         };
-        let elf = get_app_elf(name)?;
-        task.init(elf);
-        Ok(Arc::new(task))
+        task.init(elf_data)?;
+        let task = alloc_task(task);
+        register_task(&task);
+        Ok(task)
+    }
+
+    /// Lock the inner state, locking it again on the same hart deadlocks.
+    /// Parents are locked before children.
+    pub fn inner_exclusive_access(&self) -> SpinLockGuard<'_, TaskInner> {
+        self.inner.lock()
     }
 
-    pub fn inner_exclusive_access(&self) -> RefMut<'_, TaskInner> {
-        self.inner.exclusive_access()
+    /// Lock the inner state if nobody holds it, for code which may already
+    /// hold the lock of another task.
+    pub fn try_inner_exclusive_access(&self) -> Option<SpinLockGuard<'_, TaskInner>> {
+        self.inner.try_lock()
     }
 
     pub fn from_weak(weak: &Weak<Self>) -> Arc<Self> {
@@ -163,15 +236,17 @@ impl Display for Task {
 }
 
 pub fn fork_task(parent: &Arc<Task>) -> Arc<Task> {
+    // the child copies the fp state from the trap context
+    fp_flush(parent);
     let new_pid = alloc_pid();
     let p_inner = parent.inner_exclusive_access();
     // init child task
-    let child_task = Arc::from(Task {
+    let child_task = alloc_task(Task {
         pid: new_pid.clone(),
         name: parent.name.clone(),
         start_time_ms: get_time_ms(),
         kernel_stack: alloc_kernel_stack(new_pid.clone()),
-        inner: unsafe { UPSafeCell::new(TaskInner::default()) },
+        inner: SpinLock::new(TaskInner::default()),
     });
 
     let mut child_inner = child_task.inner_exclusive_access();
@@ -179,6 +254,8 @@ pub fn fork_task(parent: &Arc<Task>) -> Arc<Task> {
     {
         child_inner.syscall_times = [0; MAX_SYSCALL_NUM];
         child_inner.state = TaskState::Ready;
+        child_inner.parent = Some(Arc::downgrade(parent));
+        child_inner.affinity = p_inner.affinity;
     }
     // init new memory_set
     let trap_ctx_ppn = {
@@ -203,6 +280,8 @@ pub fn fork_task(parent: &Arc<Task>) -> Arc<Task> {
         child_trapctx.kernel_sp = kernel_stack_top;
     }
     drop(child_inner);
+    drop(p_inner);
+    register_task(&child_task);
     add_task(Arc::clone(&child_task));
     child_task
 }
@@ -1,35 +1,89 @@
 use crate::config::CLOCK_FREQ;
 use crate::sbi::set_timer;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use riscv::register::time;
 
 const TICKS_PER_SEC: usize = 100;
 const MICRO_PER_MILLISEC: usize = 1_000;
 const MICRO_PER_SEC: usize = 1_000_000;
+const NANO_PER_SEC: usize = 1_000_000_000;
+
+/// frequency of the `time` CSR, from the device tree
+static TIMEBASE_FREQ: AtomicUsize = AtomicUsize::new(CLOCK_FREQ);
+/// wall-clock time at boot in ns since the Unix epoch, 0 until the RTC
+/// has been read
+static BOOT_EPOCH_NS: AtomicUsize = AtomicUsize::new(0);
+
+pub fn set_timebase_freq(freq: usize) {
+    TIMEBASE_FREQ.store(freq, Ordering::Relaxed);
+}
+
+fn timebase_freq() -> usize {
+    TIMEBASE_FREQ.load(Ordering::Relaxed)
+}
 
 pub fn get_time() -> usize {
     time::read()
 }
 
 pub fn get_time_us() -> usize {
-    time::read() / (CLOCK_FREQ / MICRO_PER_SEC)
+    (time::read() as u128 * MICRO_PER_SEC as u128 / timebase_freq() as u128) as usize
 }
 
 pub fn get_time_ms() -> usize {
     get_time_us() / MICRO_PER_MILLISEC
 }
 
+/// time since boot in ns
+pub fn get_time_ns() -> usize {
+    (time::read() as u128 * NANO_PER_SEC as u128 / timebase_freq() as u128) as usize
+}
+
+/// Set the wall-clock time to `now_ns` since the Unix epoch.
+pub fn set_realtime(now_ns: usize) {
+    BOOT_EPOCH_NS.store(now_ns.saturating_sub(get_time_ns()), Ordering::Relaxed);
+}
+
+/// wall-clock time in ns since the Unix epoch, or since boot without an RTC
+pub fn get_realtime_ns() -> usize {
+    BOOT_EPOCH_NS.load(Ordering::Relaxed) + get_time_ns()
+}
+
 #[repr(C)]
 #[derive(Debug, Default)]
 pub struct TimeVal {
     pub sec: usize,
     pub usec: usize,
 }
+
+/// set `time` to the wall-clock time
 pub fn set_time_val(time: &mut TimeVal) {
-    let cpu_time = get_time();
-    time.sec = cpu_time / CLOCK_FREQ;
-    time.usec = (cpu_time - time.sec * CLOCK_FREQ) / (CLOCK_FREQ / MICRO_PER_SEC)
+    let now_ns = get_realtime_ns();
+    time.sec = now_ns / NANO_PER_SEC;
+    time.usec = now_ns % NANO_PER_SEC / (NANO_PER_SEC / MICRO_PER_SEC);
+}
+
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct TimeSpec {
+    pub sec: usize,
+    pub nsec: usize,
+}
+
+impl TimeSpec {
+    pub fn from_ns(ns: usize) -> Self {
+        Self {
+            sec: ns / NANO_PER_SEC,
+            nsec: ns % NANO_PER_SEC,
+        }
+    }
 }
 
+/// Have the timer interrupt after the next time slice. Should the SBI
+/// refuse, the hart only gets preempted by other interrupts until the next
+/// try.
 pub fn set_next_trigger() {
-    set_timer(get_time() + CLOCK_FREQ / TICKS_PER_SEC);
+    if let Err(err) = set_timer(get_time() + timebase_freq() / TICKS_PER_SEC) {
+        log::error!("cannot set the timer: {:?}", err);
+    }
 }
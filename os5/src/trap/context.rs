@@ -1,6 +1,6 @@
 use core::fmt::Display;
 
-use riscv::register::sstatus::{self, Sstatus, SPP};
+use riscv::register::sstatus::{self, Sstatus, FS, SPP};
 
 use crate::{
     config::{PAGE_SIZE, TRAMPOLINE},
@@ -9,6 +9,40 @@ use crate::{
 
 use super::handler::trap_handler;
 
+extern "C" {
+    fn __save_fp(fp_ctx: *mut FpContext);
+    fn __load_fp(fp_ctx: *const FpContext);
+}
+
+/// 浮点寄存器 f0~f31 与 fcsr, 不由 trap.S 保存, 而是按 sstatus.FS 延迟切换.
+#[repr(C)]
+#[derive(Default)]
+pub struct FpContext {
+    pub f: [u64; 32],
+    pub fcsr: usize,
+}
+
+impl FpContext {
+    /// save the fp registers of this hart into the context
+    pub fn save(&mut self) {
+        unsafe {
+            sstatus::set_fs(FS::Clean);
+            __save_fp(self);
+        }
+    }
+
+    /// load the fp registers of this hart from the context
+    pub fn load(&self) {
+        unsafe {
+            sstatus::set_fs(FS::Clean);
+            __load_fp(self);
+        }
+    }
+}
+
+const SSTATUS_FS_SHIFT: usize = 13;
+const SSTATUS_FS_MASK: usize = 0b11 << SSTATUS_FS_SHIFT;
+
 #[repr(C)]
 pub struct TrapContext {
     pub x: [usize; 32],
@@ -17,6 +51,8 @@ pub struct TrapContext {
     pub kernel_satp: usize,  // 保存内核地址空间的token.
     pub kernel_sp: usize,    // 内核栈栈顶的虚拟地址.
     pub trap_handler: usize, // trap handler 入口点虚拟地址.
+    pub kernel_tp: usize,    // 返回用户态的 hart 的 id, trap 时恢复到 tp.
+    pub fp: FpContext,       // 浮点上下文, 位于 trap.S 访问的字段之后.
 }
 
 impl TrapContext {
@@ -27,6 +63,9 @@ impl TrapContext {
             sstatus.set_spp(SPP::User);
             sstatus
         };
+        // the fp registers are switched in on the first fp instruction
+        self.set_fs(FS::Off);
+        self.fp = FpContext::default();
         self.kernel_satp = KERNEL_SPACE.lock().token();
         self.kernel_sp = kernel_stack;
         self.trap_handler = trap_handler as usize;
@@ -43,6 +82,16 @@ impl TrapContext {
         self.x[10 + n] = v
     }
 
+    pub fn fs(&self) -> FS {
+        self.sstatus.fs()
+    }
+
+    /// `Sstatus` cannot be built from bits, so the saved value is patched in place.
+    pub fn set_fs(&mut self, fs: FS) {
+        let bits = unsafe { &mut *(&mut self.sstatus as *mut Sstatus as *mut usize) };
+        *bits = (*bits & !SSTATUS_FS_MASK) | (fs as usize) << SSTATUS_FS_SHIFT;
+    }
+
     pub fn get_ptr(&mut self) -> usize {
         self as *mut TrapContext as usize
     }
@@ -1,6 +1,12 @@
 use crate::{
+    drivers::handle_external_interrupt,
+    mm::{PageFaultError, PageFaultKind, VirtAddr},
+    smp::hart_id,
     syscall::{self, sys_exit},
-    task::{pop_cur_task, run_task, switch_task, weak_cur_task, Task, TaskState},
+    task::{
+        account_trap, balance_tick, fp_claim, fp_trap, pop_cur_task, run_task, switch_task,
+        weak_cur_task, Task, TaskState,
+    },
     timer::set_next_trigger,
 };
 use riscv::register::{
@@ -11,11 +17,13 @@ use riscv::register::{
 #[no_mangle]
 pub fn trap_handler() -> ! {
     super::init();
+    account_trap();
     let scause = scause::read();
     let stval = stval::read();
     let weak_task = weak_cur_task().expect("still not run user task?");
     {
         let task = Task::from_weak(&weak_task);
+        fp_trap(&task);
         let inner = task.inner_exclusive_access();
         let trap_ctx = inner.trap_context();
         log::debug!("task_{} trap_handler, task.trap_ctx={}", task.pid, trap_ctx);
@@ -36,37 +44,76 @@ pub fn trap_handler() -> ! {
                     .sepc += 4;
             };
             syscall::syscall_handler(&weak_task);
-            {
-                Task::from_weak(&weak_task)
-                    .inner_exclusive_access()
-                    .set_state(TaskState::Ready);
-            };
             run_task(pop_cur_task().unwrap());
         }
-        Trap::Exception(Exception::LoadPageFault) | Trap::Exception(Exception::StorePageFault) => {
-            log::info!("page fault, try to access virtual address 0x{:x}", stval);
-            sys_exit(pop_cur_task().unwrap(), 1);
+        Trap::Exception(Exception::LoadPageFault)
+        | Trap::Exception(Exception::StorePageFault)
+        | Trap::Exception(Exception::InstructionPageFault) => {
+            let kind = match scause.cause() {
+                Trap::Exception(Exception::LoadPageFault) => PageFaultKind::Load,
+                Trap::Exception(Exception::StorePageFault) => PageFaultKind::Store,
+                _ => PageFaultKind::Fetch,
+            };
+            let result = Task::from_weak(&weak_task)
+                .inner_exclusive_access()
+                .addr_space
+                .handle_page_fault(VirtAddr::from(stval), kind);
+            match result {
+                Ok(()) => run_task(pop_cur_task().unwrap()),
+                Err(PageFaultError::StackOverflow) => {
+                    let task = pop_cur_task().unwrap();
+                    println!(
+                        "[kernel] {}, stack overflow, try to access virtual address 0x{:x}, killed",
+                        task,
+                        stval
+                    );
+                    sys_exit(task, 1);
+                }
+                Err(err) => {
+                    log::info!(
+                        "page fault ({:?}), try to access virtual address 0x{:x}",
+                        err,
+                        stval
+                    );
+                    sys_exit(pop_cur_task().unwrap(), 1);
+                }
+            }
         }
         Trap::Exception(Exception::StoreFault) | Trap::Exception(Exception::LoadFault) => {
             log::error!("memory access fault, core dump");
             sys_exit(pop_cur_task().unwrap(), 1);
         }
         Trap::Exception(Exception::IllegalInstruction) => {
+            let task = pop_cur_task().unwrap();
+            // the first fp instruction of a task traps while fp is off
+            if fp_claim(&task) {
+                run_task(task);
+            }
             log::error!("illegal instruction, core dump");
-            sys_exit(pop_cur_task().unwrap(), 1);
+            sys_exit(task, 1);
         }
         Trap::Interrupt(Interrupt::SupervisorTimer) => {
             log::info!("Timer interrupt.");
             set_next_trigger();
+            balance_tick();
             {
                 let task = Task::from_weak(&weak_task);
                 let mut inner = task.inner_exclusive_access();
                 inner.set_state(TaskState::Ready);
             }
-            switch_task(pop_cur_task().unwrap());
+            switch_task(pop_cur_task().unwrap(), false);
+        }
+        Trap::Interrupt(Interrupt::SupervisorSoft) => {
+            // an IPI only wakes a sleeping hart, the task goes on
+            super::clear_ipi();
+            run_task(pop_cur_task().unwrap());
+        }
+        Trap::Interrupt(Interrupt::SupervisorExternal) => {
+            handle_external_interrupt(hart_id());
+            run_task(pop_cur_task().unwrap());
         }
-        _ => {
-            unimplemented!()
+        cause => {
+            panic!("unsupported trap {:?}, stval = {:#x}", cause, stval);
         }
     }
 }
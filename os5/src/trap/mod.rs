@@ -2,12 +2,15 @@ mod context;
 mod handler;
 mod restore;
 
-use riscv::register::sie;
+use riscv::register::{sie, sip};
 use riscv::register::{stvec, utvec::TrapMode};
 
+use crate::{drivers::handle_external_interrupt, smp::hart_id};
+
 pub use {context::TrapContext, restore::restore};
 
 core::arch::global_asm!(include_str!("trap.S"));
+core::arch::global_asm!(include_str!("fp.S"));
 extern "C" {
     fn __alltraps() -> !;
     fn __restore(user_ctx: usize, user_token: usize) -> !;
@@ -22,3 +25,49 @@ pub fn enable_timer_interrupt() {
         sie::set_stimer();
     }
 }
+
+/// returns whether the timer interrupt was enabled
+pub fn disable_timer_interrupt() -> bool {
+    let enabled = sie::read().stimer();
+    unsafe {
+        sie::clear_stimer();
+    }
+    enabled
+}
+
+/// take IPIs, which wake a hart from [`wait_for_interrupt`]
+pub fn enable_software_interrupt() {
+    unsafe {
+        sie::set_ssoft();
+    }
+}
+
+/// take device interrupts from the PLIC
+pub fn enable_external_interrupt() {
+    unsafe {
+        sie::set_sext();
+    }
+}
+
+/// Sleep until an interrupt is pending. The kernel runs with interrupts
+/// off, so nothing is taken and pending IPIs and device interrupts are
+/// handled here. Returns whether the timer interrupt is pending, which is
+/// left to the caller.
+pub fn wait_for_interrupt() -> bool {
+    unsafe { riscv::asm::wfi() };
+    clear_ipi();
+    poll_external_interrupt();
+    sip::read().stimer()
+}
+
+/// Handle the pending device interrupts, which the kernel does not take.
+pub fn poll_external_interrupt() {
+    if sip::read().sext() {
+        handle_external_interrupt(hart_id());
+    }
+}
+
+fn clear_ipi() {
+    // SSIP is the only bit of sip writable in S-mode
+    unsafe { core::arch::asm!("csrc sip, {}", in(reg) 1 << 1) };
+}
@@ -0,0 +1,48 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    checked, exit, fork, proc_info, sched_setaffinity, waitpid, yield_, Errno, ProcInfo,
+};
+
+/*
+理想结果：绑定到单个 hart 后只在该 hart 上运行，子进程继承绑定，非法参数返回错误，输出 Test affinity0 OK!
+*/
+
+#[no_mangle]
+fn main() -> i32 {
+    let all = checked::sched_getaffinity(0).unwrap();
+    assert_ne!(all, 0);
+    let hart = all.trailing_zeros() as usize;
+    assert_eq!(sched_setaffinity(0, 1 << hart), 0);
+    assert_eq!(checked::sched_getaffinity(0), Ok(1 << hart));
+    // a running task moves at its next switch
+    yield_();
+    let mut info = ProcInfo::new();
+    for _ in 0..20 {
+        assert_eq!(proc_info(0, &mut info), 0);
+        assert_eq!(info.hart, hart);
+        yield_();
+    }
+
+    let pid = fork();
+    if pid == 0 {
+        let inherited = checked::sched_getaffinity(0) == Ok(1 << hart);
+        exit(if inherited { 0 } else { 1 });
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+
+    assert_eq!(checked::sched_setaffinity(0, 0), Err(Errno::EINVAL));
+    assert_eq!(
+        checked::sched_setaffinity(usize::MAX, all),
+        Err(Errno::ESRCH)
+    );
+    assert_eq!(sched_setaffinity(0, all), 0);
+    println!("Test affinity0 OK!");
+    0
+}
@@ -0,0 +1,58 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{fork, sbrk, spawn, waitpid};
+
+/*
+理想结果：多次启动 ch5b_aslr_probe，栈和 mmap 区的地址不全相同；fork 出的子进程与父进程布局一致，
+输出 Test aslr0 OK!
+*/
+
+/// with 15 or 16 random bits each, all runs agree by chance with a
+/// probability below 2^-45
+const RUNS: usize = 4;
+
+/// the layout bits reported by a run of `ch5b_aslr_probe`
+fn probe() -> i32 {
+    let pid = spawn("ch5b_aslr_probe\0");
+    assert!(pid > 0, "cannot spawn ch5b_aslr_probe");
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert!(exit_code >= 0, "ch5b_aslr_probe failed");
+    exit_code
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let local = 0usize;
+    let stack = &local as *const usize as usize;
+    let code = main as usize;
+    let heap = sbrk(0) as usize;
+    println!(
+        "code at {:#x}, heap at {:#x}, stack at {:#x}",
+        code, heap, stack
+    );
+    let mut layouts = [0; RUNS];
+    for layout in layouts.iter_mut() {
+        *layout = probe();
+    }
+    let differ = |mask: i32| layouts.iter().any(|l| l & mask != layouts[0] & mask);
+    assert!(differ(0xffff), "the stack is not randomized");
+    assert!(differ(0x7fff << 16), "the mmap base is not randomized");
+    let pid = fork();
+    if pid == 0 {
+        let child_local = 0usize;
+        let child_stack = &child_local as *const usize as usize;
+        // the child runs the same code on a copy of the parent's stack
+        let same = main as usize == code && child_stack.abs_diff(stack) < 4096;
+        return if same { 0 } else { 1 };
+    }
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    println!("Test aslr0 OK!");
+    0
+}
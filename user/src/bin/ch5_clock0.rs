@@ -0,0 +1,43 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    checked, clock_gettime, get_time, Errno, TimeSpec, CLOCK_MONOTONIC, CLOCK_PROCESS_CPUTIME_ID,
+    CLOCK_REALTIME,
+};
+
+/*
+理想结果：CLOCK_REALTIME 为 RTC 给出的真实时间，与 get_time 一致，CLOCK_MONOTONIC 不回退，忙等后进程 CPU 时间增加，非法时钟返回 EINVAL，输出 Test clock0 OK!
+*/
+
+/// 2020-01-01 00:00:00 UTC
+const YEAR_2020: usize = 1_577_836_800;
+
+#[no_mangle]
+fn main() -> i32 {
+    let real = checked::clock_gettime(CLOCK_REALTIME).unwrap();
+    assert!(real.sec > YEAR_2020);
+    assert!(real.nsec < 1_000_000_000);
+    // get_time is the wall clock in ms, no longer cut to 16 bits of seconds
+    let diff = get_time() - (real.as_ns() / 1_000_000) as isize;
+    assert!((0..1000).contains(&diff));
+
+    let mono = checked::clock_gettime(CLOCK_MONOTONIC).unwrap();
+    assert!(mono.as_ns() < real.as_ns());
+    let cpu = checked::clock_gettime(CLOCK_PROCESS_CPUTIME_ID).unwrap();
+    let start = get_time();
+    while get_time() - start < 50 {}
+    let mono_after = checked::clock_gettime(CLOCK_MONOTONIC).unwrap();
+    assert!(mono_after.as_ns() >= mono.as_ns() + 50_000_000);
+    let cpu_after = checked::clock_gettime(CLOCK_PROCESS_CPUTIME_ID).unwrap();
+    assert!(cpu_after.as_ns() > cpu.as_ns());
+
+    let mut tp = TimeSpec::new();
+    assert_eq!(clock_gettime(3, &mut tp), -1);
+    assert_eq!(checked::clock_gettime(3).unwrap_err(), Errno::EINVAL);
+    println!("Test clock0 OK!");
+    0
+}
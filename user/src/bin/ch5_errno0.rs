@@ -0,0 +1,38 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{checked, strerror, syscall, Errno};
+
+/*
+理想结果：各类错误的系统调用返回对应的错误码，输出 Test errno0 OK!
+*/
+
+#[no_mangle]
+fn main() -> i32 {
+    let start: usize = 0x10000000;
+    let len: usize = 4096;
+    assert_eq!(
+        checked::exec("no_such_app\0", &[0 as *const u8]),
+        Err(Errno::ENOENT)
+    );
+    assert_eq!(checked::waitpid(-1, &mut 0), Err(Errno::ECHILD));
+    assert_eq!(checked::mmap(start, len, 8), Err(Errno::EINVAL));
+    assert_eq!(checked::mmap(start + 1, len, 3), Err(Errno::EINVAL));
+    assert_eq!(checked::mmap(start, len, 3), Ok(()));
+    assert_eq!(checked::mmap(start, len, 3), Err(Errno::EEXIST));
+    assert_eq!(checked::mprotect(start, len, 5), Err(Errno::EACCES));
+    assert_eq!(checked::munmap(start, len + 4096), Err(Errno::EINVAL));
+    assert_eq!(checked::munmap(start, len), Ok(()));
+    assert_eq!(checked::shmget(0x1234, len, 0), Err(Errno::ENOENT));
+    assert_eq!(checked::write(42, b"x"), Err(Errno::EBADF));
+    // nothing is mapped at address 0
+    let bad = unsafe { core::slice::from_raw_parts(0 as *const u8, 1) };
+    assert_eq!(checked::write(1, bad), Err(Errno::EFAULT));
+    assert_eq!(Errno::from_ret(syscall(9999, [0; 3])), Err(Errno::ENOSYS));
+    println!("{:?}: {}", Errno::EFAULT, strerror(Errno::EFAULT));
+    println!("Test errno0 OK!");
+    0
+}
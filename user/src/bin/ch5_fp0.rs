@@ -0,0 +1,101 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::arch::asm;
+use user_lib::{fork, wait};
+
+/*
+理想结果：多个进程同时使用浮点寄存器并被抢占，各自的浮点值保持不变，输出 Test fp0 OK!
+*/
+
+const NUM: usize = 4;
+const SPIN: usize = 50_000_000;
+
+/// Fill eight fp registers with `seed..seed + 8`, spin long enough to be
+/// preempted several times, then check that the values are still there.
+fn fp_survives(seed: usize) -> bool {
+    let ok: usize;
+    unsafe {
+        asm!(
+            "fcvt.d.l ft0, {s}",
+            "addi {t}, {s}, 1",
+            "fcvt.d.l ft1, {t}",
+            "addi {t}, {s}, 2",
+            "fcvt.d.l ft2, {t}",
+            "addi {t}, {s}, 3",
+            "fcvt.d.l ft3, {t}",
+            "addi {t}, {s}, 4",
+            "fcvt.d.l ft4, {t}",
+            "addi {t}, {s}, 5",
+            "fcvt.d.l ft5, {t}",
+            "addi {t}, {s}, 6",
+            "fcvt.d.l ft6, {t}",
+            "addi {t}, {s}, 7",
+            "fcvt.d.l ft7, {t}",
+            "mv {t}, {n}",
+            "2:",
+            "addi {t}, {t}, -1",
+            "bnez {t}, 2b",
+            "li {ok}, 0",
+            "fcvt.l.d {t}, ft0",
+            "bne {t}, {s}, 3f",
+            "fcvt.l.d {t}, ft1",
+            "addi {t}, {t}, -1",
+            "bne {t}, {s}, 3f",
+            "fcvt.l.d {t}, ft2",
+            "addi {t}, {t}, -2",
+            "bne {t}, {s}, 3f",
+            "fcvt.l.d {t}, ft3",
+            "addi {t}, {t}, -3",
+            "bne {t}, {s}, 3f",
+            "fcvt.l.d {t}, ft4",
+            "addi {t}, {t}, -4",
+            "bne {t}, {s}, 3f",
+            "fcvt.l.d {t}, ft5",
+            "addi {t}, {t}, -5",
+            "bne {t}, {s}, 3f",
+            "fcvt.l.d {t}, ft6",
+            "addi {t}, {t}, -6",
+            "bne {t}, {s}, 3f",
+            "fcvt.l.d {t}, ft7",
+            "addi {t}, {t}, -7",
+            "bne {t}, {s}, 3f",
+            "li {ok}, 1",
+            "3:",
+            s = in(reg) seed,
+            n = in(reg) SPIN,
+            t = out(reg) _,
+            ok = out(reg) ok,
+            out("ft0") _,
+            out("ft1") _,
+            out("ft2") _,
+            out("ft3") _,
+            out("ft4") _,
+            out("ft5") _,
+            out("ft6") _,
+            out("ft7") _,
+        );
+    }
+    ok == 1
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    for i in 0..NUM {
+        let pid = fork();
+        if pid == 0 {
+            return if fp_survives((i + 1) * 100) { 0 } else { 1 };
+        }
+    }
+    assert!(fp_survives(1000));
+    let mut exit_code = 0;
+    for _ in 0..NUM {
+        assert!(wait(&mut exit_code) > 0);
+        assert_eq!(exit_code, 0);
+    }
+    println!("Test fp0 OK!");
+    0
+}
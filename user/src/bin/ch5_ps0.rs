@@ -0,0 +1,61 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    checked, exit, fork, getpid, list_tasks, proc_info, waitpid, yield_, Errno, ProcInfo,
+    TaskStatus,
+};
+
+/*
+理想结果：能列出自身与子进程，进程信息中的父进程、状态与系统调用计数正确，子进程退出后被回收前状态为 Exited，输出 Test ps0 OK!
+*/
+
+const SYSCALL_GETPID: usize = 172;
+
+#[no_mangle]
+fn main() -> i32 {
+    let pid = fork();
+    if pid == 0 {
+        for _ in 0..100 {
+            yield_();
+        }
+        exit(0);
+    }
+    let my_pid = getpid() as usize;
+    let mut pids = [0usize; 64];
+    let n = list_tasks(&mut pids);
+    assert!(n >= 2);
+    let listed = &pids[..(n as usize).min(pids.len())];
+    assert!(listed.contains(&my_pid) && listed.contains(&(pid as usize)));
+
+    let mut info = ProcInfo::new();
+    assert_eq!(proc_info(0, &mut info), 0);
+    assert_eq!(info.pid, my_pid);
+    assert_eq!(info.status, TaskStatus::Running);
+    assert!(info.resident_pages > 0);
+    assert!(info.syscall_times[SYSCALL_GETPID] >= 1);
+    assert!(info.syscall_total() >= 4);
+
+    assert_eq!(proc_info(pid as usize, &mut info), 0);
+    assert_eq!(info.pid, pid as usize);
+    assert_eq!(info.ppid, my_pid);
+    // the child may be running on another hart, it is at least started
+    assert_ne!(info.status, TaskStatus::UnInit);
+    assert_eq!(checked::proc_info(usize::MAX, &mut info), Err(Errno::ESRCH));
+
+    // once it has exited, it is reported so until it is waited for
+    loop {
+        assert_eq!(proc_info(pid as usize, &mut info), 0);
+        if info.status == TaskStatus::Exited {
+            break;
+        }
+        yield_();
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    println!("Test ps0 OK!");
+    0
+}
@@ -0,0 +1,45 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::vec::Vec;
+use user_lib::{brk, sbrk};
+
+/*
+理想结果：堆通过 sbrk 自动扩展，可以分配超过初始 16 KiB 的内存，超出地址空间的 break 被拒绝，输出 Test sbrk0 OK!
+*/
+
+const LEN: usize = 1 << 18;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let old_brk = brk(0);
+    assert!(old_brk > 0);
+    assert_eq!(sbrk(0), old_brk);
+    // grow the break by hand and use the new memory
+    assert_eq!(sbrk(4096), old_brk);
+    assert_eq!(brk(0), old_brk + 4096);
+    let page = unsafe { core::slice::from_raw_parts_mut(old_brk as *mut u8, 4096) };
+    page.fill(0x5a);
+    assert!(page.iter().all(|b| *b == 0x5a));
+    // the allocator asks for more memory on its own
+    let mut v: Vec<usize> = Vec::with_capacity(LEN);
+    for i in 0..LEN {
+        v.push(i);
+    }
+    for (i, x) in v.iter().enumerate() {
+        assert_eq!(*x, i);
+    }
+    assert!(brk(0) > old_brk + (LEN * core::mem::size_of::<usize>()) as isize);
+    // a break past the stack or out of the address space is refused
+    let cur_brk = brk(0);
+    assert_eq!(sbrk(isize::MAX), -1);
+    assert_eq!(sbrk(isize::MIN), -1);
+    assert_eq!(brk(usize::MAX), cur_brk);
+    assert_eq!(brk(0), cur_brk);
+    println!("Test sbrk0 OK!");
+    0
+}
@@ -0,0 +1,42 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{fork, shmat, shmdt, shmget, wait, IPC_PRIVATE};
+
+/*
+理想结果：子进程通过共享内存写入的数据对父进程可见，输出 Test shm0 OK!
+*/
+
+const LEN: usize = 8192;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let shm_id = shmget(IPC_PRIVATE, LEN, 0);
+    assert!(shm_id > 0, "shmget failed");
+    let start = shmat(shm_id as usize, 0, 0);
+    assert!(start > 0, "shmat failed");
+    let start = start as usize;
+    let buf = unsafe { core::slice::from_raw_parts_mut(start as *mut u8, LEN) };
+    buf.fill(0);
+    let pid = fork();
+    if pid == 0 {
+        for (i, byte) in buf.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        assert_eq!(shmdt(start), 0);
+        return 0;
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(pid, wait(&mut exit_code));
+    assert_eq!(exit_code, 0);
+    for (i, byte) in buf.iter().enumerate() {
+        assert_eq!(*byte, i as u8);
+    }
+    assert_eq!(shmdt(start), 0);
+    assert_eq!(shmdt(start), -1);
+    println!("Test shm0 OK!");
+    0
+}
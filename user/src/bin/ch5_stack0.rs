@@ -0,0 +1,28 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+/*
+理想结果：递归使用超过初始 80 KiB 的栈空间，栈按需增长，输出 Test stack0 OK!
+*/
+
+const DEPTH: usize = 512;
+
+fn recurse(depth: usize) -> usize {
+    let mut frame = [0u8; 1024];
+    for (i, byte) in frame.iter_mut().enumerate() {
+        unsafe { core::ptr::write_volatile(byte, (i + depth) as u8) };
+    }
+    let sum = if depth == 0 { 0 } else { recurse(depth - 1) };
+    sum + unsafe { core::ptr::read_volatile(&frame[depth % 1024]) } as usize
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let expected: usize = (0..=DEPTH).map(|d| ((d % 1024 + d) as u8) as usize).sum();
+    assert_eq!(recurse(DEPTH), expected);
+    println!("Test stack0 OK!");
+    0
+}
@@ -0,0 +1,51 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{sbrk, swap_stat, SwapStat};
+
+/*
+理想结果：堆扩展到超过物理内存的大小，页被换出到交换盘，再次访问时内容不变，输出 Test swap0 OK!
+*/
+
+/// more than the 128 MiB of RAM of QEMU virt
+const LEN: usize = 144 << 20;
+const PAGE_SIZE: usize = 4096;
+
+fn pattern(page: usize, round: usize) -> usize {
+    page.wrapping_mul(0x9e37_79b9) ^ round
+}
+
+/// check every page and note `round` in it, or only check if `round` is 0
+fn check(heap: &mut [usize], last: usize, round: usize) {
+    for (page, word) in heap.iter_mut().step_by(PAGE_SIZE / 8).enumerate() {
+        assert_eq!(*word, pattern(page, last), "page {} is corrupted", page);
+        if round != 0 {
+            *word = pattern(page, round);
+        }
+    }
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let start = sbrk(LEN as isize);
+    assert!(start > 0);
+    let heap = unsafe { core::slice::from_raw_parts_mut(start as *mut usize, LEN / 8) };
+    for (page, word) in heap.iter_mut().step_by(PAGE_SIZE / 8).enumerate() {
+        *word = pattern(page, 1);
+    }
+    // pages written again go out dirty, pages only read come back from
+    // the copy they left on the swap disk
+    check(heap, 1, 2);
+    check(heap, 2, 0);
+    check(heap, 2, 0);
+    let mut stat = SwapStat::default();
+    assert_eq!(swap_stat(&mut stat), 0);
+    println!("swap ins: {}, swap outs: {}", stat.swap_ins, stat.swap_outs);
+    assert!(stat.swap_outs > 0 && stat.swap_ins > 0);
+    assert_eq!(sbrk(-(LEN as isize)), start + LEN as isize);
+    println!("Test swap0 OK!");
+    0
+}
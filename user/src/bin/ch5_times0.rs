@@ -0,0 +1,65 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    exit, fork, get_time, getrusage, task_info, times, waitpid, yield_, Rusage, TaskInfo, Tms,
+    RUSAGE_CHILDREN, RUSAGE_SELF,
+};
+
+/*
+理想结果：用户态忙等计入用户时间，yield 计入主动切换，回收子进程后其时间计入 RUSAGE_CHILDREN，输出 Test times0 OK!
+*/
+
+/// busy loop in user mode for `ms` milliseconds
+fn spin(ms: isize) {
+    let start = get_time();
+    while get_time() - start < ms {
+        for _ in 0..1000 {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+#[no_mangle]
+fn main() -> i32 {
+    let mut before = Rusage::default();
+    assert_eq!(getrusage(RUSAGE_SELF, &mut before), 0);
+    spin(200);
+    for _ in 0..10 {
+        yield_();
+    }
+    let mut after = Rusage::default();
+    assert_eq!(getrusage(RUSAGE_SELF, &mut after), 0);
+    assert!(after.ru_utime.as_us() > before.ru_utime.as_us());
+    assert!(after.ru_stime.as_us() >= before.ru_stime.as_us());
+    assert!(after.ru_nvcsw >= before.ru_nvcsw + 10);
+    assert!(after.ru_maxrss > 0);
+
+    let info = TaskInfo::new();
+    assert_eq!(task_info(&info), 0);
+    assert!(info.utime_us >= after.ru_utime.as_us());
+    assert!(info.nvcsw >= 10);
+    // the task cannot have been on the processor for longer than it exists
+    assert!((info.utime_us + info.stime_us) / 1000 <= info.time + 10);
+
+    let pid = fork();
+    if pid == 0 {
+        spin(200);
+        exit(0);
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    let mut children = Rusage::default();
+    assert_eq!(getrusage(RUSAGE_CHILDREN, &mut children), 0);
+    assert!(children.ru_utime.as_us() > 0);
+    assert_eq!(getrusage(1, &mut children), -1);
+
+    let mut tms = Tms::default();
+    assert!(times(&mut tms) > 0);
+    assert!(tms.tms_utime > 0 && tms.tms_cutime > 0);
+    println!("Test times0 OK!");
+    0
+}
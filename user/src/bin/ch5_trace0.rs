@@ -0,0 +1,46 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exit, fork, mmap, munmap, trace, trace_read, waitpid, TraceRecord, TRACE_MEMORY};
+
+/*
+理想结果：子进程的内存类系统调用被记录，记录中的参数与返回值正确，输出 Test trace0 OK!
+*/
+
+#[no_mangle]
+fn main() -> i32 {
+    let start: usize = 0x10000000;
+    let len: usize = 4096;
+    let pid = fork();
+    if pid == 0 {
+        assert_eq!(trace(0, TRACE_MEMORY), 0);
+        assert_eq!(mmap(start, len, 3), 0);
+        assert_eq!(munmap(start, len), 0);
+        assert_eq!(munmap(start, len), -1);
+        exit(0);
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    let mut records = [TraceRecord::default(); 8];
+    assert_eq!(trace_read(pid as usize, 0, &mut records), 3);
+    assert!(records.iter().take(3).all(|r| r.pid == pid as usize));
+    assert_eq!(records[0].name(), "mmap");
+    assert_eq!(records[0].args(), &[start, len, 3]);
+    assert_eq!(records[0].ret, 0);
+    assert_eq!(records[1].name(), "munmap");
+    assert_eq!(records[1].args(), &[start, len]);
+    assert_eq!(records[1].ret, 0);
+    assert!(records[2].ret < 0);
+    assert!(records[0].seq < records[1].seq && records[1].seq < records[2].seq);
+    // reading on from the last record gives nothing new
+    assert_eq!(
+        trace_read(pid as usize, records[2].seq + 1, &mut records),
+        0
+    );
+    println!("Test trace0 OK!");
+    0
+}
@@ -0,0 +1,36 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{mmap, mprotect, munmap};
+
+/*
+理想结果：可写可执行的映射被拒绝，写过的页面不能再变为可执行，输出 Test wx0 OK!
+*/
+
+#[no_mangle]
+fn main() -> i32 {
+    let start: usize = 0x10000000;
+    let len: usize = 4096 * 2;
+    // W+X at once
+    assert_eq!(mmap(start, len, 7), -1);
+    assert_eq!(mmap(start, len, 3), 0);
+    unsafe {
+        *(start as *mut u8) = 0x13;
+    }
+    // writable pages cannot become executable, not even read-only first
+    assert_eq!(mprotect(start, 4096, 5), -1);
+    assert_eq!(mprotect(start, 4096, 1), 0);
+    assert_eq!(mprotect(start, 4096, 5), -1);
+    // the second page is split off and still writable
+    unsafe {
+        *((start + 4096) as *mut u8) = 0x13;
+        assert_eq!(*(start as *const u8), 0x13);
+    }
+    assert_eq!(munmap(start, 4096), 0);
+    assert_eq!(munmap(start + 4096, 4096), 0);
+    println!("Test wx0 OK!");
+    0
+}
@@ -0,0 +1,21 @@
+#![no_std]
+#![no_main]
+
+extern crate user_lib;
+
+use user_lib::{shmat, shmget, IPC_PRIVATE};
+
+/// Exit with the low 16 bits of the page number of the stack in bits 0 to
+/// 15 and those of the first free mmap address in bits 16 to 30, for
+/// `ch5_aslr0` to compare the layouts of several runs.
+#[no_mangle]
+pub fn main() -> i32 {
+    let local = 0usize;
+    let stack = &local as *const usize as usize;
+    let shm_id = shmget(IPC_PRIVATE, 4096, 0);
+    let mmap = shmat(shm_id as usize, 0, 0);
+    if shm_id <= 0 || mmap <= 0 {
+        return -1;
+    }
+    ((stack >> 12) & 0xffff | ((mmap as usize >> 12) & 0x7fff) << 16) as i32
+}
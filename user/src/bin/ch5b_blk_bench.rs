@@ -0,0 +1,54 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exit, fork, get_time, spawn, waitpid};
+
+const ROUNDS: usize = 16;
+
+/// Load `ch5b_true` from the disk `ROUNDS` times in each of `workers`
+/// processes at once, returns the time taken in ms.
+fn run(workers: usize) -> isize {
+    let start = get_time();
+    let mut pids = [0; 8];
+    for pid in pids.iter_mut().take(workers) {
+        *pid = fork();
+        if *pid == 0 {
+            for _ in 0..ROUNDS {
+                let child = spawn("ch5b_true\0");
+                assert!(child > 0);
+                let mut exit_code: i32 = 0;
+                assert_eq!(waitpid(child as usize, &mut exit_code), child);
+            }
+            exit(0);
+        }
+    }
+    for &pid in pids.iter().take(workers) {
+        let mut exit_code: i32 = 0;
+        assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+        assert_eq!(exit_code, 0);
+    }
+    get_time() - start
+}
+
+/// Report how many ELF files per second the kernel reads from the disk,
+/// with one and with several processes loading at the same time. A process
+/// waiting for a block gives up its hart, so more processes keep more
+/// requests in flight, as long as they do not read the same block.
+#[no_mangle]
+pub fn main() -> i32 {
+    for workers in [1, 2, 4] {
+        let ms = run(workers).max(1);
+        let loads = workers * ROUNDS;
+        println!(
+            "workers = {}, {} loads in {}ms, {} loads/s",
+            workers,
+            loads,
+            ms,
+            loads as isize * 1000 / ms
+        );
+    }
+    0
+}
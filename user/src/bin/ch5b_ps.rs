@@ -0,0 +1,47 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{list_tasks, proc_info, ProcInfo};
+
+const MAX_TASKS: usize = 128;
+
+/// List all tasks of the kernel.
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut pids = [0usize; MAX_TASKS];
+    let n = list_tasks(&mut pids);
+    if n < 0 {
+        println!("ps: cannot list tasks");
+        return -1;
+    }
+    println!(
+        "{:>5} {:>5} {:<8} {:>4} {:>10} {:>10} {:>6} {:>8} NAME",
+        "PID", "PPID", "STATE", "PRIO", "PASS", "TIME(ms)", "RSS", "SYSCALLS"
+    );
+    let mut info = ProcInfo::new();
+    for &pid in pids.iter().take(n as usize) {
+        // the task may have been freed in between
+        if proc_info(pid, &mut info) != 0 {
+            continue;
+        }
+        println!(
+            "{:>5} {:>5} {:<8} {:>4} {:>10} {:>10} {:>6} {:>8} {}",
+            info.pid,
+            info.ppid,
+            info.status.name(),
+            info.priority,
+            info.pass,
+            info.cpu_time_us / 1000,
+            info.resident_pages,
+            info.syscall_total(),
+            info.name()
+        );
+    }
+    if n as usize > MAX_TASKS {
+        println!("... {} more tasks", n as usize - MAX_TASKS);
+    }
+    0
+}
@@ -0,0 +1,89 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+#[macro_use]
+extern crate user_lib;
+
+const LF: u8 = 0x0au8;
+const CR: u8 = 0x0du8;
+
+use alloc::string::String;
+use user_lib::console::getchar;
+use user_lib::{
+    exec, exit, flush, fork, sys_waitpid, trace, trace_read, yield_, Errno, TraceRecord, TRACE_ALL,
+};
+
+fn print_record(record: &TraceRecord) {
+    print!("[{}] {}(", record.pid, record.name());
+    for (i, arg) in record.args().iter().enumerate() {
+        if i > 0 {
+            print!(", ");
+        }
+        print!("{:#x}", arg);
+    }
+    print!(") = ");
+    match Errno::from_ret(record.ret) {
+        Ok(ret) => print!("{:#x}", ret),
+        Err(errno) => print!("-1 {:?} ({})", errno, errno),
+    }
+    println!(" <{}us>", record.duration_us);
+}
+
+/// Print the records of `pid` from `cursor` on, returns the new cursor.
+fn drain(pid: usize, mut cursor: usize) -> usize {
+    let mut records = [TraceRecord::default(); 16];
+    loop {
+        let n = trace_read(pid, cursor, &mut records);
+        if n <= 0 {
+            return cursor;
+        }
+        for record in &records[..n as usize] {
+            print_record(record);
+            cursor = record.seq + 1;
+        }
+    }
+}
+
+/// Run an app read from the console with all its syscalls traced.
+#[no_mangle]
+pub fn main() -> i32 {
+    print!("strace app: ");
+    flush();
+    let mut line = String::new();
+    loop {
+        let c = getchar();
+        if c == LF || c == CR {
+            print!("\n");
+            break;
+        }
+        print!("{}", c as char);
+        flush();
+        line.push(c as char);
+    }
+    line.push('\0');
+    let pid = fork();
+    if pid == 0 {
+        trace(0, TRACE_ALL);
+        if exec(line.as_str(), &[0 as *const u8]) == -1 {
+            println!("strace: cannot execute {}", line.trim_end_matches('\0'));
+            exit(-4);
+        }
+        unreachable!();
+    }
+    let mut cursor = 0;
+    let mut exit_code: i32 = 0;
+    loop {
+        cursor = drain(pid as usize, cursor);
+        match Errno::from_ret(sys_waitpid(pid, &mut exit_code)) {
+            Err(Errno::EAGAIN) => {
+                yield_();
+            }
+            _ => break,
+        }
+    }
+    drain(pid as usize, cursor);
+    println!("[{}] exited with code {}", pid, exit_code);
+    0
+}
@@ -0,0 +1,47 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{getrusage, set_priority, spawn, waitpid, Rusage, RUSAGE_CHILDREN};
+
+static TESTS: &[(&str, usize)] = &[
+    ("ch5_stride0\0", 5),
+    ("ch5_stride1\0", 6),
+    ("ch5_stride2\0", 7),
+    ("ch5_stride3\0", 8),
+    ("ch5_stride4\0", 9),
+    ("ch5_stride5\0", 10),
+];
+
+/// Run the stride tests side by side and report the processor time each
+/// of them really got, which should be proportional to its priority.
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut pids = [0; 6];
+    for (i, (test, _)) in TESTS.iter().enumerate() {
+        pids[i] = spawn(test);
+    }
+    set_priority(4);
+    let mut usage = Rusage::default();
+    getrusage(RUSAGE_CHILDREN, &mut usage);
+    let mut last_us = usage.ru_utime.as_us() + usage.ru_stime.as_us();
+    for (i, (test, prio)) in TESTS.iter().enumerate() {
+        let mut exit_code: i32 = 0;
+        assert_eq!(waitpid(pids[i] as usize, &mut exit_code), pids[i]);
+        // the usage of the children grows by the child just waited for
+        getrusage(RUSAGE_CHILDREN, &mut usage);
+        let now_us = usage.ru_utime.as_us() + usage.ru_stime.as_us();
+        let used_us = now_us - last_us;
+        last_us = now_us;
+        println!(
+            "{}: priority = {}, cpu time = {}ms, ratio = {}",
+            test.trim_end_matches('\0'),
+            prio,
+            used_us / 1000,
+            used_us / 1000 / prio
+        );
+    }
+    0
+}
@@ -0,0 +1,74 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+#[macro_use]
+extern crate user_lib;
+
+use alloc::vec::Vec;
+use user_lib::{get_time, list_tasks, proc_info, sleep, ProcInfo};
+
+const MAX_TASKS: usize = 128;
+const ROUNDS: usize = 5;
+const PERIOD_MS: usize = 1000;
+
+/// pid, name and cpu time of every task
+fn sample() -> Vec<(usize, ProcInfo)> {
+    let mut pids = [0usize; MAX_TASKS];
+    let n = list_tasks(&mut pids).max(0) as usize;
+    let mut tasks = Vec::new();
+    for &pid in pids.iter().take(n.min(MAX_TASKS)) {
+        let mut info = ProcInfo::new();
+        if proc_info(pid, &mut info) == 0 {
+            tasks.push((pid, info));
+        }
+    }
+    tasks
+}
+
+/// Show the tasks using the most processor time, refreshed every second.
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut last = sample();
+    let mut last_time = get_time();
+    for _ in 0..ROUNDS {
+        sleep(PERIOD_MS);
+        let now = sample();
+        let now_time = get_time();
+        let period_us = ((now_time - last_time).max(1) as usize) * 1000;
+        // cpu time used in the period, tasks new in this period count from 0
+        let mut usage: Vec<(usize, &ProcInfo)> = now
+            .iter()
+            .map(|(pid, info)| {
+                let before = last
+                    .iter()
+                    .find(|(last_pid, _)| last_pid == pid)
+                    .map_or(0, |(_, info)| info.cpu_time_us);
+                (info.cpu_time_us.saturating_sub(before), info)
+            })
+            .collect();
+        usage.sort_by(|a, b| b.0.cmp(&a.0));
+        println!("top - {} tasks", now.len());
+        println!(
+            "{:>5} {:<8} {:>4} {:>6} {:>10} {:>6} NAME",
+            "PID", "STATE", "PRIO", "%CPU", "TIME(ms)", "RSS"
+        );
+        for (used, info) in usage.iter() {
+            println!(
+                "{:>5} {:<8} {:>4} {:>6} {:>10} {:>6} {}",
+                info.pid,
+                info.status.name(),
+                info.priority,
+                used * 100 / period_us,
+                info.cpu_time_us / 1000,
+                info.resident_pages,
+                info.name()
+            );
+        }
+        print!("\n");
+        last = now;
+        last_time = now_time;
+    }
+    0
+}
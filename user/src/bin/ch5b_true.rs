@@ -0,0 +1,10 @@
+#![no_std]
+#![no_main]
+
+extern crate user_lib;
+
+/// Exit at once, for spawning an app without output.
+#[no_mangle]
+pub fn main() -> i32 {
+    0
+}
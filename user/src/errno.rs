@@ -0,0 +1,208 @@
+//! Error numbers returned by the kernel.
+//!
+//! A failing syscall returns the negated error number. The plain wrappers
+//! of this crate report every failure as -1, the ones in [`checked`] return
+//! the [`Errno`] instead.
+
+use core::fmt::{self, Debug, Display, Formatter};
+
+/// an error number as returned, negated, by a failing syscall
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct Errno(pub isize);
+
+impl Errno {
+    pub const ENOENT: Errno = Errno(2);
+    pub const ESRCH: Errno = Errno(3);
+    pub const ENOEXEC: Errno = Errno(8);
+    pub const EBADF: Errno = Errno(9);
+    pub const ECHILD: Errno = Errno(10);
+    pub const EAGAIN: Errno = Errno(11);
+    pub const ENOMEM: Errno = Errno(12);
+    pub const EACCES: Errno = Errno(13);
+    pub const EFAULT: Errno = Errno(14);
+    pub const EEXIST: Errno = Errno(17);
+    pub const EINVAL: Errno = Errno(22);
+    pub const ENOSYS: Errno = Errno(38);
+
+    /// split a raw syscall return value into a value and an error
+    pub fn from_ret(ret: isize) -> Result<usize, Errno> {
+        if ret < 0 {
+            Err(Errno(-ret))
+        } else {
+            Ok(ret as usize)
+        }
+    }
+
+    fn name(self) -> Option<&'static str> {
+        Some(match self {
+            Self::ENOENT => "ENOENT",
+            Self::ESRCH => "ESRCH",
+            Self::ENOEXEC => "ENOEXEC",
+            Self::EBADF => "EBADF",
+            Self::ECHILD => "ECHILD",
+            Self::EAGAIN => "EAGAIN",
+            Self::ENOMEM => "ENOMEM",
+            Self::EACCES => "EACCES",
+            Self::EFAULT => "EFAULT",
+            Self::EEXIST => "EEXIST",
+            Self::EINVAL => "EINVAL",
+            Self::ENOSYS => "ENOSYS",
+            _ => return None,
+        })
+    }
+}
+
+/// a human readable description of `errno`
+pub fn strerror(errno: Errno) -> &'static str {
+    match errno {
+        Errno::ENOENT => "No such file or directory",
+        Errno::ESRCH => "No such process",
+        Errno::ENOEXEC => "Exec format error",
+        Errno::EBADF => "Bad file descriptor",
+        Errno::ECHILD => "No child processes",
+        Errno::EAGAIN => "Resource temporarily unavailable",
+        Errno::ENOMEM => "Cannot allocate memory",
+        Errno::EACCES => "Permission denied",
+        Errno::EFAULT => "Bad address",
+        Errno::EEXIST => "File exists",
+        Errno::EINVAL => "Invalid argument",
+        Errno::ENOSYS => "Function not implemented",
+        _ => "Unknown error",
+    }
+}
+
+impl Debug for Errno {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self.name() {
+            Some(name) => f.write_str(name),
+            None => write!(f, "Errno({})", self.0),
+        }
+    }
+}
+
+impl Display for Errno {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(strerror(*self))
+    }
+}
+
+/// Wrappers which return the [`Errno`] of a failed syscall.
+pub mod checked {
+    use super::Errno;
+    use crate::{syscall::*, ProcInfo, Rusage, TimeSpec, Tms, TraceRecord};
+
+    pub fn clock_gettime(clock: usize) -> Result<TimeSpec, Errno> {
+        let mut tp = TimeSpec::new();
+        Errno::from_ret(sys_clock_gettime(clock, &mut tp)).map(|_| tp)
+    }
+
+    /// Returns the clock ticks since boot.
+    pub fn times(tms: &mut Tms) -> Result<usize, Errno> {
+        Errno::from_ret(sys_times(tms))
+    }
+
+    pub fn getrusage(who: isize, usage: &mut Rusage) -> Result<(), Errno> {
+        Errno::from_ret(sys_getrusage(who, usage)).map(|_| ())
+    }
+
+    pub fn fork() -> Result<usize, Errno> {
+        Errno::from_ret(sys_fork())
+    }
+
+    /// `path` has to end with a `\0`
+    pub fn exec(path: &str, args: &[*const u8]) -> Result<usize, Errno> {
+        Errno::from_ret(sys_exec(path, args))
+    }
+
+    /// `path` has to end with a `\0`
+    pub fn spawn(path: &str) -> Result<usize, Errno> {
+        Errno::from_ret(sys_spawn(path))
+    }
+
+    /// Wait for child `pid`, or any child if `pid` is -1, to exit.
+    /// Returns the pid of the child.
+    pub fn waitpid(pid: isize, exit_code: &mut i32) -> Result<usize, Errno> {
+        loop {
+            match Errno::from_ret(sys_waitpid(pid, exit_code as *mut _)) {
+                Err(Errno::EAGAIN) => {
+                    sys_yield();
+                }
+                ret => return ret,
+            }
+        }
+    }
+
+    pub fn set_priority(prio: isize) -> Result<usize, Errno> {
+        Errno::from_ret(sys_set_priority(prio))
+    }
+
+    pub fn sched_setaffinity(pid: usize, mask: usize) -> Result<(), Errno> {
+        Errno::from_ret(sys_sched_setaffinity(pid, &mask)).map(|_| ())
+    }
+
+    pub fn sched_getaffinity(pid: usize) -> Result<usize, Errno> {
+        let mut mask = 0;
+        Errno::from_ret(sys_sched_getaffinity(pid, &mut mask)).map(|_| mask)
+    }
+
+    pub fn mmap(start: usize, len: usize, prot: usize) -> Result<(), Errno> {
+        Errno::from_ret(sys_mmap(start, len, prot)).map(|_| ())
+    }
+
+    pub fn mprotect(start: usize, len: usize, prot: usize) -> Result<(), Errno> {
+        Errno::from_ret(sys_mprotect(start, len, prot)).map(|_| ())
+    }
+
+    pub fn munmap(start: usize, len: usize) -> Result<(), Errno> {
+        Errno::from_ret(sys_munmap(start, len)).map(|_| ())
+    }
+
+    /// Returns the old program break.
+    pub fn sbrk(increment: isize) -> Result<usize, Errno> {
+        Errno::from_ret(sys_sbrk(increment))
+    }
+
+    pub fn shmget(key: usize, size: usize, flags: usize) -> Result<usize, Errno> {
+        Errno::from_ret(sys_shmget(key, size, flags))
+    }
+
+    /// Returns the address the segment is attached at.
+    pub fn shmat(shm_id: usize, addr: usize, flags: usize) -> Result<usize, Errno> {
+        Errno::from_ret(sys_shmat(shm_id, addr, flags))
+    }
+
+    pub fn shmdt(addr: usize) -> Result<(), Errno> {
+        Errno::from_ret(sys_shmdt(addr)).map(|_| ())
+    }
+
+    pub fn read(fd: usize, buf: &mut [u8]) -> Result<usize, Errno> {
+        Errno::from_ret(sys_read(fd, buf))
+    }
+
+    pub fn write(fd: usize, buf: &[u8]) -> Result<usize, Errno> {
+        Errno::from_ret(sys_write(fd, buf))
+    }
+
+    /// Returns the old trace mask.
+    pub fn trace(pid: usize, mask: usize) -> Result<usize, Errno> {
+        Errno::from_ret(sys_trace(pid, mask))
+    }
+
+    /// Returns the number of records read.
+    pub fn trace_read(
+        pid: usize,
+        cursor: usize,
+        records: &mut [TraceRecord],
+    ) -> Result<usize, Errno> {
+        Errno::from_ret(sys_trace_read(pid, cursor, records))
+    }
+
+    /// Returns the number of tasks, which may be more than fit into `pids`.
+    pub fn list_tasks(pids: &mut [usize]) -> Result<usize, Errno> {
+        Errno::from_ret(sys_list_tasks(pids))
+    }
+
+    pub fn proc_info(pid: usize, info: &mut ProcInfo) -> Result<(), Errno> {
+        Errno::from_ret(sys_proc_info(pid, info)).map(|_| ())
+    }
+}
@@ -5,6 +5,7 @@
 
 #[macro_use]
 pub mod console;
+mod errno;
 mod lang_items;
 mod syscall;
 
@@ -14,16 +15,63 @@ extern crate core;
 extern crate bitflags;
 
 use alloc::vec::Vec;
-use buddy_system_allocator::LockedHeap;
+use buddy_system_allocator::Heap;
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    ptr::NonNull,
+};
 pub use console::{flush, STDIN, STDOUT};
+pub use errno::{checked, strerror, Errno};
+use spin::Mutex;
 pub use syscall::*;
 
 const USER_HEAP_SIZE: usize = 16384;
+/// the heap grows by at least this many bytes through `sbrk`
+const USER_HEAP_GROW_SIZE: usize = 65536;
 
 static mut HEAP_SPACE: [u8; USER_HEAP_SIZE] = [0; USER_HEAP_SIZE];
 
+/// A buddy heap starting in `HEAP_SPACE`, which moves the program break
+/// to get more memory once it is exhausted.
+struct GrowableHeap(Mutex<Heap>);
+
+impl GrowableHeap {
+    /// Add enough memory for `layout` to the heap, any range twice as large
+    /// as a power-of-two block contains an aligned one.
+    fn grow(heap: &mut Heap, layout: &Layout) -> bool {
+        let block = layout.size().max(layout.align()).next_power_of_two();
+        let size = (block * 2).max(USER_HEAP_GROW_SIZE);
+        let start = sbrk(size as isize);
+        if start < 0 {
+            return false;
+        }
+        unsafe {
+            heap.add_to_heap(start as usize, start as usize + size);
+        }
+        true
+    }
+}
+
+unsafe impl GlobalAlloc for GrowableHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut heap = self.0.lock();
+        if let Ok(ptr) = heap.alloc(layout) {
+            return ptr.as_ptr();
+        }
+        if Self::grow(&mut heap, &layout) {
+            if let Ok(ptr) = heap.alloc(layout) {
+                return ptr.as_ptr();
+            }
+        }
+        core::ptr::null_mut()
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.0.lock().dealloc(NonNull::new_unchecked(ptr), layout)
+    }
+}
+
 #[global_allocator]
-static HEAP: LockedHeap = LockedHeap::empty();
+static HEAP: GrowableHeap = GrowableHeap(Mutex::new(Heap::empty()));
 
 #[alloc_error_handler]
 pub fn handle_alloc_error(layout: core::alloc::Layout) -> ! {
@@ -49,7 +97,8 @@ fn clear_bss() {
 pub extern "C" fn _start(argc: usize, argv: usize) -> ! {
     clear_bss();
     unsafe {
-        HEAP.lock()
+        HEAP.0
+            .lock()
             .init(HEAP_SPACE.as_ptr() as usize, USER_HEAP_SIZE);
     }
     let mut v: Vec<&'static str> = Vec::new();
@@ -86,7 +135,7 @@ bitflags! {
 }
 
 #[repr(C)]
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone, Copy)]
 pub struct TimeVal {
     pub sec: usize,
     pub usec: usize,
@@ -96,14 +145,54 @@ impl TimeVal {
     pub fn new() -> Self {
         Self::default()
     }
+    pub fn as_us(&self) -> usize {
+        self.sec * 1_000_000 + self.usec
+    }
 }
 
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TimeSpec {
+    pub sec: usize,
+    pub nsec: usize,
+}
+
+impl TimeSpec {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn as_ns(&self) -> usize {
+        self.sec * 1_000_000_000 + self.nsec
+    }
+}
+
+/// wall-clock time since the Unix epoch
+pub const CLOCK_REALTIME: usize = 0;
+/// time since boot
+pub const CLOCK_MONOTONIC: usize = 1;
+/// processor time of the calling process
+pub const CLOCK_PROCESS_CPUTIME_ID: usize = 2;
+
+#[repr(C)]
 #[derive(Copy, Clone, PartialEq, Debug)]
 pub enum TaskStatus {
     UnInit,
     Ready,
     Running,
     Exited,
+    Blocked,
+}
+
+impl TaskStatus {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TaskStatus::UnInit => "UnInit",
+            TaskStatus::Ready => "Ready",
+            TaskStatus::Running => "Running",
+            TaskStatus::Exited => "Exited",
+            TaskStatus::Blocked => "Blocked",
+        }
+    }
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -114,11 +203,20 @@ pub struct SyscallInfo {
 
 const MAX_SYSCALL_NUM: usize = 500;
 
+#[repr(C)]
 #[derive(Debug)]
 pub struct TaskInfo {
     pub status: TaskStatus,
     pub syscall_times: [u32; MAX_SYSCALL_NUM],
     pub time: usize,
+    /// time spent in user mode, in us
+    pub utime_us: usize,
+    /// time spent in the kernel, in us
+    pub stime_us: usize,
+    /// voluntary context switches
+    pub nvcsw: usize,
+    /// involuntary context switches
+    pub nivcsw: usize,
 }
 
 impl TaskInfo {
@@ -127,8 +225,139 @@ impl TaskInfo {
             status: TaskStatus::UnInit,
             syscall_times: [0; MAX_SYSCALL_NUM],
             time: 0,
+            utime_us: 0,
+            stime_us: 0,
+            nvcsw: 0,
+            nivcsw: 0,
+        }
+    }
+}
+
+/// times in clock ticks, `CLOCKS_PER_SEC` per second
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Tms {
+    pub tms_utime: usize,
+    pub tms_stime: usize,
+    pub tms_cutime: usize,
+    pub tms_cstime: usize,
+}
+
+pub const CLOCKS_PER_SEC: usize = 100;
+
+pub const RUSAGE_SELF: isize = 0;
+pub const RUSAGE_CHILDREN: isize = -1;
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Rusage {
+    pub ru_utime: TimeVal,
+    pub ru_stime: TimeVal,
+    pub ru_maxrss: usize,
+    pub ru_ixrss: usize,
+    pub ru_idrss: usize,
+    pub ru_isrss: usize,
+    pub ru_minflt: usize,
+    pub ru_majflt: usize,
+    pub ru_nswap: usize,
+    pub ru_inblock: usize,
+    pub ru_oublock: usize,
+    pub ru_msgsnd: usize,
+    pub ru_msgrcv: usize,
+    pub ru_nsignals: usize,
+    pub ru_nvcsw: usize,
+    pub ru_nivcsw: usize,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct ProcInfo {
+    pub pid: usize,
+    /// 0 if the parent is gone
+    pub ppid: usize,
+    name: [u8; 32],
+    pub status: TaskStatus,
+    pub priority: usize,
+    pub pass: usize,
+    /// time spent on the processor, in us
+    pub cpu_time_us: usize,
+    pub start_time_ms: usize,
+    pub resident_pages: usize,
+    /// the hart the task last ran on
+    pub hart: usize,
+    pub syscall_times: [u32; MAX_SYSCALL_NUM],
+}
+
+impl ProcInfo {
+    pub fn new() -> Self {
+        ProcInfo {
+            pid: 0,
+            ppid: 0,
+            name: [0; 32],
+            status: TaskStatus::UnInit,
+            priority: 0,
+            pass: 0,
+            cpu_time_us: 0,
+            start_time_ms: 0,
+            resident_pages: 0,
+            hart: 0,
+            syscall_times: [0; MAX_SYSCALL_NUM],
         }
     }
+    pub fn name(&self) -> &str {
+        let len = self.name.iter().position(|&b| b == 0).unwrap_or(32);
+        core::str::from_utf8(&self.name[..len]).unwrap_or("?")
+    }
+    /// number of syscalls made so far
+    pub fn syscall_total(&self) -> usize {
+        self.syscall_times.iter().map(|&n| n as usize).sum()
+    }
+}
+
+impl Default for ProcInfo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SwapStat {
+    pub swap_ins: usize,
+    pub swap_outs: usize,
+}
+
+pub const TRACE_PROCESS: usize = 1 << 0;
+pub const TRACE_MEMORY: usize = 1 << 1;
+pub const TRACE_IO: usize = 1 << 2;
+pub const TRACE_TIME: usize = 1 << 3;
+pub const TRACE_INFO: usize = 1 << 4;
+pub const TRACE_ALL: usize = TRACE_PROCESS | TRACE_MEMORY | TRACE_IO | TRACE_TIME | TRACE_INFO;
+
+/// a syscall recorded by the kernel for a traced task
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TraceRecord {
+    pub seq: usize,
+    pub pid: usize,
+    pub id: usize,
+    name: [u8; 16],
+    pub nargs: usize,
+    pub args: [usize; 6],
+    pub ret: isize,
+    pub start_us: usize,
+    pub duration_us: usize,
+}
+
+impl TraceRecord {
+    pub fn name(&self) -> &str {
+        let len = self.name.iter().position(|&b| b == 0).unwrap_or(16);
+        core::str::from_utf8(&self.name[..len]).unwrap_or("?")
+    }
+    /// the arguments the kernel decoded
+    pub fn args(&self) -> &[usize] {
+        &self.args[..self.nargs.min(6)]
+    }
 }
 
 #[repr(C)]
@@ -227,50 +456,73 @@ pub fn yield_() -> isize {
 pub fn get_time() -> isize {
     let time = TimeVal::new();
     match sys_get_time(&time, 0) {
-        0 => ((time.sec & 0xffff) * 1000 + time.usec / 1000) as isize,
+        0 => (time.sec * 1000 + time.usec / 1000) as isize,
         _ => -1,
     }
 }
 
+/// report any failure of `ret` as -1, the `checked` wrappers keep the errno
+fn or_minus_one(ret: isize) -> isize {
+    if ret < 0 {
+        -1
+    } else {
+        ret
+    }
+}
+
+/// Read `clock`, one of the `CLOCK_*` constants, into `tp`. Returns 0, or
+/// -1 for an unknown clock or a bad `tp`.
+pub fn clock_gettime(clock: usize, tp: &mut TimeSpec) -> isize {
+    or_minus_one(sys_clock_gettime(clock, tp))
+}
+
+/// Get the processor times of the caller and its waited for children.
+/// Returns the clock ticks since boot.
+pub fn times(tms: &mut Tms) -> isize {
+    sys_times(tms)
+}
+
+pub fn getrusage(who: isize, usage: &mut Rusage) -> isize {
+    sys_getrusage(who, usage)
+}
+
 pub fn getpid() -> isize {
     sys_getpid()
 }
 
 pub fn fork() -> isize {
-    sys_fork()
+    or_minus_one(sys_fork())
 }
 
 pub fn exec(path: &str, args: &[*const u8]) -> isize {
-    sys_exec(path, args)
+    or_minus_one(sys_exec(path, args))
 }
 
 pub fn set_priority(prio: isize) -> isize {
-    sys_set_priority(prio)
+    or_minus_one(sys_set_priority(prio))
+}
+
+/// Restrict task `pid`, the caller itself if it is 0, to the harts in `mask`.
+pub fn sched_setaffinity(pid: usize, mask: usize) -> isize {
+    or_minus_one(sys_sched_setaffinity(pid, &mask))
+}
+
+/// Get the mask of harts task `pid`, the caller itself if it is 0, may run on.
+pub fn sched_getaffinity(pid: usize, mask: &mut usize) -> isize {
+    or_minus_one(sys_sched_getaffinity(pid, mask))
 }
 
 pub fn wait(exit_code: &mut i32) -> isize {
-    loop {
-        match sys_waitpid(-1, exit_code as *mut _) {
-            -2 => {
-                sys_yield();
-            }
-            n => {
-                return n;
-            }
-        }
+    match checked::waitpid(-1, exit_code) {
+        Ok(pid) => pid as isize,
+        Err(_) => -1,
     }
 }
 
 pub fn waitpid(pid: usize, exit_code: &mut i32) -> isize {
-    loop {
-        match sys_waitpid(pid as isize, exit_code as *mut _) {
-            -2 => {
-                sys_yield();
-            }
-            n => {
-                return n;
-            }
-        }
+    match checked::waitpid(pid as isize, exit_code) {
+        Ok(pid) => pid as isize,
+        Err(_) => -1,
     }
 }
 
@@ -284,16 +536,44 @@ pub fn sleep(period_ms: usize) {
         sys_yield();
     }
 }
+pub fn brk(addr: usize) -> isize {
+    sys_brk(addr)
+}
+
+pub fn sbrk(increment: isize) -> isize {
+    or_minus_one(sys_sbrk(increment))
+}
+
 pub fn mmap(start: usize, len: usize, prot: usize) -> isize {
-    sys_mmap(start, len, prot)
+    or_minus_one(sys_mmap(start, len, prot))
+}
+
+pub fn mprotect(start: usize, len: usize, prot: usize) -> isize {
+    or_minus_one(sys_mprotect(start, len, prot))
 }
 
 pub fn munmap(start: usize, len: usize) -> isize {
-    sys_munmap(start, len)
+    or_minus_one(sys_munmap(start, len))
+}
+
+pub const IPC_PRIVATE: usize = 0;
+pub const IPC_CREAT: usize = 0o1000;
+pub const SHM_RDONLY: usize = 0o10000;
+
+pub fn shmget(key: usize, size: usize, flags: usize) -> isize {
+    or_minus_one(sys_shmget(key, size, flags))
+}
+
+pub fn shmat(shm_id: usize, addr: usize, flags: usize) -> isize {
+    or_minus_one(sys_shmat(shm_id, addr, flags))
+}
+
+pub fn shmdt(addr: usize) -> isize {
+    or_minus_one(sys_shmdt(addr))
 }
 
 pub fn spawn(path: &str) -> isize {
-    sys_spawn(path)
+    or_minus_one(sys_spawn(path))
 }
 
 pub fn dup(fd: usize) -> isize {
@@ -307,6 +587,34 @@ pub fn task_info(info: &TaskInfo) -> isize {
     sys_task_info(info)
 }
 
+pub fn swap_stat(stat: &mut SwapStat) -> isize {
+    sys_swap_stat(stat)
+}
+
+/// Trace the syscalls of task `pid`, the caller itself if it is 0, whose
+/// classes are in `mask`. Returns the old mask.
+pub fn trace(pid: usize, mask: usize) -> isize {
+    sys_trace(pid, mask)
+}
+
+/// Read the trace records of task `pid`, or of every task if it is 0,
+/// starting at sequence number `cursor`. Only the records of the caller and
+/// of its children are read. Returns the number read.
+pub fn trace_read(pid: usize, cursor: usize, records: &mut [TraceRecord]) -> isize {
+    sys_trace_read(pid, cursor, records)
+}
+
+/// Write the pids of all tasks to `pids` as far as they fit. Returns the
+/// number of tasks.
+pub fn list_tasks(pids: &mut [usize]) -> isize {
+    sys_list_tasks(pids)
+}
+
+/// Get the state of task `pid`, the caller itself if it is 0.
+pub fn proc_info(pid: usize, info: &mut ProcInfo) -> isize {
+    sys_proc_info(pid, info)
+}
+
 pub fn thread_create(entry: usize, arg: usize) -> isize {
     sys_thread_create(entry, arg)
 }
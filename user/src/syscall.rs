@@ -1,6 +1,6 @@
-use crate::TaskInfo;
+use crate::{ProcInfo, Rusage, SwapStat, TaskInfo, Tms, TraceRecord};
 
-use super::{Stat, TimeVal};
+use super::{Stat, TimeSpec, TimeVal};
 
 pub const SYSCALL_OPENAT: usize = 56;
 pub const SYSCALL_CLOSE: usize = 57;
@@ -11,22 +11,38 @@ pub const SYSCALL_LINKAT: usize = 37;
 pub const SYSCALL_FSTAT: usize = 80;
 pub const SYSCALL_EXIT: usize = 93;
 pub const SYSCALL_SLEEP: usize = 101;
+pub const SYSCALL_CLOCK_GETTIME: usize = 113;
+pub const SYSCALL_SCHED_SETAFFINITY: usize = 122;
+pub const SYSCALL_SCHED_GETAFFINITY: usize = 123;
 pub const SYSCALL_YIELD: usize = 124;
+pub const SYSCALL_TIMES: usize = 153;
+pub const SYSCALL_GETRUSAGE: usize = 165;
 pub const SYSCALL_GETTIMEOFDAY: usize = 169;
 pub const SYSCALL_GETPID: usize = 172;
 pub const SYSCALL_GETTID: usize = 178;
+pub const SYSCALL_SHMGET: usize = 194;
+pub const SYSCALL_SHMAT: usize = 196;
+pub const SYSCALL_SHMDT: usize = 197;
 pub const SYSCALL_FORK: usize = 220;
 pub const SYSCALL_EXEC: usize = 221;
 pub const SYSCALL_WAITPID: usize = 260;
 pub const SYSCALL_SET_PRIORITY: usize = 140;
+pub const SYSCALL_BRK: usize = 214;
 pub const SYSCALL_MUNMAP: usize = 215;
 pub const SYSCALL_MMAP: usize = 222;
+pub const SYSCALL_MPROTECT: usize = 226;
 pub const SYSCALL_SPAWN: usize = 400;
 pub const SYSCALL_MAIL_READ: usize = 401;
 pub const SYSCALL_MAIL_WRITE: usize = 402;
 pub const SYSCALL_DUP: usize = 24;
 pub const SYSCALL_PIPE: usize = 59;
 pub const SYSCALL_TASK_INFO: usize = 410;
+pub const SYSCALL_SWAP_STAT: usize = 411;
+pub const SYSCALL_SBRK: usize = 412;
+pub const SYSCALL_TRACE: usize = 413;
+pub const SYSCALL_TRACE_READ: usize = 414;
+pub const SYSCALL_LIST_TASKS: usize = 415;
+pub const SYSCALL_PROC_INFO: usize = 416;
 pub const SYSCALL_THREAD_CREATE: usize = 460;
 pub const SYSCALL_WAITTID: usize = 462;
 pub const SYSCALL_MUTEX_CREATE: usize = 463;
@@ -158,6 +174,21 @@ pub fn sys_get_time(time: &TimeVal, tz: usize) -> isize {
     syscall(SYSCALL_GETTIMEOFDAY, [time as *const _ as usize, tz, 0])
 }
 
+pub fn sys_clock_gettime(clock: usize, tp: &mut TimeSpec) -> isize {
+    syscall(SYSCALL_CLOCK_GETTIME, [clock, tp as *mut _ as usize, 0])
+}
+
+pub fn sys_times(tms: &mut Tms) -> isize {
+    syscall(SYSCALL_TIMES, [tms as *mut _ as usize, 0, 0])
+}
+
+pub fn sys_getrusage(who: isize, usage: &mut Rusage) -> isize {
+    syscall(
+        SYSCALL_GETRUSAGE,
+        [who as usize, usage as *mut _ as usize, 0],
+    )
+}
+
 pub fn sys_getpid() -> isize {
     syscall(SYSCALL_GETPID, [0, 0, 0])
 }
@@ -181,14 +212,56 @@ pub fn sys_set_priority(prio: isize) -> isize {
     syscall(SYSCALL_SET_PRIORITY, [prio as usize, 0, 0])
 }
 
+pub fn sys_sched_setaffinity(pid: usize, mask: &usize) -> isize {
+    syscall(
+        SYSCALL_SCHED_SETAFFINITY,
+        [
+            pid,
+            core::mem::size_of::<usize>(),
+            mask as *const _ as usize,
+        ],
+    )
+}
+
+pub fn sys_sched_getaffinity(pid: usize, mask: &mut usize) -> isize {
+    syscall(
+        SYSCALL_SCHED_GETAFFINITY,
+        [pid, core::mem::size_of::<usize>(), mask as *mut _ as usize],
+    )
+}
+
 pub fn sys_mmap(start: usize, len: usize, prot: usize) -> isize {
     syscall(SYSCALL_MMAP, [start, len, prot])
 }
 
+pub fn sys_mprotect(start: usize, len: usize, prot: usize) -> isize {
+    syscall(SYSCALL_MPROTECT, [start, len, prot])
+}
+
 pub fn sys_munmap(start: usize, len: usize) -> isize {
     syscall(SYSCALL_MUNMAP, [start, len, 0])
 }
 
+pub fn sys_shmget(key: usize, size: usize, flags: usize) -> isize {
+    syscall(SYSCALL_SHMGET, [key, size, flags])
+}
+
+pub fn sys_shmat(shm_id: usize, addr: usize, flags: usize) -> isize {
+    syscall(SYSCALL_SHMAT, [shm_id, addr, flags])
+}
+
+pub fn sys_shmdt(addr: usize) -> isize {
+    syscall(SYSCALL_SHMDT, [addr, 0, 0])
+}
+
+pub fn sys_brk(addr: usize) -> isize {
+    syscall(SYSCALL_BRK, [addr, 0, 0])
+}
+
+pub fn sys_sbrk(increment: isize) -> isize {
+    syscall(SYSCALL_SBRK, [increment as usize, 0, 0])
+}
+
 pub fn sys_spawn(path: &str) -> isize {
     syscall(SYSCALL_SPAWN, [path.as_ptr() as usize, 0, 0])
 }
@@ -205,6 +278,39 @@ pub fn sys_task_info(info: &TaskInfo) -> isize {
     syscall(SYSCALL_TASK_INFO, [info as *const _ as usize, 0, 0])
 }
 
+pub fn sys_swap_stat(stat: &mut SwapStat) -> isize {
+    syscall(SYSCALL_SWAP_STAT, [stat as *mut _ as usize, 0, 0])
+}
+
+pub fn sys_trace(pid: usize, mask: usize) -> isize {
+    syscall(SYSCALL_TRACE, [pid, mask, 0])
+}
+
+pub fn sys_trace_read(pid: usize, cursor: usize, records: &mut [TraceRecord]) -> isize {
+    syscall6(
+        SYSCALL_TRACE_READ,
+        [
+            pid,
+            cursor,
+            records.as_mut_ptr() as usize,
+            records.len(),
+            0,
+            0,
+        ],
+    )
+}
+
+pub fn sys_list_tasks(pids: &mut [usize]) -> isize {
+    syscall(
+        SYSCALL_LIST_TASKS,
+        [pids.as_mut_ptr() as usize, pids.len(), 0],
+    )
+}
+
+pub fn sys_proc_info(pid: usize, info: &mut ProcInfo) -> isize {
+    syscall(SYSCALL_PROC_INFO, [pid, info as *mut _ as usize, 0])
+}
+
 pub fn sys_thread_create(entry: usize, arg: usize) -> isize {
     syscall(SYSCALL_THREAD_CREATE, [entry, arg, 0])
 }